@@ -0,0 +1,435 @@
+use crate::core::model::{Checksum, HashAlgo, PieceHashes};
+use anyhow::Context;
+use sha2::Digest;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// eD2k 分块大小（9500 KiB）
+const ED2K_CHUNK: u64 = 9_728_000;
+
+/// 下载内容与期望摘要不一致
+#[derive(thiserror::Error, Debug)]
+#[error("checksum mismatch ({algo}): expected {expected}, got {actual}")]
+pub struct ChecksumMismatch {
+    pub algo: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// 分片（或其中某个 piece）的哈希与期望不符，该分片需要重新下载
+#[derive(thiserror::Error, Debug)]
+#[error("fragment hash mismatch at offset {offset} ({algo}): expected {expected}, got {actual}")]
+pub struct FragmentHashMismatch {
+    pub offset: u64,
+    pub algo: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl HashAlgo {
+    /// 接受 aria2 / Metalink 风格的名字：md5, sha-1, sha-256, sha-512, blake3, ed2k
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().replace(['-', '_'], "").as_str() {
+            "md5" => Some(Self::Md5),
+            "sha1" => Some(Self::Sha1),
+            "sha256" => Some(Self::Sha256),
+            "sha512" => Some(Self::Sha512),
+            "blake3" => Some(Self::Blake3),
+            "ed2k" => Some(Self::Ed2k),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Md5 => "md5",
+            Self::Sha1 => "sha-1",
+            Self::Sha256 => "sha-256",
+            Self::Sha512 => "sha-512",
+            Self::Blake3 => "blake3",
+            Self::Ed2k => "ed2k",
+        }
+    }
+
+    fn hex_len(self) -> usize {
+        match self {
+            Self::Md5 | Self::Ed2k => 32,
+            Self::Sha1 => 40,
+            Self::Sha256 | Self::Blake3 => 64,
+            Self::Sha512 => 128,
+        }
+    }
+}
+
+impl Checksum {
+    pub fn new(algo: HashAlgo, hex: &str) -> anyhow::Result<Self> {
+        let hex = hex.trim().to_ascii_lowercase();
+        if hex.len() != algo.hex_len() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            anyhow::bail!("invalid {} digest: {}", algo.name(), hex);
+        }
+        Ok(Self { algo, hex })
+    }
+
+    /// 解析 `algo=hex`，例如 `sha-256=9f86d08...`
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let (algo, hex) = spec
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("invalid checksum format (expected algo=hex): {}", spec))?;
+        let algo = HashAlgo::parse(algo.trim())
+            .ok_or_else(|| anyhow::anyhow!("unsupported checksum algorithm: {}", algo))?;
+        Self::new(algo, hex)
+    }
+}
+
+/// 解析逗号分隔的多个 `algo=hex`（LinkInput.options["checksum"] 的格式）
+pub fn parse_checksum_list(spec: &str) -> anyhow::Result<Vec<Checksum>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(Checksum::parse)
+        .collect()
+}
+
+/// 解析 `.sha256` 之类的旁路校验文件：每行 `<hex>  [*]<filename>`，或整个文件只有一个 hex
+pub fn parse_sidecar(text: &str, file_name: &str) -> Option<String> {
+    let entries: Vec<(&str, Option<&str>)> = text
+        .lines()
+        .filter_map(|line| {
+            let mut it = line.split_whitespace();
+            let hex = it.next()?;
+            Some((hex, it.next().map(|n| n.trim_start_matches('*'))))
+        })
+        .collect();
+
+    entries
+        .iter()
+        .find(|(_, name)| name.map(|n| n.rsplit('/').next() == Some(file_name)).unwrap_or(false))
+        .or_else(|| if entries.len() == 1 { entries.first() } else { None })
+        .map(|(hex, _)| hex.to_ascii_lowercase())
+}
+
+pub enum Hasher {
+    Md5(md5::Md5),
+    Sha1(sha1::Sha1),
+    Sha256(sha2::Sha256),
+    Sha512(sha2::Sha512),
+    Blake3(Box<blake3::Hasher>),
+    Ed2k(Ed2kHasher),
+}
+
+impl Hasher {
+    pub fn new(algo: HashAlgo) -> Self {
+        match algo {
+            HashAlgo::Md5 => Self::Md5(md5::Md5::new()),
+            HashAlgo::Sha1 => Self::Sha1(sha1::Sha1::new()),
+            HashAlgo::Sha256 => Self::Sha256(sha2::Sha256::new()),
+            HashAlgo::Sha512 => Self::Sha512(sha2::Sha512::new()),
+            HashAlgo::Blake3 => Self::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgo::Ed2k => Self::Ed2k(Ed2kHasher::default()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Md5(h) => h.update(data),
+            Self::Sha1(h) => h.update(data),
+            Self::Sha256(h) => h.update(data),
+            Self::Sha512(h) => h.update(data),
+            Self::Blake3(h) => {
+                h.update(data);
+            }
+            Self::Ed2k(h) => h.update(data),
+        }
+    }
+
+    pub fn finalize_hex(self) -> String {
+        match self {
+            Self::Md5(h) => to_hex(&h.finalize()),
+            Self::Sha1(h) => to_hex(&h.finalize()),
+            Self::Sha256(h) => to_hex(&h.finalize()),
+            Self::Sha512(h) => to_hex(&h.finalize()),
+            Self::Blake3(h) => to_hex(h.finalize().as_bytes()),
+            Self::Ed2k(h) => h.finalize_hex(),
+        }
+    }
+}
+
+/// eD2k：每 9500 KiB 一块做 MD4；只有一块时即为该块 MD4，否则对各块 MD4 拼接后再做 MD4。
+/// 文件大小恰为块大小整数倍时按 eMule 的做法追加一个空块的 MD4。
+#[derive(Default)]
+pub struct Ed2kHasher {
+    chunk: md4::Md4,
+    chunk_len: u64,
+    chunk_hashes: Vec<u8>,
+}
+
+impl Ed2kHasher {
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = ((ED2K_CHUNK - self.chunk_len) as usize).min(data.len());
+            self.chunk.update(&data[..take]);
+            self.chunk_len += take as u64;
+            data = &data[take..];
+
+            if self.chunk_len == ED2K_CHUNK {
+                let full = std::mem::take(&mut self.chunk);
+                self.chunk_hashes.extend_from_slice(&full.finalize());
+                self.chunk_len = 0;
+            }
+        }
+    }
+
+    fn finalize_hex(self) -> String {
+        let last = self.chunk.finalize();
+        if self.chunk_hashes.is_empty() {
+            return to_hex(&last);
+        }
+        let mut all = self.chunk_hashes;
+        all.extend_from_slice(&last);
+        to_hex(&md4::Md4::digest(&all))
+    }
+}
+
+/// 分片下载过程中按 piece 边界增量计算哈希并与 piece 列表比对。
+/// 只校验完整落在本分片内的 piece；分片计划按 piece 长度对齐时即覆盖全部 piece。
+pub struct PieceVerifier<'a> {
+    pieces: &'a PieceHashes,
+    total: u64,
+    pos: u64,
+    current: Option<(usize, Hasher)>,
+}
+
+impl<'a> PieceVerifier<'a> {
+    pub fn new(pieces: &'a PieceHashes, total: u64, offset: u64) -> Self {
+        Self { pieces, total, pos: offset, current: None }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) -> Result<(), FragmentHashMismatch> {
+        let piece_len = self.pieces.piece_len.max(1);
+        while !data.is_empty() {
+            let idx = (self.pos / piece_len) as usize;
+            let piece_start = idx as u64 * piece_len;
+            let piece_end = (piece_start + piece_len).min(self.total);
+
+            if self.current.is_none() && self.pos == piece_start && idx < self.pieces.hashes.len() {
+                self.current = Some((idx, Hasher::new(self.pieces.algo)));
+            }
+
+            let take = ((piece_end.saturating_sub(self.pos)) as usize).min(data.len()).max(1);
+            if let Some((_, h)) = self.current.as_mut() {
+                h.update(&data[..take]);
+            }
+            self.pos += take as u64;
+            data = &data[take..];
+
+            if self.pos >= piece_end {
+                if let Some((i, h)) = self.current.take() {
+                    let actual = h.finalize_hex();
+                    if actual != self.pieces.hashes[i] {
+                        return Err(FragmentHashMismatch {
+                            offset: piece_start,
+                            algo: self.pieces.algo.name(),
+                            expected: self.pieces.hashes[i].clone(),
+                            actual,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn verify_file_blocking(path: &Path, expected: &[Checksum]) -> anyhow::Result<()> {
+    let mut file = std::fs::File::open(path).with_context(|| format!("open {:?} for verification", path))?;
+    let mut hashers: Vec<Hasher> = expected.iter().map(|c| Hasher::new(c.algo)).collect();
+
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = file.read(&mut buf).with_context(|| format!("read {:?}", path))?;
+        if n == 0 {
+            break;
+        }
+        for h in hashers.iter_mut() {
+            h.update(&buf[..n]);
+        }
+    }
+
+    for (c, h) in expected.iter().zip(hashers) {
+        let actual = h.finalize_hex();
+        if actual != c.hex {
+            return Err(ChecksumMismatch { algo: c.algo.name(), expected: c.hex.clone(), actual }.into());
+        }
+    }
+    Ok(())
+}
+
+/// 读一遍文件同时计算所有期望摘要并比对（在阻塞线程池中执行）
+pub async fn verify_file(path: &Path, expected: &[Checksum]) -> anyhow::Result<()> {
+    let path: PathBuf = path.to_path_buf();
+    let expected = expected.to_vec();
+    tokio::task::spawn_blocking(move || verify_file_blocking(&path, &expected))
+        .await
+        .context("checksum task panicked")?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(algo: HashAlgo, data: &[u8]) -> String {
+        let mut h = Hasher::new(algo);
+        h.update(data);
+        h.finalize_hex()
+    }
+
+    #[test]
+    fn known_answers() {
+        let cases = [
+            (HashAlgo::Md5, "900150983cd24fb0d6963f7d28e17f72"),
+            (HashAlgo::Sha1, "a9993e364706816aba3e25717850c26c9cd0d89d"),
+            (HashAlgo::Sha256, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            (
+                HashAlgo::Sha512,
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+            ),
+            (HashAlgo::Blake3, "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"),
+            // 不足一块：即 MD4
+            (HashAlgo::Ed2k, "a448017aaf21d8525fc10ae87aa6729d"),
+        ];
+        for (algo, hex) in cases {
+            assert_eq!(digest(algo, b"abc"), hex, "{}", algo.name());
+            // 分多次 update 结果相同
+            let mut h = Hasher::new(algo);
+            h.update(b"a");
+            h.update(b"bc");
+            assert_eq!(h.finalize_hex(), hex, "{}", algo.name());
+        }
+    }
+
+    #[test]
+    fn ed2k_chunk_boundary() {
+        // 恰好一整块：两块 MD4（第二块为空）再做 MD4
+        let zeros = vec![0u8; ED2K_CHUNK as usize];
+        assert_eq!(digest(HashAlgo::Ed2k, &zeros), "fc21d9af828f92a8df64beac3357425d");
+        // 块内的 MD4 本身
+        assert_eq!(to_hex(&md4::Md4::digest(&zeros)), "d7def262a127cd79096a108e7a9fc138");
+
+        // 跨块的 update 与一次性 update 一致
+        let mut data = zeros;
+        data.push(1);
+        let mut h = Hasher::new(HashAlgo::Ed2k);
+        for part in data.chunks(1_000_003) {
+            h.update(part);
+        }
+        let split = h.finalize_hex();
+        assert_eq!(split, digest(HashAlgo::Ed2k, &data));
+        let mut both = md4::Md4::digest(&data[..ED2K_CHUNK as usize]).to_vec();
+        both.extend_from_slice(&md4::Md4::digest([1u8]));
+        assert_eq!(split, to_hex(&md4::Md4::digest(&both)));
+    }
+
+    #[test]
+    fn checksum_parsing() {
+        let c = Checksum::parse(" SHA-256 = BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD ").unwrap();
+        assert_eq!(c.algo, HashAlgo::Sha256);
+        assert_eq!(c.hex, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(Checksum::parse("sha_1=a9993e364706816aba3e25717850c26c9cd0d89d").unwrap().algo, HashAlgo::Sha1);
+
+        assert!(Checksum::parse("a9993e364706816aba3e25717850c26c9cd0d89d").is_err());
+        assert!(Checksum::parse("crc32=12345678").is_err());
+        // 长度不对或含非十六进制字符
+        assert!(Checksum::parse("md5=900150983cd24fb0d6963f7d28e17f7").is_err());
+        assert!(Checksum::parse("md5=900150983cd24fb0d6963f7d28e17f7g").is_err());
+
+        let list = parse_checksum_list("md5=900150983cd24fb0d6963f7d28e17f72, ,sha-1=a9993e364706816aba3e25717850c26c9cd0d89d,").unwrap();
+        assert_eq!(list.iter().map(|c| c.algo).collect::<Vec<_>>(), [HashAlgo::Md5, HashAlgo::Sha1]);
+        assert!(parse_checksum_list("md5=900150983cd24fb0d6963f7d28e17f72,sha-1=xyz").is_err());
+        assert!(parse_checksum_list("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn sidecar_parsing() {
+        // 只有一个 hex
+        assert_eq!(parse_sidecar("ABCDEF\n", "a.iso").as_deref(), Some("abcdef"));
+        // 多行时按文件名挑选；`*` 为二进制模式前缀，路径只比较最后一段
+        let text = "111  b.iso\n222 *a.iso\n333  dir/c.iso\n";
+        assert_eq!(parse_sidecar(text, "a.iso").as_deref(), Some("222"));
+        assert_eq!(parse_sidecar(text, "c.iso").as_deref(), Some("333"));
+        assert_eq!(parse_sidecar(text, "d.iso"), None);
+        // 唯一的一行即使文件名不同也采用
+        assert_eq!(parse_sidecar("444  other.iso\n", "a.iso").as_deref(), Some("444"));
+        assert_eq!(parse_sidecar("\n\n", "a.iso"), None);
+    }
+
+    /// 10 字节、每 4 字节一个 piece：最后一个 piece 只有 2 字节
+    fn pieces(data: &[u8]) -> PieceHashes {
+        PieceHashes { algo: HashAlgo::Sha1, piece_len: 4, hashes: data.chunks(4).map(|c| digest(HashAlgo::Sha1, c)).collect() }
+    }
+
+    /// 从 offset 开始按 step 字节一次喂给 PieceVerifier
+    fn verify(p: &PieceHashes, total: u64, offset: u64, data: &[u8], step: usize) -> Result<(), FragmentHashMismatch> {
+        let mut v = PieceVerifier::new(p, total, offset);
+        data.chunks(step).try_for_each(|c| v.update(c))
+    }
+
+    #[test]
+    fn piece_verifier_accepts_matching_data() {
+        let data = b"0123456789";
+        let p = pieces(data);
+        assert_eq!(p.hashes.len(), 3);
+        for step in [1, 3, 4, 10] {
+            verify(&p, 10, 0, data, step).unwrap();
+        }
+        // 对齐的分片，以及只含较短的最后一个 piece 的分片
+        verify(&p, 10, 4, &data[4..8], 3).unwrap();
+        verify(&p, 10, 8, &data[8..], 1).unwrap();
+    }
+
+    #[test]
+    fn piece_verifier_detects_corrupted_piece() {
+        let data = b"0123456789";
+        let p = pieces(data);
+        let mut bad = *data;
+        bad[5] = b'x';
+        let e = verify(&p, 10, 0, &bad, 3).unwrap_err();
+        assert_eq!((e.offset, e.expected.as_str()), (4, p.hashes[1].as_str()));
+        assert_eq!(e.actual, digest(HashAlgo::Sha1, b"4x67"));
+
+        // 最后一个（较短的）piece 出错
+        bad = *data;
+        bad[9] = b'x';
+        assert_eq!(verify(&p, 10, 0, &bad, 10).unwrap_err().offset, 8);
+    }
+
+    #[test]
+    fn piece_verifier_skips_partial_leading_piece() {
+        let data = b"0123456789";
+        let p = pieces(data);
+        // 分片从 piece 中间（6）开始：[4, 8) 不完整不校验，[8, 10) 照常校验
+        let mut frag = data[6..].to_vec();
+        frag[0] = b'x';
+        verify(&p, 10, 6, &frag, 1).unwrap();
+        frag[3] = b'x';
+        assert_eq!(verify(&p, 10, 6, &frag, 4).unwrap_err().offset, 8);
+    }
+
+    #[tokio::test]
+    async fn verify_file_reports_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("abc.txt");
+        std::fs::write(&path, b"abc").unwrap();
+
+        let good = parse_checksum_list("md5=900150983cd24fb0d6963f7d28e17f72,sha-1=a9993e364706816aba3e25717850c26c9cd0d89d").unwrap();
+        verify_file(&path, &good).await.unwrap();
+
+        let bad = [good[0].clone(), Checksum::new(HashAlgo::Sha1, &"0".repeat(40)).unwrap()];
+        let e = verify_file(&path, &bad).await.unwrap_err();
+        let m = e.downcast_ref::<ChecksumMismatch>().unwrap();
+        assert_eq!((m.algo, m.actual.as_str()), ("sha-1", "a9993e364706816aba3e25717850c26c9cd0d89d"));
+    }
+}
@@ -1,29 +1,113 @@
 use crate::core::assembler::Assembler;
-use crate::core::events::EngineEvent;
+use crate::core::checksum::{parse_checksum_list, verify_file, ChecksumMismatch, FragmentHashMismatch, Hasher, PieceVerifier};
+use crate::core::events::{EngineEvent, ErrorCategory};
+use crate::core::hosts::{HostSlots, MAX_BUSY_STREAK};
+use crate::core::meter::TransferMeter;
 use crate::core::model::*;
 use crate::core::planner::plan_ranges;
-use crate::core::store::SqliteStore;
-use crate::plugins::registry::{DriverContext, PluginRegistry, ResolveContext};
+use crate::core::ratelimit::{acquire_all, RateLimiter};
+use crate::core::recovery;
+use crate::core::sources::{Source, SourcePool};
+use crate::core::store::{
+    FragmentRecord, HistoryRecord, ItemRecord, JobItemRecord, JobRecord, SqliteStore, DB_FILE_NAME, OWNER_HEARTBEAT_SECS,
+};
+use crate::plugins::http::driver::HttpDriverError;
+use crate::plugins::registry::{
+    DriverContext, ExternalToolFailed, HostBusy, PluginRegistry, ProbeInfo, ResolveContext, TransferDriver,
+};
 use anyhow::Context;
 use futures::stream::{FuturesUnordered, StreamExt};
-use std::sync::Arc;
-use tokio::sync::{broadcast, Mutex, Notify};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::sync::{broadcast, watch, Mutex, Notify, Semaphore};
+use tokio::task::JoinSet;
 use tokio::time::{Duration, Instant};
 use uuid::Uuid;
 
+/// 任务运行控制：由 pause/resume/cancel 写入，run_job / download_item 监听
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunState {
+    Running,
+    Paused,
+    Cancelled,
+}
+
+/// 动态切分后两段都不小于该长度，太小的尾巴不值得再开一个连接
+const MIN_SPLIT: u64 = 512 * 1024;
+
+/// 在途分片的进度：received 为已占位字节，limit 为当前目标长度（被切分时缩短）
+struct InFlight {
+    received: u64,
+    limit: u64,
+}
+
+/// run_job 的输入：新任务从 LinkInput 解析，恢复的任务沿用保存的解析结果
+enum JobPlan {
+    Inputs(Vec<LinkInput>),
+    Restored { items: Vec<JobItemRecord>, resolve_failed: bool },
+}
+
+/// 单个 item 在 job 内的最终结果
+enum ItemOutcome {
+    Done,
+    Failed,
+    Cancelled,
+}
+
+/// download_item 因暂停/取消被中断（不是下载失败）
+#[derive(thiserror::Error, Debug)]
+enum Interrupted {
+    #[error("job paused")]
+    Paused,
+    #[error("job cancelled")]
+    Cancelled,
+}
+
 #[derive(Clone)]
 pub struct Engine {
     registry: Arc<PluginRegistry>,
     out_dir: std::path::PathBuf,
     concurrency: usize,
     chunk_size: u64,
+    fragment_retries: u32,
+    fragment_hash: Option<HashAlgo>,
     driver_ctx: DriverContext,
     event_tx: broadcast::Sender<EngineEvent>,
     jobs: Arc<Mutex<std::collections::HashMap<JobId, JobStatus>>>,
     job_notifies: Arc<Mutex<std::collections::HashMap<JobId, Arc<Notify>>>>,
+    job_controls: Arc<Mutex<std::collections::HashMap<JobId, watch::Sender<RunState>>>>,
+    /// 全局限速，所有驱动共享
+    speed_limit: Arc<RateLimiter>,
+    /// 按主机 / item 的限速器，按需创建，默认速率取自 DriverContext
+    host_limits: Arc<Mutex<HashMap<String, Arc<RateLimiter>>>>,
+    item_limits: Arc<Mutex<HashMap<ItemId, Arc<RateLimiter>>>>,
+    /// 正在下载的 item 本次运行的传输统计，结束时写入下载历史
+    item_meters: Arc<StdMutex<HashMap<ItemId, Arc<TransferMeter>>>>,
+    /// 每主机连接数与 429/503 退避，跨 item / job 共享
+    host_slots: Arc<HostSlots>,
+    /// 每个 job 同时下载的 item 数，运行中可调整
+    max_active_items: Arc<AtomicUsize>,
+    /// 同时运行的 job 数（0 = 不限），运行中可调整；超出的 job 以 Pending 排队
+    max_active_jobs: Arc<AtomicUsize>,
+    /// 已占到运行名额的 job
+    active_jobs: Arc<StdMutex<HashSet<JobId>>>,
+    /// job 结束或上限调整时唤醒排队的 job
+    job_slot_freed: Arc<Notify>,
+    /// 所有 item 合计的连接名额（None = 不限）
+    connections: Option<Arc<Semaphore>>,
     store: SqliteStore,
+    /// 本进程在 jobs.owner 里的标识；别的进程据此判断任务是否有人在跑
+    owner: Arc<str>,
 }
 
+/// 任务正由另一个仍在运行的进程（例如同一 out_dir 上的 daemon）下载
+#[derive(thiserror::Error, Debug)]
+#[error("job {0} is being downloaded by another process")]
+pub struct JobOwnedElsewhere(pub JobId);
+
 impl Engine {
     /// ✅ async ctor：不再 block_on
     pub async fn new(
@@ -31,40 +115,146 @@ impl Engine {
         out_dir: std::path::PathBuf,
         concurrency: usize,
         chunk_size: u64,
+        fragment_retries: u32,
+        fragment_hash: Option<HashAlgo>,
         driver_ctx: DriverContext,
     ) -> anyhow::Result<Self> {
-        let (event_tx, _) = broadcast::channel(256);
+        let (event_tx, _) = broadcast::channel(1024);
 
         tokio::fs::create_dir_all(&out_dir).await
             .with_context(|| format!("create out_dir {}", out_dir.display()))?;
 
-        let db_path = out_dir.join(".downloader.sqlite");
+        let db_path = out_dir.join(DB_FILE_NAME);
         let store = SqliteStore::open(&db_path).await?;
+        let owner: Arc<str> = Uuid::new_v4().to_string().into();
+        // 本进程还没开始下载；没有别的进程在跑任务时，库里残留的 Downloading 分片都是上次进程被杀时留下的。
+        // 有的话只能等恢复 / 下载到具体 item 时再逐个回退
+        if !store.has_live_owner_except(&owner).await? {
+            store.reset_all_downloading_fragments().await?;
+        }
+        {
+            let (store, owner) = (store.clone(), owner.clone());
+            tokio::spawn(async move {
+                let mut tick = tokio::time::interval(Duration::from_secs(OWNER_HEARTBEAT_SECS));
+                loop {
+                    tick.tick().await;
+                    let _ = store.touch_owned_jobs(&owner).await;
+                }
+            });
+        }
+        let host_slots = Arc::new(HostSlots::new(driver_ctx.max_conns_per_host));
+        let connections = (driver_ctx.max_connections > 0).then(|| Arc::new(Semaphore::new(driver_ctx.max_connections)));
 
         Ok(Self {
             registry: Arc::new(registry),
             out_dir,
             concurrency: concurrency.max(1),
             chunk_size: chunk_size.max(1024 * 1024),
+            fragment_retries,
+            fragment_hash,
             driver_ctx,
             event_tx,
             jobs: Arc::new(Mutex::new(std::collections::HashMap::new())),
             job_notifies: Arc::new(Mutex::new(std::collections::HashMap::new())),
+            job_controls: Arc::new(Mutex::new(std::collections::HashMap::new())),
+            speed_limit: Arc::new(RateLimiter::new(0)),
+            host_limits: Arc::new(Mutex::new(HashMap::new())),
+            item_limits: Arc::new(Mutex::new(HashMap::new())),
+            item_meters: Arc::new(StdMutex::new(HashMap::new())),
+            host_slots,
+            max_active_items: Arc::new(AtomicUsize::new(1)),
+            max_active_jobs: Arc::new(AtomicUsize::new(0)),
+            active_jobs: Arc::new(StdMutex::new(HashSet::new())),
+            job_slot_freed: Arc::new(Notify::new()),
+            connections,
             store,
+            owner,
         })
     }
 
+    pub fn out_dir(&self) -> &Path {
+        &self.out_dir
+    }
+
     pub fn subscribe(&self) -> broadcast::Receiver<EngineEvent> {
         self.event_tx.subscribe()
     }
 
     pub async fn add_and_start(&self, inputs: Vec<LinkInput>) -> anyhow::Result<JobId> {
         let job_id = Uuid::new_v4();
+        // 先落库再开始：进程中途退出后 restore() 能找回
+        self.store.insert_job(job_id, &inputs, &self.owner).await?;
+        self.start_job(job_id, JobPlan::Inputs(inputs), JobStatus::Pending).await;
+        Ok(job_id)
+    }
+
+    /// 载入上次进程退出时未结束的任务并继续（daemon 启动时调用）；暂停中的任务恢复后仍为暂停。
+    /// 本进程已在运行的、以及另一个仍在运行的进程名下的任务跳过。
+    /// 已解析的任务直接沿用保存的 item，已完成的 item 不再下载。
+    pub async fn restore(&self) -> anyhow::Result<Vec<JobRecord>> {
+        let mut restored = vec![];
+        for job in self.store.load_unfinished_jobs().await? {
+            if self.jobs.lock().await.contains_key(&job.job_id) || !self.store.claim_job(job.job_id, &self.owner).await? {
+                continue;
+            }
+            self.restore_job(&job).await?;
+            restored.push(job);
+        }
+        Ok(restored)
+    }
+
+    /// 重新运行库中的一个任务（失败 / 取消的也可以）：失败的 item 重新下载，已完成的 item 跳过。
+    /// 任务正由别的进程运行时返回 JobOwnedElsewhere
+    pub async fn resume_stored_job(&self, job_id: JobId) -> anyhow::Result<JobRecord> {
+        if self.jobs.lock().await.contains_key(&job_id) {
+            anyhow::bail!("job {} is already running", job_id);
+        }
+        let find = || async {
+            self.store
+                .load_jobs()
+                .await?
+                .into_iter()
+                .find(|j| j.job_id == job_id)
+                .with_context(|| format!("no such job: {}", job_id))
+        };
+        if find().await?.status == JobStatus::Completed {
+            anyhow::bail!("job {} already completed", job_id);
+        }
+        if !self.store.claim_job(job_id, &self.owner).await? {
+            return Err(JobOwnedElsewhere(job_id).into());
+        }
+        self.store.reopen_job(job_id).await?;
+        let job = find().await?;
+        self.restore_job(&job).await?;
+        Ok(job)
+    }
+
+    async fn restore_job(&self, job: &JobRecord) -> anyhow::Result<()> {
+        let plan = if job.resolved {
+            let items = self.store.load_job_items(job.job_id).await?;
+            // 进程退出时正在下载的分片停在 Downloading，回退为 Missing 才会重新下载
+            for r in items.iter().filter(|r| !matches!(r.status, ItemStatus::Done | ItemStatus::Failed)) {
+                let Some(res0) = r.item.resources.first() else { continue };
+                if let Some(rec) = self.store.find_item(&res0.uri, &r.item.target_path).await? {
+                    self.store.reset_downloading_fragments(rec.item_db_id).await?;
+                }
+            }
+            JobPlan::Restored { items, resolve_failed: job.resolve_failed }
+        } else {
+            JobPlan::Inputs(job.inputs.clone())
+        };
+        let status = if job.status == JobStatus::Paused { JobStatus::Paused } else { JobStatus::Pending };
+        self.start_job(job.job_id, plan, status).await;
+        Ok(())
+    }
+
+    /// 注册任务的状态 / 通知 / 控制通道并在后台运行；status 为 Paused 时任务以暂停状态开始
+    async fn start_job(&self, job_id: JobId, plan: JobPlan, status: JobStatus) {
         {
             let mut jobs = self.jobs.lock().await;
-            jobs.insert(job_id, JobStatus::Pending);
+            jobs.insert(job_id, status);
         }
-        let _ = self.event_tx.send(EngineEvent::JobStatusChanged { job_id, status: JobStatus::Pending });
+        let _ = self.event_tx.send(EngineEvent::JobStatusChanged { job_id, status });
 
         let notify = Arc::new(Notify::new());
         {
@@ -72,12 +262,259 @@ impl Engine {
             m.insert(job_id, notify.clone());
         }
 
+        let initial = if status == JobStatus::Paused { RunState::Paused } else { RunState::Running };
+        let (ctl_tx, ctl_rx) = watch::channel(initial);
+        {
+            let mut m = self.job_controls.lock().await;
+            m.insert(job_id, ctl_tx);
+        }
+
         let engine = self.clone();
         tokio::spawn(async move {
-            engine.run_job(job_id, inputs, notify).await;
+            engine.run_job(job_id, plan, notify, ctl_rx).await;
         });
+    }
 
-        Ok(job_id)
+    /// 暂停任务：中断正在下载的分片（store 中回退为 Missing），resume 后按分片表继续
+    pub async fn pause_job(&self, job_id: JobId) -> anyhow::Result<()> {
+        if self.control_job(job_id, RunState::Paused).await? {
+            self.set_job_status(job_id, JobStatus::Paused).await;
+        }
+        Ok(())
+    }
+
+    /// 恢复任务；还在排队等运行名额的任务回到 Pending，拿到名额后由 execute_job 切到 Running
+    pub async fn resume_job(&self, job_id: JobId) -> anyhow::Result<()> {
+        if !self.control_job(job_id, RunState::Running).await? {
+            return Ok(());
+        }
+        if self.holds_job_slot(job_id) {
+            self.set_job_status(job_id, JobStatus::Running).await;
+        } else {
+            self.set_job_status(job_id, JobStatus::Pending).await;
+            // 改状态期间刚拿到名额的任务，execute_job 可能已经检查过状态（当时还是 Paused）
+            if self.holds_job_slot(job_id) {
+                self.set_job_status_if(job_id, JobStatus::Pending, JobStatus::Running).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// 取消任务：停止下载但保留 .partial 与分片记录，最终状态为 Cancelled
+    pub async fn cancel_job(&self, job_id: JobId) -> anyhow::Result<()> {
+        self.control_job(job_id, RunState::Cancelled).await?;
+        Ok(())
+    }
+
+    /// 内存中的任务状态；本进程没有运行过的任务返回 None
+    pub async fn job_status(&self, job_id: JobId) -> Option<JobStatus> {
+        self.jobs.lock().await.get(&job_id).copied()
+    }
+
+    /// 所有任务（含历史任务）；本进程中运行的任务以内存中的状态为准
+    pub async fn list_jobs(&self) -> anyhow::Result<Vec<JobSnapshot>> {
+        let jobs = self.jobs.lock().await.clone();
+        Ok(self
+            .store
+            .load_jobs()
+            .await?
+            .into_iter()
+            .map(|j| JobSnapshot {
+                job_id: j.job_id,
+                status: jobs.get(&j.job_id).copied().unwrap_or(j.status),
+                links: j.inputs.into_iter().map(|i| i.raw).collect(),
+                resolve_failed: j.resolve_failed,
+                created_at: j.created_at,
+            })
+            .collect())
+    }
+
+    /// 某个任务（None = 全部任务）已解析出的 item
+    pub async fn list_items(&self, job_id: Option<JobId>) -> anyhow::Result<Vec<ItemSnapshot>> {
+        let job_ids = match job_id {
+            Some(id) => vec![id],
+            None => self.store.load_jobs().await?.into_iter().map(|j| j.job_id).collect(),
+        };
+
+        let mut out = vec![];
+        for id in job_ids {
+            for r in self.store.load_job_items(id).await? {
+                let uri = r.item.resources.first().map(|res| res.uri.clone()).unwrap_or_default();
+                let rec = self.store.find_item(&uri, &r.item.target_path).await?;
+                out.push(ItemSnapshot {
+                    item_id: r.item.id,
+                    job_id: id,
+                    display_name: r.item.display_name,
+                    target_path: r.item.target_path,
+                    uri,
+                    status: r.status,
+                    downloaded: rec.as_ref().map(|x| x.downloaded_bytes.max(0) as u64).unwrap_or(0),
+                    total: rec.and_then(|x| x.total_size).map(|t| t as u64).or(r.item.total_size),
+                });
+            }
+        }
+        Ok(out)
+    }
+
+    /// 单个 item；先查出所属任务，只读该任务的 item
+    pub async fn get_item(&self, item_id: ItemId) -> anyhow::Result<Option<ItemSnapshot>> {
+        let Some(job_id) = self.store.find_item_job(item_id).await? else {
+            return Ok(None);
+        };
+        Ok(self.list_items(Some(job_id)).await?.into_iter().find(|i| i.item_id == item_id))
+    }
+
+    /// 全局限速（bytes/s，0 = 不限），对正在进行的下载立即生效
+    pub fn set_speed_limit(&self, bytes_per_sec: u64) {
+        self.speed_limit.set_rate(bytes_per_sec);
+    }
+
+    #[cfg(test)]
+    pub fn speed_limit(&self) -> u64 {
+        self.speed_limit.rate()
+    }
+
+    #[cfg(test)]
+    pub fn max_active_items(&self) -> usize {
+        self.max_active_items.load(Ordering::Relaxed)
+    }
+
+    /// 每个 job 同时下载的 item 数（至少 1），新值在下一个 item 开始时生效
+    pub fn set_max_active_items(&self, n: usize) {
+        self.max_active_items.store(n.max(1), Ordering::Relaxed);
+    }
+
+    /// 同时运行的 job 数（0 = 不限）；调大时排队的 job 立即开始，调小时已在运行的 job 不受影响
+    pub fn set_max_active_jobs(&self, n: usize) {
+        self.max_active_jobs.store(n, Ordering::Relaxed);
+        self.job_slot_freed.notify_waiters();
+    }
+
+    /// 单个主机的限速；该主机尚无限速器时创建
+    pub async fn set_host_speed_limit(&self, host: &str, bytes_per_sec: u64) {
+        self.host_limiter(host).await.set_rate(bytes_per_sec);
+    }
+
+    /// 单个 item 的限速；item 还没开始下载时先记下，开始时沿用
+    pub async fn set_item_speed_limit(&self, item_id: ItemId, bytes_per_sec: u64) {
+        self.item_limiter(item_id).await.set_rate(bytes_per_sec);
+    }
+
+    async fn host_limiter(&self, host: &str) -> Arc<RateLimiter> {
+        let mut m = self.host_limits.lock().await;
+        m.entry(host.to_ascii_lowercase())
+            .or_insert_with(|| Arc::new(RateLimiter::new(self.driver_ctx.host_speed_limit)))
+            .clone()
+    }
+
+    async fn item_limiter(&self, item_id: ItemId) -> Arc<RateLimiter> {
+        let mut m = self.item_limits.lock().await;
+        m.entry(item_id)
+            .or_insert_with(|| Arc::new(RateLimiter::new(self.driver_ctx.item_speed_limit)))
+            .clone()
+    }
+
+    /// 某个来源的分片需要经过的限速器：全局 -> 主机 -> item
+    async fn limiters_for(&self, item_id: ItemId, res: &ResourceDescriptor) -> Vec<Arc<RateLimiter>> {
+        let mut v = vec![self.speed_limit.clone()];
+        if let Some(host) = url::Url::parse(&res.uri).ok().and_then(|u| u.host_str().map(str::to_string)) {
+            v.push(self.host_limiter(&host).await);
+        }
+        v.push(self.item_limiter(item_id).await);
+        v
+    }
+
+    /// 切换运行状态；返回 false 表示当前状态下该操作无效果（如重复暂停）
+    async fn control_job(&self, job_id: JobId, to: RunState) -> anyhow::Result<bool> {
+        let m = self.job_controls.lock().await;
+        let ctl = m
+            .get(&job_id)
+            .with_context(|| format!("job not running: {}", job_id))?;
+        Ok(ctl.send_if_modified(|cur| {
+            let allowed = match to {
+                RunState::Paused => *cur == RunState::Running,
+                RunState::Running => *cur == RunState::Paused,
+                RunState::Cancelled => *cur != RunState::Cancelled,
+            };
+            if allowed {
+                *cur = to;
+            }
+            allowed
+        }))
+    }
+
+    /// 仅当当前状态为 from 时改为 to（与并发的 pause/resume 不互相覆盖）
+    async fn set_job_status_if(&self, job_id: JobId, from: JobStatus, to: JobStatus) {
+        {
+            let mut jobs = self.jobs.lock().await;
+            if jobs.get(&job_id) != Some(&from) {
+                return;
+            }
+            jobs.insert(job_id, to);
+        }
+        self.persist_job_status(job_id, to).await;
+    }
+
+    async fn set_job_status(&self, job_id: JobId, status: JobStatus) {
+        {
+            let mut jobs = self.jobs.lock().await;
+            jobs.insert(job_id, status);
+        }
+        self.persist_job_status(job_id, status).await;
+    }
+
+    /// 写入 store 并广播状态变化
+    async fn persist_job_status(&self, job_id: JobId, status: JobStatus) {
+        if let Err(e) = self.store.set_job_status(job_id, status).await {
+            let (category, retryable) = Self::classify_error(&e);
+            let _ = self.event_tx.send(EngineEvent::Error {
+                job_id: Some(job_id),
+                item_id: None,
+                scope: format!("store job={}", job_id),
+                message: format!("{:#}", e),
+                category,
+                retryable,
+            });
+        }
+        let _ = self.event_tx.send(EngineEvent::JobStatusChanged { job_id, status });
+    }
+
+    /// 等待暂停/取消信号（Running 时一直挂起）
+    async fn stop_requested(ctl: &mut watch::Receiver<RunState>) -> Interrupted {
+        loop {
+            match *ctl.borrow_and_update() {
+                RunState::Paused => return Interrupted::Paused,
+                RunState::Cancelled => return Interrupted::Cancelled,
+                RunState::Running => {}
+            }
+            if ctl.changed().await.is_err() {
+                futures::future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// 暂停期间阻塞，直到恢复（Running）或取消（Cancelled）
+    async fn wait_runnable(ctl: &mut watch::Receiver<RunState>) -> RunState {
+        loop {
+            let s = *ctl.borrow_and_update();
+            if s != RunState::Paused {
+                return s;
+            }
+            if ctl.changed().await.is_err() {
+                return RunState::Cancelled;
+            }
+        }
+    }
+
+    /// 外部工具类下载（bt/adb/ed2k/sftp）没有分片，暂停/取消时直接丢弃 future
+    async fn interruptible<T>(
+        ctl: &mut watch::Receiver<RunState>,
+        fut: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        tokio::select! {
+            r = fut => r,
+            stop = Self::stop_requested(ctl) => Err(stop.into()),
+        }
     }
 
     pub async fn wait_job(&self, job_id: JobId) {
@@ -87,36 +524,247 @@ impl Engine {
         };
 
         if let Some(n) = notify {
-            n.notified().await;
+            // 先登记再检查状态：任务可能在取到 notify 之后、开始等待之前结束
+            let notified = n.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.is_job_finished(job_id).await {
+                return;
+            }
+            notified.await;
         }
     }
 
-    async fn run_job(&self, job_id: JobId, inputs: Vec<LinkInput>, notify: Arc<Notify>) {
+    async fn run_job(&self, job_id: JobId, plan: JobPlan, notify: Arc<Notify>, mut ctl: watch::Receiver<RunState>) {
+        // 排队等运行名额；排队中被取消的任务不再下载
+        let final_status = if self.acquire_job_slot(job_id, &mut ctl).await {
+            let status = self.execute_job(job_id, plan, ctl).await;
+            self.release_job_slot(job_id);
+            status
+        } else {
+            JobStatus::Cancelled
+        };
+        self.set_job_status(job_id, final_status).await;
+
         {
-            let mut jobs = self.jobs.lock().await;
-            jobs.insert(job_id, JobStatus::Running);
+            let mut m = self.job_controls.lock().await;
+            m.remove(&job_id);
+        }
+        {
+            let mut m = self.job_notifies.lock().await;
+            m.remove(&job_id);
+        }
+        notify.notify_waiters();
+    }
+
+    /// 等到同时运行的 job 数低于 max_active_jobs（0 = 不限）再占一个名额；等待期间任务保持 Pending。
+    /// 被取消时返回 false
+    async fn acquire_job_slot(&self, job_id: JobId, ctl: &mut watch::Receiver<RunState>) -> bool {
+        loop {
+            // 先登记再检查，避免错过检查之后、等待之前的释放
+            let freed = self.job_slot_freed.notified();
+            tokio::pin!(freed);
+            freed.as_mut().enable();
+            {
+                let mut active = self.active_jobs.lock().unwrap();
+                let max = self.max_active_jobs.load(Ordering::Relaxed);
+                if max == 0 || active.len() < max {
+                    active.insert(job_id);
+                    return true;
+                }
+            }
+            tokio::select! {
+                _ = &mut freed => {}
+                r = ctl.changed() => {
+                    if r.is_err() || *ctl.borrow() == RunState::Cancelled {
+                        return false;
+                    }
+                }
+            }
+        }
+    }
+
+    fn release_job_slot(&self, job_id: JobId) {
+        self.active_jobs.lock().unwrap().remove(&job_id);
+        self.job_slot_freed.notify_waiters();
+    }
+
+    fn holds_job_slot(&self, job_id: JobId) -> bool {
+        self.active_jobs.lock().unwrap().contains(&job_id)
+    }
+
+    /// 解析（或沿用保存的 item）并下载，返回任务的最终状态
+    async fn execute_job(&self, job_id: JobId, plan: JobPlan, ctl: watch::Receiver<RunState>) -> JobStatus {
+        // 只有仍是 Pending 时才切到 Running：开始前已被暂停（或以暂停状态恢复）的任务保持 Paused
+        let pending = self.jobs.lock().await.get(&job_id) == Some(&JobStatus::Pending);
+        if pending {
+            self.set_job_status_if(job_id, JobStatus::Pending, JobStatus::Running).await;
+        }
+
+        let (items, mut any_failed) = match plan {
+            JobPlan::Inputs(inputs) => {
+                let (items, failed) = self.resolve_inputs(job_id, inputs).await;
+                if let Err(e) = self.store.save_job_items(job_id, &items, failed).await {
+                    let (category, retryable) = Self::classify_error(&e);
+                    let _ = self.event_tx.send(EngineEvent::Error {
+                        job_id: Some(job_id),
+                        item_id: None,
+                        scope: format!("store job={}", job_id),
+                        message: format!("{:#}", e),
+                        category,
+                        retryable,
+                    });
+                }
+                (items, failed)
+            }
+            JobPlan::Restored { items, resolve_failed } => {
+                let mut failed = resolve_failed;
+                let mut pending = vec![];
+                for r in items {
+                    match r.status {
+                        ItemStatus::Done => {}
+                        ItemStatus::Failed => failed = true,
+                        _ => {
+                            self.emit_item_added(&r.item);
+                            pending.push(r.item);
+                        }
+                    }
+                }
+                (pending, failed)
+            }
+        };
+
+        // 同时下载至多 max_active_items 个 item；item 内部仍按 concurrency 开分片，总连接数由 connections 限制
+        let mut queue: VecDeque<DownloadItem> = items.into();
+        let mut active: JoinSet<ItemOutcome> = JoinSet::new();
+        let mut active_paths: HashMap<tokio::task::Id, PathBuf> = HashMap::new();
+        let mut cancelled = false;
+        loop {
+            while !cancelled && active.len() < self.max_active_items.load(Ordering::Relaxed) {
+                // 目标路径相同的 item 不能同时写，等前一个结束再开始
+                let Some(pos) = queue.iter().position(|i| !active_paths.values().any(|p| *p == i.target_path)) else {
+                    break;
+                };
+                let item = queue.remove(pos).expect("position in queue");
+                let path = item.target_path.clone();
+                let engine = self.clone();
+                let item_ctl = ctl.clone();
+                let handle = active.spawn(async move { engine.run_item(item, item_ctl).await });
+                active_paths.insert(handle.id(), path);
+            }
+
+            let Some(joined) = active.join_next_with_id().await else { break };
+            match joined {
+                Ok((id, outcome)) => {
+                    active_paths.remove(&id);
+                    match outcome {
+                        ItemOutcome::Done => {}
+                        ItemOutcome::Failed => any_failed = true,
+                        ItemOutcome::Cancelled => cancelled = true,
+                    }
+                }
+                Err(e) => {
+                    active_paths.remove(&e.id());
+                    any_failed = true;
+                    let _ = self.event_tx.send(EngineEvent::Error {
+                        job_id: Some(job_id),
+                        item_id: None,
+                        scope: format!("job({})", job_id),
+                        message: format!("item task failed: {}", e),
+                        category: ErrorCategory::Internal,
+                        retryable: false,
+                    });
+                }
+            }
+            if *ctl.borrow() == RunState::Cancelled {
+                cancelled = true;
+            }
         }
-        let _ = self.event_tx.send(EngineEvent::JobStatusChanged { job_id, status: JobStatus::Running });
 
-        let ctx = ResolveContext { out_dir: self.out_dir.clone(), user_agent: self.driver_ctx.user_agent.clone() };
+        if cancelled {
+            JobStatus::Cancelled
+        } else if any_failed {
+            JobStatus::Failed
+        } else {
+            JobStatus::Completed
+        }
+    }
 
+    /// 逐个解析输入；返回解析出的 item 以及是否有输入解析失败
+    async fn resolve_inputs(&self, job_id: JobId, inputs: Vec<LinkInput>) -> (Vec<DownloadItem>, bool) {
         let mut items: Vec<DownloadItem> = vec![];
         let mut any_failed = false;
         for input in inputs {
             let input_options = input.options.clone();
+            let input_checksums = match input_options.get("checksum").map(|v| parse_checksum_list(v)).transpose() {
+                Ok(c) => c.unwrap_or_default(),
+                Err(e) => {
+                    any_failed = true;
+                    let _ = self.event_tx.send(EngineEvent::Error {
+                        job_id: Some(job_id),
+                        item_id: None,
+                        scope: "resolve".to_string(),
+                        message: format!("invalid checksum for input {}: {:#}", input.raw, e),
+                        category: ErrorCategory::Resolve,
+                        retryable: false,
+                    });
+                    continue;
+                }
+            };
+            // options["out"]：目标文件名（相对 dir）；options["dir"]：目标目录（相对 out_dir）
+            let out_name = match input_options.get("out").map(|v| Self::relative_out_path(v)).transpose() {
+                Ok(p) => p,
+                Err(e) => {
+                    any_failed = true;
+                    let _ = self.event_tx.send(EngineEvent::Error {
+                        job_id: Some(job_id),
+                        item_id: None,
+                        scope: "resolve".to_string(),
+                        message: format!("invalid out for input {}: {:#}", input.raw, e),
+                        category: ErrorCategory::Resolve,
+                        retryable: false,
+                    });
+                    continue;
+                }
+            };
+            let out_dir = match input_options.get("dir").map(|d| self.target_dir(d)).transpose() {
+                Ok(d) => d.unwrap_or_else(|| self.out_dir.clone()),
+                Err(e) => {
+                    any_failed = true;
+                    let _ = self.event_tx.send(EngineEvent::Error {
+                        job_id: Some(job_id),
+                        item_id: None,
+                        scope: "resolve".to_string(),
+                        message: format!("invalid dir for input {}: {:#}", input.raw, e),
+                        category: ErrorCategory::Resolve,
+                        retryable: false,
+                    });
+                    continue;
+                }
+            };
+            let ctx = ResolveContext {
+                out_dir,
+                user_agent: self.driver_ctx.user_agent.clone(),
+            };
             let resolver = match self.registry.best_resolver(&input) {
                 Some(r) => r,
                 None => {
                     any_failed = true;
                     let _ = self.event_tx.send(EngineEvent::Error {
+                        job_id: Some(job_id),
+                        item_id: None,
                         scope: "resolve".to_string(),
                         message: format!("no resolver for input: {}", input.raw),
+                        category: ErrorCategory::Resolve,
+                        retryable: false,
                     });
                     continue;
                 }
             };
 
             let _ = self.event_tx.send(EngineEvent::Info {
+                job_id: Some(job_id),
+                item_id: None,
                 scope: "resolve".to_string(),
                 message: format!("input={} resolver={}", input.raw, resolver.name()),
             });
@@ -126,12 +774,57 @@ impl Engine {
                 Ok(resolved) => {
                     for w in &resolved.warnings {
                         let _ = self.event_tx.send(EngineEvent::Info {
+                            job_id: Some(job_id),
+                            item_id: None,
                             scope: "resolve-warning".to_string(),
                             message: w.clone(),
                         });
                     }
-                    for d in resolved.drafts {
+                    let mut drafts = resolved.drafts;
+                    if let Some(out) = &out_name {
+                        if let [d] = drafts.as_mut_slice() {
+                            d.suggested_path = ctx.out_dir.join(out);
+                            d.display_name = out.file_name().unwrap_or_default().to_string_lossy().to_string();
+                        } else {
+                            let _ = self.event_tx.send(EngineEvent::Info {
+                                job_id: Some(job_id),
+                                item_id: None,
+                                scope: "resolve-warning".to_string(),
+                                message: format!("out= ignored: {} resolved to {} items", input.raw, drafts.len()),
+                            });
+                        }
+                    }
+                    // options["mirrors"]：同一文件的其它地址（空白分隔），作为镜像加在 item 上
+                    if let Some(mirrors) = input_options.get("mirrors") {
+                        if let [d] = drafts.as_mut_slice() {
+                            for uri in mirrors.split_whitespace() {
+                                match Self::mirror_resource(uri, &input) {
+                                    Some(r) if !d.resources.iter().any(|x| x.uri == r.uri) => d.resources.push(r),
+                                    Some(_) => {}
+                                    None => {
+                                        let _ = self.event_tx.send(EngineEvent::Info {
+                                            job_id: Some(job_id),
+                                            item_id: None,
+                                            scope: "resolve-warning".to_string(),
+                                            message: format!("mirror ignored (unsupported URI): {}", uri),
+                                        });
+                                    }
+                                }
+                            }
+                        } else {
+                            let _ = self.event_tx.send(EngineEvent::Info {
+                                job_id: Some(job_id),
+                                item_id: None,
+                                scope: "resolve-warning".to_string(),
+                                message: format!("mirrors= ignored: {} resolved to {} items", input.raw, drafts.len()),
+                            });
+                        }
+                    }
+                    for d in drafts {
                         let item_id = Uuid::new_v4();
+                        // 用户显式给出的摘要优先放在前面，resolver 提供的一并校验
+                        let mut checksums = input_checksums.clone();
+                        checksums.extend(d.checksums.into_iter().filter(|c| !input_checksums.contains(c)));
                         let item = DownloadItem {
                             id: item_id,
                             job_id,
@@ -142,174 +835,1142 @@ impl Engine {
                             resources: d.resources,
                             options: input_options.clone(),
                             fragments: vec![],
+                            checksums,
+                            piece_hashes: d.piece_hashes,
+                            resolver: resolver.name().to_string(),
                         };
-                        if let Some(res0) = item.resources.get(0) {
-                            let _ = self.event_tx.send(EngineEvent::ItemAdded {
-                                item_id,
-                                display_name: item.display_name.clone(),
-                                target_path: item.target_path.clone(),
-                                uri: res0.uri.clone(),
-                            });
-                        }
+                        self.emit_item_added(&item);
                         items.push(item);
                     }
                 }
                 Err(e) => {
                     any_failed = true;
                     let _ = self.event_tx.send(EngineEvent::Error {
+                        job_id: Some(job_id),
+                        item_id: None,
                         scope: format!("resolve({})", resolver.name()),
                         message: format!("{:#}", e),
+                        category: ErrorCategory::Resolve,
+                        retryable: Self::classify_error(&e).1,
                     });
                 }
             }
         }
+        (items, any_failed)
+    }
 
-        for mut item in items {
-            let r = self.download_item(&mut item).await;
-            match r {
-                Ok(_) => {
-                    let _ = self.event_tx.send(EngineEvent::ItemStatusChanged { item_id: item.id, status: ItemStatus::Done });
-                }
-                Err(e) => {
-                    any_failed = true;
-                    let _ = self.event_tx.send(EngineEvent::Error {
-                        scope: format!("item({})", item.display_name),
-                        message: format!("{:#}", e),
-                    });
-                    let _ = self.event_tx.send(EngineEvent::ItemStatusChanged { item_id: item.id, status: ItemStatus::Failed });
+    /// 镜像地址只接受能按分片下载的 http(s) / ftp；headers 与 FTP 账号沿用输入的设置
+    fn mirror_resource(uri: &str, input: &LinkInput) -> Option<ResourceDescriptor> {
+        let url = url::Url::parse(uri).ok()?;
+        let mut meta = HashMap::new();
+        let (rtype, headers, caps) = match url.scheme() {
+            "http" | "https" => (
+                ResourceType::Http,
+                input.headers.clone(),
+                Capabilities { supports_ranges: true, max_parallel: 8 },
+            ),
+            "ftp" => {
+                for key in ["ftp_user", "ftp_pass", "ftp_port"] {
+                    if let Some(v) = input.options.get(key) {
+                        meta.insert(key.to_string(), v.clone());
+                    }
                 }
+                (ResourceType::Ftp, HashMap::new(), Capabilities { supports_ranges: true, max_parallel: 1 })
             }
-        }
-
-        {
-            let mut jobs = self.jobs.lock().await;
-            jobs.insert(job_id, if any_failed { JobStatus::Failed } else { JobStatus::Completed });
-        }
-        let _ = self.event_tx.send(EngineEvent::JobStatusChanged {
-            job_id,
-            status: if any_failed { JobStatus::Failed } else { JobStatus::Completed },
-        });
+            _ => return None,
+        };
+        Some(ResourceDescriptor { rtype, uri: uri.to_string(), headers, meta, caps })
+    }
 
-        {
-            let mut m = self.job_notifies.lock().await;
-            m.remove(&job_id);
+    /// out 只能是相对路径，且不能用 `..` 跳出目标目录
+    fn relative_out_path(out: &str) -> anyhow::Result<PathBuf> {
+        let p = PathBuf::from(out);
+        if p.as_os_str().is_empty() || !p.components().all(|c| matches!(c, std::path::Component::Normal(_) | std::path::Component::CurDir)) {
+            anyhow::bail!("must be a relative file path without '..': {}", out);
         }
-        notify.notify_waiters();
+        Ok(p)
     }
 
-    pub async fn is_job_finished(&self, job_id: JobId) -> bool {
-        let jobs = self.jobs.lock().await;
-        matches!(jobs.get(&job_id), Some(JobStatus::Completed | JobStatus::Failed))
+    /// dir 相对 out_dir；绝对路径（aria2 客户端常这样传）必须落在 out_dir 之内。
+    /// 按字面规整 `.` / `..` 后再比较，不跟随符号链接。
+    fn target_dir(&self, dir: &str) -> anyhow::Result<PathBuf> {
+        let p = Path::new(dir);
+        if p.as_os_str().is_empty() {
+            anyhow::bail!("must not be empty");
+        }
+        let base = std::path::absolute(&self.out_dir)?;
+        let mut norm = PathBuf::new();
+        for c in base.join(p).components() {
+            match c {
+                std::path::Component::ParentDir => {
+                    if !norm.pop() {
+                        anyhow::bail!("escapes the root directory: {}", dir);
+                    }
+                }
+                std::path::Component::CurDir => {}
+                c => norm.push(c),
+            }
+        }
+        match norm.strip_prefix(&base) {
+            Ok(rel) => Ok(self.out_dir.join(rel)),
+            Err(_) => anyhow::bail!("must stay inside {}: {}", self.out_dir.display(), dir),
+        }
     }
 
-    async fn download_item(&self, item: &mut DownloadItem) -> anyhow::Result<()> {
-        let _ = self.event_tx.send(EngineEvent::ItemStatusChanged { item_id: item.id, status: ItemStatus::Downloading });
-
-        let res = item.resources.get(0).context("no resource")?.clone();
-        if matches!(res.rtype, ResourceType::BitTorrent) {
-            let info = res.meta.get("infohash").cloned().unwrap_or_default();
-            let _ = self.event_tx.send(EngineEvent::Info {
-                scope: format!("bt item={}", item.display_name),
-                message: format!("starting magnet download. infohash={}", info),
-            });
-
-            crate::plugins::bt::driver::BtDriver::new()
-                .download_magnet_to_dir(&res, &self.driver_ctx, &item.target_path)
-                .await?;
-
-            let _ = self.event_tx.send(EngineEvent::Info {
-                scope: format!("bt item={}", item.display_name),
-                message: "completed".to_string(),
+    fn emit_item_added(&self, item: &DownloadItem) {
+        if let Some(res0) = item.resources.first() {
+            let _ = self.event_tx.send(EngineEvent::ItemAdded {
+                job_id: item.job_id,
+                item_id: item.id,
+                display_name: item.display_name.clone(),
+                target_path: item.target_path.clone(),
+                uri: res0.uri.clone(),
             });
-            return Ok(());
+        }
+    }
+
+    /// 下载单个 item：暂停时原地等待恢复后继续，直到完成、失败或取消
+    async fn run_item(&self, mut item: DownloadItem, mut ctl: watch::Receiver<RunState>) -> ItemOutcome {
+        // 第一次开始下载时才计时；一直没开始就取消的 item 不记历史
+        let mut run: Option<(i64, Arc<TransferMeter>)> = None;
+        let mut last_error = None;
+        let outcome = loop {
+            if Self::wait_runnable(&mut ctl).await == RunState::Cancelled {
+                break ItemOutcome::Cancelled;
+            }
+            match &run {
+                Some((_, meter)) => meter.resume(),
+                None => {
+                    let meter = Arc::new(TransferMeter::new());
+                    self.item_meters.lock().unwrap().insert(item.id, meter.clone());
+                    run = Some((SqliteStore::now_epoch(), meter));
+                }
+            }
+
+            self.store.set_job_item_status(item.id, ItemStatus::Downloading).await.ok();
+            let r = self.download_item(&mut item, &mut ctl).await;
+            // 暂停等待的时间不计入平均速度
+            if let Some((_, meter)) = &run {
+                meter.pause();
+            }
+            match r {
+                Ok(_) => {
+                    let _ = self.event_tx.send(EngineEvent::ItemStatusChanged { item_id: item.id, status: ItemStatus::Done });
+                    break ItemOutcome::Done;
+                }
+                Err(e) => match e.downcast_ref::<Interrupted>() {
+                    Some(Interrupted::Paused) => {
+                        let _ = self.event_tx.send(EngineEvent::Info {
+                            job_id: Some(item.job_id),
+                            item_id: Some(item.id),
+                            scope: format!("item({})", item.display_name),
+                            message: "paused".to_string(),
+                        });
+                    }
+                    Some(Interrupted::Cancelled) => break ItemOutcome::Cancelled,
+                    None => {
+                        let (category, retryable) = Self::classify_error(&e);
+                        let _ = self.event_tx.send(EngineEvent::Error {
+                            job_id: Some(item.job_id),
+                            item_id: Some(item.id),
+                            scope: format!("item({})", item.display_name),
+                            message: format!("{:#}", e),
+                            category,
+                            retryable,
+                        });
+                        let _ = self.event_tx.send(EngineEvent::ItemStatusChanged { item_id: item.id, status: ItemStatus::Failed });
+                        last_error = Some(format!("{:#}", e));
+                        break ItemOutcome::Failed;
+                    }
+                },
+            }
+        };
+        match outcome {
+            ItemOutcome::Done => self.store.set_job_item_status(item.id, ItemStatus::Done).await.ok(),
+            ItemOutcome::Failed => self.store.set_job_item_status(item.id, ItemStatus::Failed).await.ok(),
+            ItemOutcome::Cancelled => None,
+        };
+        self.item_limits.lock().await.remove(&item.id);
+        if let Some((started_at, meter)) = run {
+            self.item_meters.lock().unwrap().remove(&item.id);
+            self.record_history(&item, &outcome, last_error, started_at, &meter).await;
+        }
+        outcome
+    }
+
+    /// 写一行下载历史；写入失败只上报，不影响 item 的结果
+    async fn record_history(
+        &self,
+        item: &DownloadItem,
+        outcome: &ItemOutcome,
+        error: Option<String>,
+        started_at: i64,
+        meter: &TransferMeter,
+    ) {
+        let Some(res) = item.resources.first() else { return };
+        let driver = match res.rtype {
+            ResourceType::BitTorrent => Some("bt"),
+            ResourceType::Adb => Some("adb"),
+            ResourceType::Ed2k => Some("ed2k"),
+            ResourceType::Sftp => Some("sftp"),
+            _ => self.registry.driver_for(res).map(|d| d.name()),
+        };
+
+        // 外部工具整体下载，不经过分片计数，完成时按文件大小计
+        let mut bytes = meter.bytes();
+        if bytes == 0 && matches!(outcome, ItemOutcome::Done) && matches!(res.rtype, ResourceType::Adb | ResourceType::Ed2k | ResourceType::Sftp) {
+            bytes = tokio::fs::metadata(&item.target_path).await.map(|m| m.len()).unwrap_or(0);
+        }
+        let avg = (bytes as f64 / meter.elapsed().as_secs_f64().max(0.001)) as u64;
+
+        let record = HistoryRecord {
+            job_id: item.job_id,
+            item_id: item.id,
+            source_uri: res.uri.clone(),
+            host: url::Url::parse(&res.uri).ok().and_then(|u| u.host_str().map(|h| h.to_ascii_lowercase())),
+            target_path: item.target_path.clone(),
+            resolver: (!item.resolver.is_empty()).then(|| item.resolver.clone()),
+            driver: driver.map(str::to_string),
+            status: match outcome {
+                ItemOutcome::Done => "done",
+                ItemOutcome::Failed => "failed",
+                ItemOutcome::Cancelled => "cancelled",
+            }
+            .to_string(),
+            error,
+            total_size: item.total_size.map(|t| t as i64),
+            bytes: bytes as i64,
+            avg_speed: avg as i64,
+            peak_speed: meter.peak_bps().max(avg) as i64,
+            started_at,
+            finished_at: SqliteStore::now_epoch(),
+        };
+        if let Err(e) = self.store.insert_history(&record).await {
+            let (category, retryable) = Self::classify_error(&e);
+            let _ = self.event_tx.send(EngineEvent::Error {
+                job_id: Some(item.job_id),
+                item_id: Some(item.id),
+                scope: format!("history item={}", item.display_name),
+                message: format!("{:#}", e),
+                category,
+                retryable,
+            });
+        }
+    }
+
+    pub async fn is_job_finished(&self, job_id: JobId) -> bool {
+        let jobs = self.jobs.lock().await;
+        matches!(jobs.get(&job_id), Some(JobStatus::Completed | JobStatus::Failed | JobStatus::Cancelled))
+    }
+
+    /// 按 store 中的分片表下载所有未完成分片（Missing/Bad），失败分片按预算重新排队。
+    /// 始终保持 concurrency 个在途分片：没有排队分片时切分剩余最多的在途分片（work stealing）。
+    async fn download_fragments(
+        &self,
+        item: &DownloadItem,
+        pool: &Arc<SourcePool>,
+        partial_path: &Path,
+        concurrency: usize,
+        ctl: &mut watch::Receiver<RunState>,
+    ) -> anyhow::Result<()> {
+        let item_rec = self.store.get_item(&pool.get(0).res.uri, &item.target_path).await?;
+        let mut db_frags = self.store.load_fragments(item_rec.item_db_id).await?;
+        let total_frags = Arc::new(AtomicU64::new(db_frags.len() as u64));
+        let completed_init = db_frags.iter().filter(|f| f.state == FragmentState::Done).count() as u64;
+
+        let assembler = Arc::new(Assembler::create(partial_path, item.total_size).await?);
+
+        // piece 校验需要知道总长（最后一个 piece 可能较短）
+        let pieces = match (&item.piece_hashes, item.total_size) {
+            (Some(p), Some(_)) if p.piece_len > 0 => Some(Arc::new(p.clone())),
+            _ => None,
+        };
+
+        let downloaded = Arc::new(Mutex::new(item_rec.downloaded_bytes.max(0) as u64));
+        let completed_frags = Arc::new(Mutex::new(completed_init));
+        let meter = self.item_meters.lock().unwrap().get(&item.id).cloned();
+
+        // 同一 item 不会同时下载两次，此刻仍是 Downloading 的分片没有人在下载，一并重新排队
+        let mut pending: VecDeque<usize> = db_frags
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| matches!(f.state, FragmentState::Missing | FragmentState::Bad | FragmentState::Downloading))
+            .map(|(i, _)| i)
+            .collect();
+
+        // 在途分片的进度，切分时据此截短
+        let mut in_flight: HashMap<usize, Arc<StdMutex<InFlight>>> = HashMap::new();
+
+        let mut source_limits = vec![];
+        for src in pool.sources() {
+            source_limits.push(self.limiters_for(item.id, &src.res).await);
+        }
+        let source_hosts: Vec<Option<String>> = pool.sources().iter().map(|s| s.driver.host_key(&s.res)).collect();
+
+        let start_time = Instant::now();
+
+        let fetch = |idx: usize, f: FragmentRecord, progress: Arc<StdMutex<InFlight>>| {
+            let src = pool.acquire();
+            let pool2 = pool.clone();
+            let dctx2 = self.driver_ctx.clone();
+            let assembler2 = assembler.clone();
+            let downloaded2 = downloaded.clone();
+            let meter2 = meter.clone();
+            let completed2 = completed_frags.clone();
+            let total_frags2 = total_frags.clone();
+            let tx = self.event_tx.clone();
+            let store2 = self.store.clone();
+            let item_db_id = item_rec.item_db_id;
+            let item_id = item.id;
+            let total = item.total_size;
+            let pieces2 = pieces.clone();
+            let limits2 = source_limits[src].clone();
+            let host2 = source_hosts[src].clone();
+            let slots2 = self.host_slots.clone();
+            let conns2 = self.connections.clone();
+            // 之前记录过哈希的分片沿用原算法，方便比对重下结果
+            let frag_algo = f.hash.as_ref().map(|c| c.algo).or(self.fragment_hash);
+
+            async move {
+                store2.set_fragment_state(f.frag_db_id, FragmentState::Downloading).await.ok();
+
+                let (offset, len) = (f.offset as u64, f.len as u64);
+                let Source { res: res2, driver: driver2 } = pool2.get(src);
+                // 主机连接名额在整个分片期间占用；等待名额/退避的时间不计入来源吞吐
+                let _slot = match &host2 {
+                    Some(h) => Some(slots2.acquire(h).await),
+                    None => None,
+                };
+                // 先占主机名额再占全局名额，避免退避中的主机占着全局连接
+                let _conn = match &conns2 {
+                    Some(c) => Some(c.clone().acquire_owned().await.expect("connection semaphore closed")),
+                    None => None,
+                };
+                let fetch_start = Instant::now();
+
+                // 边收边写：每个 chunk 直接落盘并上报进度
+                let mut received = 0u64;
+                let r = async {
+                    let mut stream = if len == 0 {
+                        driver2.download_all(res2, &dctx2).await?
+                    } else {
+                        let end = offset + len - 1;
+                        driver2.download_range(res2, &dctx2, offset, end).await?
+                    };
+
+                    let mut hasher = frag_algo.map(Hasher::new);
+                    let mut verifier = pieces2
+                        .as_deref()
+                        .zip(total)
+                        .map(|(p, t)| PieceVerifier::new(p, t, offset));
+
+                    while let Some(chunk) = stream.next().await {
+                        let mut chunk = chunk?;
+                        // 先在 progress 里占位再写：被切分后只写到新的 limit 为止
+                        let reached_limit = if len > 0 {
+                            let mut p = progress.lock().unwrap();
+                            let room = p.limit - p.received;
+                            if chunk.len() as u64 > room {
+                                if p.limit == len {
+                                    anyhow::bail!("fragment overflow at offset {}: expected {} bytes", offset, len);
+                                }
+                                chunk.truncate(room as usize);
+                            }
+                            p.received += chunk.len() as u64;
+                            p.limit < len && p.received == p.limit
+                        } else {
+                            false
+                        };
+
+                        acquire_all(&limits2, chunk.len() as u64).await;
+
+                        // piece 不符时立即中止，分片按 Bad 重新排队
+                        if let Some(v) = verifier.as_mut() {
+                            v.update(&chunk)?;
+                        }
+                        if let Some(h) = hasher.as_mut() {
+                            h.update(&chunk);
+                        }
+                        assembler2.write_at(offset + received, &chunk).await?;
+                        received += chunk.len() as u64;
+
+                        if let Some(m) = &meter2 {
+                            m.add(chunk.len() as u64);
+                        }
+                        let dnow = {
+                            let mut d = downloaded2.lock().await;
+                            *d += chunk.len() as u64;
+                            *d
+                        };
+                        Self::emit_progress(&tx, item_id, dnow, total, start_time);
+
+                        if reached_limit {
+                            // 后半段已交给别的连接，提前结束本次请求
+                            break;
+                        }
+                    }
+
+                    let expected = progress.lock().unwrap().limit;
+                    if len > 0 && received != expected {
+                        anyhow::bail!("short fragment at offset {}: got {} of {} bytes", offset, received, expected);
+                    }
+                    if len == 0 && total.is_some_and(|t| t != received) {
+                        anyhow::bail!("size mismatch: got {} bytes, expected {:?}", received, total);
+                    }
+
+                    let digest = match (hasher, frag_algo) {
+                        (Some(h), Some(algo)) => Some(Checksum::new(algo, &h.finalize_hex())?),
+                        _ => None,
+                    };
+                    // 上次完成时记录的哈希：同一资源重下的分片必须一致（被切分过的分片范围已变，不比对）
+                    if let (Some(prev), Some(now)) = (&f.hash, &digest) {
+                        if expected == len && prev.hex != now.hex {
+                            return Err(FragmentHashMismatch {
+                                offset,
+                                algo: now.algo.name(),
+                                expected: prev.hex.clone(),
+                                actual: now.hex.clone(),
+                            }
+                            .into());
+                        }
+                    }
+
+                    store2
+                        .mark_fragment_done_and_add_bytes(f.frag_db_id, item_db_id, received as i64, digest.as_ref())
+                        .await?;
+
+                    let cnow = {
+                        let mut c = completed2.lock().await;
+                        *c += 1;
+                        let _ = tx.send(EngineEvent::FragmentDone {
+                            item_id,
+                            completed: *c,
+                            total: total_frags2.load(Ordering::Relaxed),
+                        });
+                        *c
+                    };
+
+                    Ok::<u64, anyhow::Error>(cnow)
+                }
+                .await;
+
+                if r.is_err() {
+                    // 失败分片会整体重下，撤回已计入进度的字节
+                    let mut d = downloaded2.lock().await;
+                    *d = d.saturating_sub(received);
+                } else {
+                    pool2.record_success(src, received, fetch_start.elapsed());
+                    if let Some(h) = &host2 {
+                        slots2.on_success(h);
+                    }
+                }
+                (idx, src, r)
+            }
+        };
+
+        let mut futs = FuturesUnordered::new();
+        loop {
+            // 补满空闲连接：先取排队分片，没有就切分在途分片
+            while futs.len() < concurrency {
+                let idx = match pending.pop_front() {
+                    Some(idx) => idx,
+                    // 全局连接已满时不切分：切出来的分片只能排队等名额
+                    None if self.connections.as_ref().is_some_and(|c| c.available_permits() == 0) => break,
+                    None => match self.steal_fragment(item_rec.item_db_id, &mut db_frags, &in_flight, pieces.as_deref()).await? {
+                        Some(idx) => {
+                            total_frags.fetch_add(1, Ordering::Relaxed);
+                            idx
+                        }
+                        None => break,
+                    },
+                };
+                let f = db_frags[idx].clone();
+                let progress = Arc::new(StdMutex::new(InFlight { received: 0, limit: f.len.max(0) as u64 }));
+                in_flight.insert(idx, progress.clone());
+                futs.push(fetch(idx, f, progress));
+            }
+
+            let next = tokio::select! {
+                r = futs.next() => Ok(r),
+                stop = Self::stop_requested(ctl) => Err(stop),
+            };
+            let (idx, src, res) = match next {
+                Ok(Some(r)) => r,
+                Ok(None) => break,
+                Err(stop) => {
+                    // 丢弃在途分片，Downloading 的分片回退为 Missing，resume 时重新下载
+                    drop(futs);
+                    pool.release_all();
+                    self.store.reset_downloading_fragments(item_rec.item_db_id).await?;
+                    return Err(stop.into());
+                }
+            };
+            in_flight.remove(&idx);
+
+            if let Err(e) = res {
+                let f = &db_frags[idx];
+
+                // 主机限流：整个主机退避，分片重新排队且不计入重试预算
+                if let (Some(busy), Some(host)) = (e.downcast_ref::<HostBusy>(), &source_hosts[src]) {
+                    let b = self.host_slots.on_busy(host, busy.retry_after);
+                    if b.streak <= MAX_BUSY_STREAK {
+                        pool.release(src);
+                        if b.new_window {
+                            let _ = self.event_tx.send(EngineEvent::Info {
+                                job_id: Some(item.job_id),
+                                item_id: Some(item.id),
+                                scope: format!("host {}", host),
+                                message: format!("busy; backing off {:.1}s, max connections now {}", b.wait.as_secs_f64(), b.limit),
+                            });
+                        }
+                        self.store.set_fragment_state(f.frag_db_id, FragmentState::Missing).await?;
+                        pending.push_back(idx);
+                        continue;
+                    }
+                }
+
+                let demoted = pool.record_failure(src);
+
+                if Self::aborts_fragment_plan(&e) {
+                    // 还有其他来源时只降级出问题的那个（主资源内容变化除外：续传记录以它为准）
+                    if (src != 0 || !Self::is_validator_changed(&e)) && pool.demote(src) {
+                        let _ = self.event_tx.send(EngineEvent::Info {
+                            job_id: Some(item.job_id),
+                            item_id: Some(item.id),
+                            scope: format!("mirrors item={}", item.display_name),
+                            message: format!("demoting {}: {:#}", pool.get(src).res.uri, e),
+                        });
+                        self.store.set_fragment_state(f.frag_db_id, FragmentState::Missing).await?;
+                        pending.push_back(idx);
+                        continue;
+                    }
+                    // 重试无意义，交给上层切换回退模式或重新开始
+                    drop(futs);
+                    pool.release_all();
+                    self.store.reset_downloading_fragments(item_rec.item_db_id).await?;
+                    return Err(e);
+                }
+
+                if demoted {
+                    let _ = self.event_tx.send(EngineEvent::Info {
+                        job_id: Some(item.job_id),
+                        item_id: Some(item.id),
+                        scope: format!("mirrors item={}", item.display_name),
+                        message: format!("demoting {} after repeated failures", pool.get(src).res.uri),
+                    });
+                }
+
+                // 失败次数持久化在分片上，暂停 / 恢复 / 重启进程都接着累计
+                let frag_db_id = f.frag_db_id;
+                db_frags[idx].retry = self.store.mark_fragment_bad(frag_db_id).await?;
+                let f = &db_frags[idx];
+
+                let (category, retryable) = Self::classify_error(&e);
+                let _ = self.event_tx.send(EngineEvent::Error {
+                    job_id: Some(item.job_id),
+                    item_id: Some(item.id),
+                    scope: format!("download_fragment(item={})", item.id),
+                    message: format!(
+                        "offset={} len={} source={} attempt={}/{}: {:#}",
+                        f.offset, f.len, pool.get(src).res.uri, f.retry, self.fragment_retries + 1, e
+                    ),
+                    category,
+                    retryable,
+                });
+
+                if f.retry > self.fragment_retries as i64 {
+                    let offset = f.offset;
+                    drop(futs);
+                    pool.release_all();
+                    self.store.reset_downloading_fragments(item_rec.item_db_id).await?;
+                    // item 就此失败；之后显式 resume 时这个分片重新获得完整的重试预算
+                    self.store.reset_fragment_retry(frag_db_id).await?;
+                    return Err(e.context(format!("fragment at offset {} exhausted its retry budget", offset)));
+                }
+
+                // Bad 分片重新排队，其余分片继续
+                pending.push_back(idx);
+            }
+        }
+
+        assembler.flush().await?;
+        Ok(())
+    }
+
+    /// 切分剩余字节最多的在途分片：原分片截短到中点，后半段落库为新分片并返回其下标。
+    /// 有 piece 列表时切点对齐到 piece 边界，保证每个 piece 仍完整落在一个分片内。
+    async fn steal_fragment(
+        &self,
+        item_db_id: i64,
+        db_frags: &mut Vec<FragmentRecord>,
+        in_flight: &HashMap<usize, Arc<StdMutex<InFlight>>>,
+        pieces: Option<&PieceHashes>,
+    ) -> anyhow::Result<Option<usize>> {
+        let victim = in_flight
+            .iter()
+            .filter(|(idx, _)| db_frags[**idx].len > 0)
+            .map(|(idx, p)| {
+                let p = p.lock().unwrap();
+                (*idx, p.limit - p.received)
+            })
+            .max_by_key(|(_, remaining)| *remaining);
+        let Some((idx, _)) = victim else { return Ok(None) };
+
+        // 在途分片只在 futs.next() 时推进，这里到落库完成之间不会有新的写入
+        let offset = db_frags[idx].offset as u64;
+        let (keep, old_limit) = {
+            let mut p = in_flight[&idx].lock().unwrap();
+            let remaining = p.limit - p.received;
+            if remaining < 2 * MIN_SPLIT {
+                return Ok(None);
+            }
+            let mut cut = p.received + remaining / 2;
+            if let Some(pl) = pieces.map(|p| p.piece_len) {
+                cut = (offset + cut).div_ceil(pl) * pl - offset;
+                if cut >= p.limit {
+                    return Ok(None);
+                }
+            }
+            let old = p.limit;
+            p.limit = cut;
+            (cut, old)
+        };
+
+        let new_offset = offset + keep;
+        let new_len = old_limit - keep;
+        let new_id = self
+            .store
+            .split_fragment(db_frags[idx].frag_db_id, item_db_id, keep as i64, new_offset as i64, new_len as i64)
+            .await?;
+
+        db_frags[idx].len = keep as i64;
+        db_frags.push(FragmentRecord {
+            frag_db_id: new_id,
+            offset: new_offset as i64,
+            len: new_len as i64,
+            state: FragmentState::Missing,
+            retry: 0,
+            hash: None,
+        });
+        Ok(Some(db_frags.len() - 1))
+    }
+
+    /// 校验 item 的期望摘要（没有摘要或目标是目录时跳过）
+    async fn verify_item(&self, item: &DownloadItem, path: &Path) -> anyhow::Result<()> {
+        if item.checksums.is_empty() {
+            return Ok(());
+        }
+        if tokio::fs::metadata(path).await.map(|m| m.is_dir()).unwrap_or(false) {
+            let _ = self.event_tx.send(EngineEvent::Info {
+                job_id: Some(item.job_id),
+                item_id: Some(item.id),
+                scope: format!("verify item={}", item.display_name),
+                message: "target is a directory; checksum verification skipped".to_string(),
+            });
+            return Ok(());
+        }
+
+        let _ = self.event_tx.send(EngineEvent::ItemStatusChanged { item_id: item.id, status: ItemStatus::Verifying });
+        verify_file(path, &item.checksums).await?;
+
+        let algos: Vec<&str> = item.checksums.iter().map(|c| c.algo.name()).collect();
+        let _ = self.event_tx.send(EngineEvent::Info {
+            job_id: Some(item.job_id),
+            item_id: Some(item.id),
+            scope: format!("verify item={}", item.display_name),
+            message: format!("checksum ok ({})", algos.join(", ")),
+        });
+        Ok(())
+    }
+
+    /// Error 事件的分类与是否值得重试：按错误链中第一个认识的错误类型判断
+    fn classify_error(e: &anyhow::Error) -> (ErrorCategory, bool) {
+        fn by_status(s: reqwest::StatusCode) -> (ErrorCategory, bool) {
+            match s.as_u16() {
+                401 | 403 | 407 => (ErrorCategory::Auth, false),
+                408 | 429 => (ErrorCategory::HttpStatus, true),
+                _ => (ErrorCategory::HttpStatus, s.is_server_error()),
+            }
+        }
+
+        for cause in e.chain() {
+            if cause.is::<ChecksumMismatch>() {
+                return (ErrorCategory::Checksum, false);
+            }
+            // 分片重新下载即可
+            if cause.is::<FragmentHashMismatch>() {
+                return (ErrorCategory::Checksum, true);
+            }
+            if cause.is::<HostBusy>() {
+                return (ErrorCategory::HttpStatus, true);
+            }
+            if cause.is::<ExternalToolFailed>() {
+                return (ErrorCategory::ExternalTool, false);
+            }
+            if let Some(h) = cause.downcast_ref::<HttpDriverError>() {
+                return match h {
+                    HttpDriverError::Status(s) => by_status(*s),
+                    _ => (ErrorCategory::HttpStatus, true),
+                };
+            }
+            if let Some(r) = cause.downcast_ref::<reqwest::Error>() {
+                return r.status().map(by_status).unwrap_or((ErrorCategory::Network, true));
+            }
+            if let Some(f) = cause.downcast_ref::<async_ftp::FtpError>() {
+                return match f {
+                    async_ftp::FtpError::InvalidResponse(desc) if desc.contains("got response: 530") => {
+                        (ErrorCategory::Auth, false)
+                    }
+                    _ => (ErrorCategory::Network, true),
+                };
+            }
+            if cause.is::<tokio::time::error::Elapsed>() {
+                return (ErrorCategory::Network, true);
+            }
+            if cause.is::<sqlx::Error>() {
+                return (ErrorCategory::Internal, false);
+            }
+            if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+                use std::io::ErrorKind::*;
+                return match io.kind() {
+                    ConnectionRefused | ConnectionReset | ConnectionAborted | NotConnected | BrokenPipe | TimedOut
+                    | UnexpectedEof => (ErrorCategory::Network, true),
+                    _ => (ErrorCategory::Disk, false),
+                };
+            }
+        }
+        (ErrorCategory::Internal, false)
+    }
+
+    /// 这类错误重试分片无意义，需要改变下载计划（回退或重新开始）
+    fn aborts_fragment_plan(e: &anyhow::Error) -> bool {
+        matches!(
+            e.downcast_ref::<HttpDriverError>(),
+            Some(
+                HttpDriverError::RangeIgnoredFull
+                    | HttpDriverError::RangeNotSupported
+                    | HttpDriverError::ValidatorChanged
+            )
+        )
+    }
+
+    /// 占用主机连接名额探测；主机限流时按退避重试
+    async fn probe_source(&self, driver: &Arc<dyn TransferDriver>, res: &ResourceDescriptor) -> anyhow::Result<ProbeInfo> {
+        let Some(host) = driver.host_key(res) else {
+            return driver.probe(res, &self.driver_ctx).await;
+        };
+        loop {
+            let r = {
+                let _slot = self.host_slots.acquire(&host).await;
+                driver.probe(res, &self.driver_ctx).await
+            };
+            match r {
+                Err(e) if e.is::<HostBusy>() => {
+                    let retry_after = e.downcast_ref::<HostBusy>().and_then(|b| b.retry_after);
+                    let b = self.host_slots.on_busy(&host, retry_after);
+                    if b.streak > MAX_BUSY_STREAK {
+                        return Err(e);
+                    }
+                    if b.new_window {
+                        let _ = self.event_tx.send(EngineEvent::Info {
+                            job_id: None,
+                            item_id: None,
+                            scope: format!("host {}", host),
+                            message: format!("busy; backing off {:.1}s, max connections now {}", b.wait.as_secs_f64(), b.limit),
+                        });
+                    }
+                }
+                Ok(p) => {
+                    self.host_slots.on_success(&host);
+                    return Ok(p);
+                }
+                r => return r,
+            }
+        }
+    }
+
+    /// 探测镜像，只保留与主资源大小一致且支持 Range 的；主资源大小未知时不混用镜像
+    async fn accept_mirrors(
+        &self,
+        item: &DownloadItem,
+        primary: Source,
+        mirrors: &[Source],
+        primary_probe: &ProbeInfo,
+    ) -> Vec<Source> {
+        let mut accepted = vec![primary];
+        let total = match primary_probe.total_size {
+            Some(t) if primary_probe.supports_ranges => t,
+            _ => {
+                if !mirrors.is_empty() {
+                    let _ = self.event_tx.send(EngineEvent::Info {
+                        job_id: Some(item.job_id),
+                        item_id: Some(item.id),
+                        scope: format!("mirrors item={}", item.display_name),
+                        message: "primary size unknown or ranges unsupported; ignoring mirrors".to_string(),
+                    });
+                }
+                return accepted;
+            }
+        };
+
+        let probes = futures::future::join_all(mirrors.iter().map(|m| self.probe_source(&m.driver, &m.res))).await;
+
+        for (m, probe) in mirrors.iter().zip(probes) {
+            let reason = match &probe {
+                Err(e) => Some(format!("probe failed: {:#}", e)),
+                Ok(p) if p.total_size != Some(total) => {
+                    Some(format!("size {:?} differs from primary {}", p.total_size, total))
+                }
+                Ok(p) if !p.supports_ranges => Some("ranges unsupported".to_string()),
+                Ok(_) => None,
+            };
+            if let Some(reason) = reason {
+                let _ = self.event_tx.send(EngineEvent::Info {
+                    job_id: Some(item.job_id),
+                    item_id: Some(item.id),
+                    scope: format!("mirrors item={}", item.display_name),
+                    message: format!("skipping {}: {}", m.res.uri, reason),
+                });
+                continue;
+            }
+
+            // 每个镜像用自己的验证器发 If-Range
+            let probe = probe.unwrap_or_default();
+            let mut res = m.res.clone();
+            if let Some(v) = probe.etag {
+                res.meta.insert("etag".to_string(), v);
+            }
+            if let Some(v) = probe.last_modified {
+                res.meta.insert("last_modified".to_string(), v);
+            }
+            accepted.push(Source { res, driver: m.driver.clone() });
+        }
+
+        if accepted.len() > 1 {
+            let _ = self.event_tx.send(EngineEvent::Info {
+                job_id: Some(item.job_id),
+                item_id: Some(item.id),
+                scope: format!("mirrors item={}", item.display_name),
+                message: format!("downloading from {} sources", accepted.len()),
+            });
+        }
+        accepted
+    }
+
+    fn describe_sources(pool: &SourcePool) -> String {
+        pool.sources()
+            .iter()
+            .zip(pool.stats())
+            .map(|(src, st)| {
+                format!(
+                    "{}: {} bytes, {:.0} KiB/s, {} ok / {} failed ({:.0}%){}",
+                    src.res.uri,
+                    st.bytes,
+                    st.throughput() / 1024.0,
+                    st.successes,
+                    st.failures,
+                    st.error_rate() * 100.0,
+                    if st.demoted { ", demoted" } else { "" }
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    fn is_validator_changed(e: &anyhow::Error) -> bool {
+        matches!(e.downcast_ref::<HttpDriverError>(), Some(HttpDriverError::ValidatorChanged))
+    }
+
+    /// 比较上次记录与本次探测：大小或验证器（优先 ETag）不同即认为远端已变化
+    fn remote_changed(prev: &ItemRecord, probe: &ProbeInfo) -> bool {
+        if let (Some(a), Some(b)) = (prev.total_size, probe.total_size) {
+            if a as u64 != b {
+                return true;
+            }
+        }
+        if let (Some(a), Some(b)) = (&prev.etag, &probe.etag) {
+            return a != b;
+        }
+        matches!((&prev.last_modified, &probe.last_modified), (Some(a), Some(b)) if a != b)
+    }
+
+    /// 根据 Range 相关错误决定回退方式；None 表示不是 Range 问题或已无可回退
+    fn fallback_for(e: &anyhow::Error, current: Option<FallbackMode>, concurrency: usize) -> Option<FallbackMode> {
+        if current == Some(FallbackMode::Full) {
+            return None;
+        }
+        match e.downcast_ref::<HttpDriverError>()? {
+            // 服务器忽略 Range，顺序请求也一样，只能整体下载
+            HttpDriverError::RangeIgnoredFull => Some(FallbackMode::Full),
+            // 可能只是拒绝并发 Range：先降为顺序请求，仍失败再整体下载
+            HttpDriverError::RangeNotSupported if current.is_none() && concurrency > 1 => Some(FallbackMode::SeqRange),
+            HttpDriverError::RangeNotSupported => Some(FallbackMode::Full),
+            _ => None,
+        }
+    }
+
+    fn emit_progress(
+        tx: &broadcast::Sender<EngineEvent>,
+        item_id: ItemId,
+        downloaded: u64,
+        total: Option<u64>,
+        start_time: Instant,
+    ) {
+        let elapsed = start_time.elapsed().as_secs_f64().max(0.001);
+        let speed = (downloaded as f64 / elapsed) as u64;
+        let eta = match (total, speed) {
+            (Some(t), s) if s > 0 && downloaded < t => Some(Duration::from_secs_f64(((t - downloaded) as f64) / (s as f64))),
+            _ => None,
+        };
+
+        let _ = tx.send(EngineEvent::Progress {
+            item_id,
+            downloaded,
+            total,
+            speed_bps: speed,
+            eta,
+        });
+    }
+
+    async fn download_item(&self, item: &mut DownloadItem, ctl: &mut watch::Receiver<RunState>) -> anyhow::Result<()> {
+        let _ = self.event_tx.send(EngineEvent::ItemStatusChanged { item_id: item.id, status: ItemStatus::Downloading });
+
+        let res = item.resources.first().context("no resource")?.clone();
+        if matches!(res.rtype, ResourceType::BitTorrent) {
+            let info = res.meta.get("infohash").cloned().unwrap_or_default();
+            let _ = self.event_tx.send(EngineEvent::Info {
+                job_id: Some(item.job_id),
+                item_id: Some(item.id),
+                scope: format!("bt item={}", item.display_name),
+                message: format!("starting magnet download. infohash={}", info),
+            });
+
+            Self::interruptible(
+                ctl,
+                crate::plugins::bt::driver::BtDriver::new()
+                    .download_magnet_to_dir(&res, &self.driver_ctx, &item.target_path),
+            )
+            .await?;
+
+            let _ = self.event_tx.send(EngineEvent::Info {
+                job_id: Some(item.job_id),
+                item_id: Some(item.id),
+                scope: format!("bt item={}", item.display_name),
+                message: "completed".to_string(),
+            });
+            return self.verify_item(item, &item.target_path).await;
         }
 
         if matches!(res.rtype, ResourceType::Adb) {
             let _ = self.event_tx.send(EngineEvent::Info {
+                job_id: Some(item.job_id),
+                item_id: Some(item.id),
                 scope: format!("adb item={}", item.display_name),
                 message: format!("pulling {}", res.uri),
             });
 
-            crate::plugins::adb::driver::AdbDriver::new()
-                .pull_to_file(&res, &self.driver_ctx, &item.target_path, &item.options)
-                .await?;
+            Self::interruptible(
+                ctl,
+                crate::plugins::adb::driver::AdbDriver::new()
+                    .pull_to_file(&res, &self.driver_ctx, &item.target_path, &item.options),
+            )
+            .await?;
 
             let _ = self.event_tx.send(EngineEvent::Info {
+                job_id: Some(item.job_id),
+                item_id: Some(item.id),
                 scope: format!("adb item={}", item.display_name),
                 message: "completed".to_string(),
             });
-            return Ok(());
+            return self.verify_item(item, &item.target_path).await;
         }
 
         if matches!(res.rtype, ResourceType::Ed2k) {
             let hash = res.meta.get("hash").cloned().unwrap_or_default();
             let size = res.meta.get("size").cloned().unwrap_or_default();
             let _ = self.event_tx.send(EngineEvent::Info {
+                job_id: Some(item.job_id),
+                item_id: Some(item.id),
                 scope: format!("ed2k item={}", item.display_name),
                 message: format!("starting (hash={} size={})", hash, size),
             });
 
-            crate::plugins::ed2k::driver::Ed2kDriver::new()
-                .download_to_path(&res, &self.driver_ctx, &item.target_path, &item.options)
-                .await?;
+            Self::interruptible(
+                ctl,
+                crate::plugins::ed2k::driver::Ed2kDriver::new()
+                    .download_to_path(&res, &self.driver_ctx, &item.target_path, &item.options),
+            )
+            .await?;
 
             let _ = self.event_tx.send(EngineEvent::Info {
+                job_id: Some(item.job_id),
+                item_id: Some(item.id),
                 scope: format!("ed2k item={}", item.display_name),
                 message: "completed".to_string(),
             });
-            return Ok(());
+            return self.verify_item(item, &item.target_path).await;
         }
 
         if matches!(res.rtype, ResourceType::Sftp) {
             let _ = self.event_tx.send(EngineEvent::Info {
+                job_id: Some(item.job_id),
+                item_id: Some(item.id),
                 scope: format!("sftp item={}", item.display_name),
                 message: format!("downloading {}", res.uri),
             });
 
-            crate::plugins::sftp::driver::SftpDriver::new()
-                .download_to_file(&res, &self.driver_ctx, &item.target_path, &item.options)
-                .await?;
+            Self::interruptible(
+                ctl,
+                crate::plugins::sftp::driver::SftpDriver::new()
+                    .download_to_file(&res, &self.driver_ctx, &item.target_path, &item.options),
+            )
+            .await?;
 
             let _ = self.event_tx.send(EngineEvent::Info {
+                job_id: Some(item.job_id),
+                item_id: Some(item.id),
                 scope: format!("sftp item={}", item.display_name),
                 message: "completed".to_string(),
             });
-            return Ok(());
+            return self.verify_item(item, &item.target_path).await;
         }
 
         let driver = self.registry.driver_for(&res).context("no driver for resource")?;
         let _ = self.event_tx.send(EngineEvent::Info {
+            job_id: Some(item.job_id),
+            item_id: Some(item.id),
             scope: format!("driver item={}", item.display_name),
             message: format!("selected driver={}", driver.name()),
         });
         let dctx = self.driver_ctx.clone();
         driver.prepare(&res, &dctx).await?;
 
-        // probe（仍用临时 HttpDriver 做 HEAD；后续可做 trait 扩展）
-        let (total_opt, supports_ranges) = driver.probe(&res, &dctx).await.unwrap_or((None, false));
+        // 其余资源作为镜像：只接受能按分片下载的类型，准备失败的直接跳过
+        let mut sources = vec![Source { res: res.clone(), driver }];
+        for mirror in item.resources.iter().skip(1) {
+            if matches!(mirror.rtype, ResourceType::BitTorrent | ResourceType::Adb | ResourceType::Ed2k | ResourceType::Sftp) {
+                continue;
+            }
+            let Some(d) = self.registry.driver_for(mirror) else { continue };
+            if let Err(e) = d.prepare(mirror, &dctx).await {
+                let (category, retryable) = Self::classify_error(&e);
+                let _ = self.event_tx.send(EngineEvent::Error {
+                    job_id: Some(item.job_id),
+                    item_id: Some(item.id),
+                    scope: format!("mirror item={}", item.display_name),
+                    message: format!("{}: {:#}", mirror.uri, e),
+                    category,
+                    retryable,
+                });
+                continue;
+            }
+            sources.push(Source { res: mirror.clone(), driver: d });
+        }
+
+        // resolver 声明的大小（Metalink 等），每次探测都要与之比对
+        let declared_size = item.total_size;
+
+        // 远端在下载过程中变化（If-Range 不匹配）或校验失败时重新探测并从头开始，最多一次
+        let mut restarted = false;
+        loop {
+            item.total_size = declared_size;
+            match self.download_with_driver(item, &sources, ctl).await {
+                Err(e) if !restarted && (Self::is_validator_changed(&e) || e.downcast_ref::<ChecksumMismatch>().is_some()) => {
+                    let _ = self.event_tx.send(EngineEvent::Info {
+                        job_id: Some(item.job_id),
+                        item_id: Some(item.id),
+                        scope: format!("validate item={}", item.display_name),
+                        message: format!("{:#}; restarting download", e),
+                    });
+                    restarted = true;
+                }
+                r => return r,
+            }
+        }
+    }
+
+    /// 探测 -> 校验续传数据 -> 规划分片 -> 下载 -> 重命名
+    /// sources[0] 为主资源：续传记录、验证器都以它为准，镜像必须与它大小一致
+    async fn download_with_driver(
+        &self,
+        item: &mut DownloadItem,
+        sources: &[Source],
+        ctl: &mut watch::Receiver<RunState>,
+    ) -> anyhow::Result<()> {
+        let mut res = sources[0].res.clone();
+        let driver = &sources[0].driver;
+
+        let probe = self.probe_source(driver, &res).await.unwrap_or_default();
         let _ = self.event_tx.send(EngineEvent::Info {
+            job_id: Some(item.job_id),
+            item_id: Some(item.id),
             scope: format!("probe item={}", item.display_name),
-            message: format!("total={:?} supports_ranges={}", total_opt, supports_ranges),
+            message: format!(
+                "total={:?} supports_ranges={} etag={:?} last_modified={:?}",
+                probe.total_size, probe.supports_ranges, probe.etag, probe.last_modified
+            ),
         });
 
+        if let (Some(expected), Some(actual)) = (item.total_size, probe.total_size) {
+            if expected != actual {
+                anyhow::bail!("remote size {} differs from expected size {}", actual, expected);
+            }
+        }
+        let (total_opt, supports_ranges) = (probe.total_size.or(item.total_size), probe.supports_ranges);
         item.total_size = total_opt;
 
         let partial_path = item.target_path.with_extension("partial");
+
+        // 上次运行留下的分片只有在远端未变化时才能复用
+        if let Some(prev) = self.store.find_item(&res.uri, &item.target_path).await? {
+            if Self::remote_changed(&prev, &probe) {
+                let _ = self.event_tx.send(EngineEvent::Info {
+                    job_id: Some(item.job_id),
+                    item_id: Some(item.id),
+                    scope: format!("validate item={}", item.display_name),
+                    message: "remote file changed since last run; discarding stale fragments".to_string(),
+                });
+                self.store.delete_fragments(prev.item_db_id).await?;
+                let _ = tokio::fs::remove_file(&partial_path).await;
+            }
+        }
+
+        // 有 piece 列表时分片按 piece 长度对齐，坏 piece 只需重下所在分片
+        let chunk_size = match &item.piece_hashes {
+            Some(p) if p.piece_len > 0 => (self.chunk_size / p.piece_len).max(1) * p.piece_len,
+            _ => self.chunk_size,
+        };
+
         let item_rec = self.store
             .upsert_item(
                 &res.uri,
                 &item.target_path,
                 &partial_path,
-                self.chunk_size as i64,
+                chunk_size as i64,
                 item.total_size.map(|v| v as i64),
                 supports_ranges && item.total_size.is_some(),
             )
             .await?;
+        self.store
+            .set_item_validators(item_rec.item_db_id, probe.etag.as_deref(), probe.last_modified.as_deref())
+            .await?;
+
+        // 供驱动发送 If-Range
+        if let Some(v) = &probe.etag {
+            res.meta.insert("etag".to_string(), v.clone());
+        }
+        if let Some(v) = &probe.last_modified {
+            res.meta.insert("last_modified".to_string(), v.clone());
+        }
+
+        let pool = Arc::new(SourcePool::new(
+            self.accept_mirrors(item, Source { res: res.clone(), driver: driver.clone() }, &sources[1..], &probe)
+                .await,
+        ));
 
         // 规划并落库 fragments（存在就不覆盖）
-        if supports_ranges && item.total_size.is_some() {
-            let total = item.total_size.unwrap();
-            let frags = plan_ranges(total, self.chunk_size);
+        if let (true, Some(total)) = (supports_ranges, item.total_size) {
+            let frags = plan_ranges(total, chunk_size);
             let ranges: Vec<(u64, u64)> = frags
                 .iter()
                 .map(|f| match f.key {
@@ -322,118 +1983,469 @@ impl Engine {
             self.store.ensure_fragments_for_ranges(item_rec.item_db_id, &[(0, 0)]).await?;
         }
 
-        let db_frags = self.store.load_fragments(item_rec.item_db_id).await?;
-        let total_frags = db_frags.len() as u64;
-        let completed_init = db_frags.iter().filter(|f| f.state == FragmentState::Done).count() as u64;
+        // .partial 被删除或截短时，记录为 Done 的分片其实没有数据
+        let reset = recovery::check_partial(&self.store, item_rec.item_db_id, &partial_path).await?;
+        if reset > 0 {
+            let _ = self.event_tx.send(EngineEvent::Info {
+                job_id: Some(item.job_id),
+                item_id: Some(item.id),
+                scope: format!("recover item={}", item.display_name),
+                message: format!("{} completed fragments have no data in {}; downloading them again", reset, partial_path.display()),
+            });
+        }
 
-        let assembler = Arc::new(Assembler::create(&partial_path, item.total_size).await?);
+        let mut concurrency = self.concurrency;
+        let mut fallback: Option<FallbackMode> = None;
+        loop {
+            let e = match self.download_fragments(item, &pool, &partial_path, concurrency, ctl).await {
+                Ok(()) => break,
+                Err(e) => e,
+            };
 
-        let downloaded = Arc::new(Mutex::new(item_rec.downloaded_bytes.max(0) as u64));
-        let completed_frags = Arc::new(Mutex::new(completed_init));
+            let mode = match Self::fallback_for(&e, fallback, concurrency) {
+                Some(m) => m,
+                None => return Err(e),
+            };
+            let _ = self.event_tx.send(EngineEvent::Info {
+                job_id: Some(item.job_id),
+                item_id: Some(item.id),
+                scope: format!("fallback item={}", item.display_name),
+                message: format!("{:#} => switching to {:?}", e, mode),
+            });
+
+            match mode {
+                FallbackMode::SeqRange => {
+                    // 保留已完成分片，剩余分片逐个请求
+                    concurrency = 1;
+                }
+                FallbackMode::Full => {
+                    // 改写分片计划：单个 (0,0) 分片 = 整体下载
+                    self.store.delete_fragments(item_rec.item_db_id).await?;
+                    self.store.set_item_supports_ranges(item_rec.item_db_id, false).await?;
+                    self.store.ensure_fragments_for_ranges(item_rec.item_db_id, &[(0, 0)]).await?;
+                }
+            }
+            fallback = Some(mode);
+        }
+
+        if pool.sources().len() > 1 {
+            let _ = self.event_tx.send(EngineEvent::Info {
+                job_id: Some(item.job_id),
+                item_id: Some(item.id),
+                scope: format!("mirrors item={}", item.display_name),
+                message: Self::describe_sources(&pool),
+            });
+        }
+
+        let db_frags2 = self.store.load_fragments(item_rec.item_db_id).await?;
+        if !db_frags2.iter().all(|f| f.state == FragmentState::Done) {
+            anyhow::bail!("not all fragments completed (unexpected)");
+        }
+
+        if let Err(e) = self.verify_item(item, &partial_path).await {
+            if e.downcast_ref::<ChecksumMismatch>().is_some() {
+                // 内容损坏：丢弃分片与 .partial，由上层决定是否重新下载
+                self.store.delete_fragments(item_rec.item_db_id).await?;
+                let _ = tokio::fs::remove_file(&partial_path).await;
+            }
+            return Err(e);
+        }
 
-        let mut pending: Vec<usize> = db_frags
+        let _ = self.event_tx.send(EngineEvent::ItemStatusChanged { item_id: item.id, status: ItemStatus::Assembling });
+
+        if tokio::fs::metadata(&item.target_path).await.is_ok() {
+            let _ = tokio::fs::remove_file(&item.target_path).await;
+        }
+        tokio::fs::rename(&partial_path, &item.target_path).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::*;
+
+    #[test]
+    fn fallback_decisions() {
+        let ignored = anyhow::Error::from(HttpDriverError::RangeIgnoredFull);
+        let rejected = anyhow::Error::from(HttpDriverError::RangeNotSupported);
+        let other = anyhow::Error::from(HttpDriverError::Status(reqwest::StatusCode::NOT_FOUND));
+
+        assert_eq!(Engine::fallback_for(&ignored, None, 4), Some(FallbackMode::Full));
+        assert_eq!(Engine::fallback_for(&rejected, None, 4), Some(FallbackMode::SeqRange));
+        // 已经是单连接，或顺序请求仍被拒绝：只能整体下载
+        assert_eq!(Engine::fallback_for(&rejected, None, 1), Some(FallbackMode::Full));
+        assert_eq!(Engine::fallback_for(&rejected, Some(FallbackMode::SeqRange), 1), Some(FallbackMode::Full));
+        // 整体下载仍失败、或不是 Range 问题：不再回退
+        assert_eq!(Engine::fallback_for(&ignored, Some(FallbackMode::Full), 4), None);
+        assert_eq!(Engine::fallback_for(&other, None, 4), None);
+    }
+
+    #[tokio::test]
+    async fn range_ignored_falls_back_to_full_download() {
+        let tmp = test_dir();
+        let dir = tmp.path().to_path_buf();
+        let data = test_data(3 * 1024 * 1024 + 123);
+        let driver = MockDriver::new(data.clone(), RangeMode::Ignore);
+        let engine = test_engine(driver.clone(), &dir, 4).await;
+
+        let mut item = test_item(&dir, "ignored.bin");
+        let (_ctl_tx, mut ctl) = watch::channel(RunState::Running);
+        engine.download_item(&mut item, &mut ctl).await.unwrap();
+
+        assert_eq!(std::fs::read(&item.target_path).unwrap(), data);
+        assert_eq!(driver.full_calls.load(Ordering::SeqCst), 1);
+        let rec = engine.store.get_item(&item.resources[0].uri, &item.target_path).await.unwrap();
+        let progress = engine.store.load_item_progress(Some(rec.item_db_id)).await.unwrap();
+        assert!(!progress[0].supports_ranges);
+        let frags = engine.store.load_fragments(rec.item_db_id).await.unwrap();
+        assert_eq!(frags.len(), 1);
+        assert_eq!((frags[0].offset, frags[0].len), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn range_rejected_falls_back_to_sequential_ranges() {
+        let tmp = test_dir();
+        let dir = tmp.path().to_path_buf();
+        let data = test_data(3 * 1024 * 1024 + 123);
+        let driver = MockDriver::new(data.clone(), RangeMode::RejectFirst);
+        let engine = test_engine(driver.clone(), &dir, 4).await;
+
+        let mut item = test_item(&dir, "rejected.bin");
+        let (_ctl_tx, mut ctl) = watch::channel(RunState::Running);
+        engine.download_item(&mut item, &mut ctl).await.unwrap();
+
+        assert_eq!(std::fs::read(&item.target_path).unwrap(), data);
+        // 顺序 Range 能继续，不需要整体下载；分片计划保持不变
+        assert_eq!(driver.full_calls.load(Ordering::SeqCst), 0);
+        let rec = engine.store.get_item(&item.resources[0].uri, &item.target_path).await.unwrap();
+        let progress = engine.store.load_item_progress(Some(rec.item_db_id)).await.unwrap();
+        assert!(progress[0].supports_ranges);
+        let frags = engine.store.load_fragments(rec.item_db_id).await.unwrap();
+        assert_eq!(frags.len(), 4);
+        assert!(frags.iter().all(|f| f.state == FragmentState::Done));
+    }
+
+    #[tokio::test]
+    async fn dir_option_must_stay_inside_out_dir() {
+        let tmp = test_dir();
+        let dir = tmp.path().to_path_buf();
+        let engine = test_engine(MockDriver::new(vec![], RangeMode::Ignore), &dir, 1).await;
+
+        assert_eq!(engine.target_dir("sub/x").unwrap(), dir.join("sub/x"));
+        assert_eq!(engine.target_dir("sub/../y").unwrap(), dir.join("y"));
+        // aria2 客户端给的绝对路径，只要在 out_dir 里就接受
+        assert_eq!(engine.target_dir(&dir.join("abs").to_string_lossy()).unwrap(), dir.join("abs"));
+        assert!(engine.target_dir("../escape").is_err());
+        assert!(engine.target_dir("sub/../../escape").is_err());
+        assert!(engine.target_dir("/etc").is_err());
+        assert!(engine.target_dir("").is_err());
+    }
+
+    /// 建一个按 ranges 切好分片的 item，返回 item_db_id 和分片记录
+    async fn item_with_fragments(engine: &Engine, dir: &Path, ranges: &[(u64, u64)]) -> (i64, Vec<FragmentRecord>) {
+        let total: u64 = ranges.iter().map(|(_, len)| len).sum();
+        let target = dir.join("steal.bin");
+        let rec = engine
+            .store
+            .upsert_item("mock://host/steal.bin", &target, &target.with_extension("partial"), 1024 * 1024, Some(total as i64), true)
+            .await
+            .unwrap();
+        engine.store.ensure_fragments_for_ranges(rec.item_db_id, ranges).await.unwrap();
+        let frags = engine.store.load_fragments(rec.item_db_id).await.unwrap();
+        (rec.item_db_id, frags)
+    }
+
+    fn in_flight(entries: &[(usize, u64, u64)]) -> HashMap<usize, Arc<StdMutex<InFlight>>> {
+        entries
             .iter()
-            .enumerate()
-            .filter(|(_, f)| matches!(f.state, FragmentState::Missing | FragmentState::Bad))
-            .map(|(i, _)| i)
-            .collect();
+            .map(|&(idx, received, limit)| (idx, Arc::new(StdMutex::new(InFlight { received, limit }))))
+            .collect()
+    }
 
-        let start_time = Instant::now();
+    #[tokio::test]
+    async fn steal_splits_largest_remaining_fragment() {
+        const MB: u64 = 1024 * 1024;
+        let tmp = test_dir();
+        let dir = tmp.path().to_path_buf();
+        let engine = test_engine(MockDriver::new(vec![], RangeMode::Ignore), &dir, 4).await;
+        let (item_db_id, mut frags) = item_with_fragments(&engine, &dir, &[(0, 4 * MB), (4 * MB, 4 * MB)]).await;
+        // 分片 0 还剩 3 MiB，分片 1 只剩 0.5 MiB
+        let flights = in_flight(&[(0, MB, 4 * MB), (1, 3 * MB + MB / 2, 4 * MB)]);
 
-        while !pending.is_empty() {
-            let batch: Vec<usize> = pending.drain(0..pending.len().min(self.concurrency)).collect();
-            let mut futs = FuturesUnordered::new();
+        let idx = engine.steal_fragment(item_db_id, &mut frags, &flights, None).await.unwrap().unwrap();
+        assert_eq!(idx, 2);
+        // 从剩余部分的中点切开：在途分片截短，后半段成为新的待下载分片
+        assert_eq!(flights[&0].lock().unwrap().limit, 2 * MB + MB / 2);
+        assert_eq!(frags[0].len as u64, 2 * MB + MB / 2);
+        assert_eq!((frags[2].offset as u64, frags[2].len as u64), (2 * MB + MB / 2, MB + MB / 2));
+        assert_eq!(frags[2].state, FragmentState::Missing);
 
-            for idx in batch {
-                let f = db_frags[idx].clone();
-                let driver2 = driver.clone();
-                let res2 = res.clone();
-                let dctx2 = dctx.clone();
-                let assembler2 = assembler.clone();
-                let downloaded2 = downloaded.clone();
-                let completed2 = completed_frags.clone();
-                let tx = self.event_tx.clone();
-                let store2 = self.store.clone();
-                let item_db_id = item_rec.item_db_id;
-                let item_id = item.id;
-                let total = item.total_size;
-
-                futs.push(async move {
-                    store2.set_fragment_state(f.frag_db_id, FragmentState::Downloading).await.ok();
-
-                    let (offset, len) = (f.offset as u64, f.len as u64);
-
-                    let bytes = if len == 0 {
-                        driver2.download_all(&res2, &dctx2).await?
-                    } else {
-                        let end = offset + len - 1;
-                        driver2.download_range(&res2, &dctx2, offset, end).await?
-                    };
+        // 切分已落库，重新加载得到同样的分片表
+        let stored = engine.store.load_fragments(item_db_id).await.unwrap();
+        let mut ranges: Vec<(i64, i64)> = stored.iter().map(|f| (f.offset, f.len)).collect();
+        ranges.sort();
+        let mut expected: Vec<(i64, i64)> = frags.iter().map(|f| (f.offset, f.len)).collect();
+        expected.sort();
+        assert_eq!(ranges, expected);
+        assert_eq!(ranges.iter().map(|(_, len)| len).sum::<i64>(), 8 * MB as i64);
+    }
 
-                    assembler2.write_at(offset, &bytes).await?;
-                    store2.mark_fragment_done_and_add_bytes(f.frag_db_id, item_db_id, bytes.len() as i64).await?;
+    #[tokio::test]
+    async fn steal_aligns_cut_to_piece_boundary() {
+        const KB: u64 = 1024;
+        let tmp = test_dir();
+        let dir = tmp.path().to_path_buf();
+        let engine = test_engine(MockDriver::new(vec![], RangeMode::Ignore), &dir, 4).await;
+        // 分片从 1000 KiB 开始，不在 piece 边界上
+        let (item_db_id, mut frags) = item_with_fragments(&engine, &dir, &[(0, 1000 * KB), (1000 * KB, 4000 * KB)]).await;
+        let pieces = PieceHashes { algo: HashAlgo::Sha1, piece_len: 768 * KB, hashes: vec![] };
+        let flights = in_flight(&[(1, 0, 4000 * KB)]);
 
-                    let dnow = {
-                        let mut d = downloaded2.lock().await;
-                        *d += bytes.len() as u64;
-                        *d
-                    };
+        let idx = engine.steal_fragment(item_db_id, &mut frags, &flights, Some(&pieces)).await.unwrap().unwrap();
+        // 中点 3000 KiB 向上取整到 768 KiB 的倍数 3072 KiB
+        assert_eq!(frags[idx].offset as u64, 3072 * KB);
+        assert_eq!(frags[idx].offset as u64 % pieces.piece_len, 0);
+        assert_eq!(frags[1].len as u64 + frags[idx].len as u64, 4000 * KB);
+        assert_eq!(flights[&1].lock().unwrap().limit, 2072 * KB);
+    }
 
-                    let cnow = {
-                        let mut c = completed2.lock().await;
-                        *c += 1;
-                        let _ = tx.send(EngineEvent::FragmentDone {
-                            item_id,
-                            completed: *c,
-                            total: total_frags,
-                        });
-                        *c
-                    };
+    #[tokio::test]
+    async fn steal_refuses_small_or_unalignable_remainders() {
+        const KB: u64 = 1024;
+        let tmp = test_dir();
+        let dir = tmp.path().to_path_buf();
+        let engine = test_engine(MockDriver::new(vec![], RangeMode::Ignore), &dir, 4).await;
+        let (item_db_id, mut frags) = item_with_fragments(&engine, &dir, &[(0, 2048 * KB)]).await;
 
-                    let elapsed = start_time.elapsed().as_secs_f64().max(0.001);
-                    let speed = (dnow as f64 / elapsed) as u64;
-                    let eta = match (total, speed) {
-                        (Some(t), s) if s > 0 && dnow < t => Some(Duration::from_secs_f64(((t - dnow) as f64) / (s as f64))),
-                        _ => None,
-                    };
+        // 剩余不到 2 * MIN_SPLIT
+        let flights = in_flight(&[(0, 1100 * KB, 2048 * KB)]);
+        assert!(engine.steal_fragment(item_db_id, &mut frags, &flights, None).await.unwrap().is_none());
+        // 对齐后的切点落在分片末尾之后
+        let flights = in_flight(&[(0, 0, 2048 * KB)]);
+        let pieces = PieceHashes { algo: HashAlgo::Sha1, piece_len: 4096 * KB, hashes: vec![] };
+        assert!(engine.steal_fragment(item_db_id, &mut frags, &flights, Some(&pieces)).await.unwrap().is_none());
+        assert!(engine.steal_fragment(item_db_id, &mut frags, &HashMap::new(), None).await.unwrap().is_none());
 
-                    let _ = tx.send(EngineEvent::Progress {
-                        item_id,
-                        downloaded: dnow,
-                        total,
-                        speed_bps: speed,
-                        eta,
-                    });
+        assert_eq!(frags.len(), 1);
+        assert_eq!(engine.store.load_fragments(item_db_id).await.unwrap().len(), 1);
+    }
 
-                    Ok::<u64, anyhow::Error>(cnow)
-                });
+    #[tokio::test]
+    async fn ranged_body_streams_into_assembler() {
+        let tmp = test_dir();
+        let dir = tmp.path().to_path_buf();
+        // 每个分片由多个 64 KiB chunk 组成，最后一个分片不满 1 MiB
+        let data = test_data(3 * 1024 * 1024 + 123);
+        let driver = MockDriver::new(data.clone(), RangeMode::Serve);
+        let engine = test_engine(driver.clone(), &dir, 4).await;
+
+        let mut item = test_item(&dir, "stream.bin");
+        let (_ctl_tx, mut ctl) = watch::channel(RunState::Running);
+        engine.download_item(&mut item, &mut ctl).await.unwrap();
+
+        assert_eq!(std::fs::read(&item.target_path).unwrap(), data);
+        assert!(!item.target_path.with_extension("partial").exists());
+        assert_eq!(driver.full_calls.load(Ordering::SeqCst), 0);
+        let rec = engine.store.get_item(&item.resources[0].uri, &item.target_path).await.unwrap();
+        let frags = engine.store.load_fragments(rec.item_db_id).await.unwrap();
+        assert_eq!(frags.len(), 4);
+        assert!(frags.iter().all(|f| f.state == FragmentState::Done));
+        assert_eq!(frags.iter().map(|f| f.len).sum::<i64>(), data.len() as i64);
+    }
+
+    /// 服务器返回的字节数与请求的范围不符：分片失败，不会写出错误的文件
+    async fn mismatched_body_fails(mode: RangeMode, name: &str) -> String {
+        let tmp = test_dir();
+        let dir = tmp.path().to_path_buf();
+        let driver = MockDriver::new(test_data(3 * 1024 * 1024 + 123), mode);
+        let engine = test_engine(driver, &dir, 2).await;
+
+        let mut item = test_item(&dir, name);
+        let (_ctl_tx, mut ctl) = watch::channel(RunState::Running);
+        let e = engine.download_item(&mut item, &mut ctl).await.unwrap_err();
+        assert!(!item.target_path.exists());
+        let rec = engine.store.get_item(&item.resources[0].uri, &item.target_path).await.unwrap();
+        let frags = engine.store.load_fragments(rec.item_db_id).await.unwrap();
+        assert!(frags.iter().any(|f| f.state != FragmentState::Done));
+        format!("{:#}", e)
+    }
+
+    #[tokio::test]
+    async fn overlong_body_is_rejected() {
+        let e = mismatched_body_fails(RangeMode::Long, "long.bin").await;
+        assert!(e.contains("fragment overflow"), "{}", e);
+    }
+
+    #[tokio::test]
+    async fn short_body_is_rejected() {
+        let e = mismatched_body_fails(RangeMode::Short, "short.bin").await;
+        assert!(e.contains("short fragment"), "{}", e);
+    }
+
+    #[tokio::test]
+    async fn job_items_run_in_parallel_under_connection_cap() {
+        let tmp = test_dir();
+        let dir = tmp.path().to_path_buf();
+        let data = test_data(1024 * 1024);
+        let driver = MockDriver::with_delay(data.clone(), RangeMode::Serve, Duration::from_millis(2));
+        // 每个 item 单连接，全局最多 2 个连接
+        let engine = test_engine_with_connections(driver.clone(), &dir, 1, 2).await;
+        engine.set_max_active_items(4);
+
+        let items: Vec<JobItemRecord> = (0..4)
+            .map(|i| JobItemRecord { item: test_item(&dir, &format!("p{}.bin", i)), status: ItemStatus::Ready })
+            .collect();
+        let paths: Vec<PathBuf> = items.iter().map(|r| r.item.target_path.clone()).collect();
+        let (_ctl_tx, ctl) = watch::channel(RunState::Running);
+        let status = engine
+            .execute_job(Uuid::new_v4(), JobPlan::Restored { items, resolve_failed: false }, ctl)
+            .await;
+
+        assert_eq!(status, JobStatus::Completed);
+        for p in &paths {
+            assert_eq!(std::fs::read(p).unwrap(), data);
+        }
+        // 多个 item 同时在下，但总连接数不超过上限
+        assert_eq!(driver.peak_streams.load(Ordering::SeqCst), 2);
+        assert_eq!(driver.open_streams.load(Ordering::SeqCst), 0);
+    }
+
+    /// 轮询直到任务进入 status（最多约 5 秒）
+    async fn wait_status(engine: &Engine, job_id: JobId, status: JobStatus) {
+        for _ in 0..500 {
+            if engine.job_status(job_id).await == Some(status) {
+                return;
             }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("job never reached {:?}, now {:?}", status, engine.job_status(job_id).await);
+    }
 
-            while let Some(res) = futs.next().await {
-                if let Err(e) = res {
-                    let _ = self.event_tx.send(EngineEvent::Error {
-                        scope: format!("download_fragment(item={})", item.id),
-                        message: format!("{:#}", e),
-                    });
-                    anyhow::bail!(e);
-                }
+    async fn wait_served(driver: &MockDriver) {
+        for _ in 0..500 {
+            if driver.served.load(Ordering::SeqCst) > 0 {
+                return;
             }
+            tokio::time::sleep(Duration::from_millis(10)).await;
         }
+        panic!("download never started");
+    }
 
-        assembler.flush().await?;
+    #[tokio::test]
+    async fn pause_stops_progress_and_resume_completes() {
+        let tmp = test_dir();
+        let dir = tmp.path().to_path_buf();
+        let data = test_data(4 * 1024 * 1024);
+        let driver = MockDriver::with_delay(data.clone(), RangeMode::Serve, Duration::from_millis(10));
+        let engine = test_engine(driver.clone(), &dir, 2).await;
 
-        let _ = self.event_tx.send(EngineEvent::ItemStatusChanged { item_id: item.id, status: ItemStatus::Assembling });
+        let job_id = engine.add_and_start(vec![test_input("pause.bin")]).await.unwrap();
+        wait_served(&driver).await;
+        engine.pause_job(job_id).await.unwrap();
+        wait_status(&engine, job_id, JobStatus::Paused).await;
 
-        let db_frags2 = self.store.load_fragments(item_rec.item_db_id).await?;
-        if !db_frags2.iter().all(|f| f.state == FragmentState::Done) {
-            anyhow::bail!("not all fragments completed (unexpected)");
-        }
+        // 在途分片被中断，暂停期间不再有数据
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let served = driver.served.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(driver.served.load(Ordering::SeqCst), served);
+        assert_eq!(driver.open_streams.load(Ordering::SeqCst), 0);
+        assert!(served < data.len());
 
-        if tokio::fs::metadata(&item.target_path).await.is_ok() {
-            let _ = tokio::fs::remove_file(&item.target_path).await;
-        }
-        tokio::fs::rename(&partial_path, &item.target_path).await?;
+        engine.resume_job(job_id).await.unwrap();
+        assert_eq!(engine.job_status(job_id).await, Some(JobStatus::Running));
+        engine.wait_job(job_id).await;
+        assert_eq!(engine.job_status(job_id).await, Some(JobStatus::Completed));
+        assert_eq!(std::fs::read(dir.join("pause.bin")).unwrap(), data);
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn cancel_keeps_partial_file() {
+        let tmp = test_dir();
+        let dir = tmp.path().to_path_buf();
+        let driver = MockDriver::with_delay(test_data(4 * 1024 * 1024), RangeMode::Serve, Duration::from_millis(10));
+        let engine = test_engine(driver.clone(), &dir, 2).await;
+
+        let job_id = engine.add_and_start(vec![test_input("cancel.bin")]).await.unwrap();
+        wait_served(&driver).await;
+        engine.cancel_job(job_id).await.unwrap();
+        engine.wait_job(job_id).await;
+
+        assert_eq!(engine.job_status(job_id).await, Some(JobStatus::Cancelled));
+        assert!(dir.join("cancel.partial").exists());
+        assert!(!dir.join("cancel.bin").exists());
+        // 在途分片回退为 Missing，下次可以续传
+        let target = dir.join("cancel.bin");
+        let rec = engine.store.get_item("mock://host/cancel.bin", &target).await.unwrap();
+        let frags = engine.store.load_fragments(rec.item_db_id).await.unwrap();
+        assert!(frags.iter().all(|f| matches!(f.state, FragmentState::Done | FragmentState::Missing)));
+        assert!(frags.iter().any(|f| f.state == FragmentState::Missing));
+    }
+
+    #[tokio::test]
+    async fn queued_job_can_be_paused_resumed_and_cancelled() {
+        let tmp = test_dir();
+        let dir = tmp.path().to_path_buf();
+        let driver = MockDriver::with_delay(test_data(2 * 1024 * 1024), RangeMode::Serve, Duration::from_millis(10));
+        let engine = test_engine(driver.clone(), &dir, 1).await;
+        engine.set_max_active_jobs(1);
+
+        let running = engine.add_and_start(vec![test_input("first.bin")]).await.unwrap();
+        wait_served(&driver).await;
+        let queued = engine.add_and_start(vec![test_input("second.bin")]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(engine.job_status(queued).await, Some(JobStatus::Pending));
+
+        // 排队中恢复：没有运行名额，仍是 Pending 而不是 Running
+        engine.pause_job(queued).await.unwrap();
+        assert_eq!(engine.job_status(queued).await, Some(JobStatus::Paused));
+        engine.resume_job(queued).await.unwrap();
+        assert_eq!(engine.job_status(queued).await, Some(JobStatus::Pending));
+
+        engine.cancel_job(queued).await.unwrap();
+        engine.wait_job(queued).await;
+        assert_eq!(engine.job_status(queued).await, Some(JobStatus::Cancelled));
+        assert!(!dir.join("second.bin").exists());
+
+        // 排在后面的任务取消后，正在运行的任务不受影响
+        engine.wait_job(running).await;
+        assert_eq!(engine.job_status(running).await, Some(JobStatus::Completed));
+        assert_eq!(driver.range_calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn fragment_retry_budget_spans_runs() {
+        let tmp = test_dir();
+        let dir = tmp.path().to_path_buf();
+        let driver = MockDriver::new(test_data(100 * 1024), RangeMode::Fail);
+        // fragment_retries = 2：同一分片总共最多尝试 3 次
+        let engine = test_engine(driver.clone(), &dir, 1).await;
+        let mut item = test_item(&dir, "flaky.bin");
+
+        // 第一次运行：失败一次后被暂停
+        let (ctl_tx, mut ctl) = watch::channel(RunState::Running);
+        *driver.on_fail.lock().unwrap() = Some(Box::new(move || {
+            let _ = ctl_tx.send(RunState::Paused);
+        }));
+        let e = engine.download_item(&mut item, &mut ctl).await.unwrap_err();
+        assert!(matches!(e.downcast_ref::<Interrupted>(), Some(Interrupted::Paused)));
+        let rec = engine.store.get_item(&item.resources[0].uri, &item.target_path).await.unwrap();
+        let frags = engine.store.load_fragments(rec.item_db_id).await.unwrap();
+        assert_eq!(frags.iter().map(|f| f.retry).collect::<Vec<_>>(), vec![1]);
+        // 暂停时被丢弃的在途请求不算失败
+        let first_run = driver.range_calls.load(Ordering::SeqCst);
+
+        // 第二次运行接着累计：只剩 2 次机会
+        let (_ctl_tx, mut ctl) = watch::channel(RunState::Running);
+        let e = engine.download_item(&mut item, &mut ctl).await.unwrap_err();
+        assert!(format!("{:#}", e).contains("exhausted its retry budget"), "{:#}", e);
+        assert_eq!(driver.range_calls.load(Ordering::SeqCst) - first_run, 2);
+
+        // 用尽后计数清零，显式 resume 时重新获得完整预算
+        let frags = engine.store.load_fragments(rec.item_db_id).await.unwrap();
+        assert!(frags.iter().all(|f| f.retry == 0));
     }
 }
@@ -1,20 +1,63 @@
 use crate::core::model::{ItemId, ItemStatus, JobId, JobStatus};
+use serde::{Serialize, Serializer};
 use std::path::PathBuf;
 use std::time::Duration;
 
-#[derive(Debug, Clone)]
+/// 序列化为 JSON（daemon 事件流）时以 `type` 字段区分事件类型
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum EngineEvent {
     JobStatusChanged { job_id: JobId, status: JobStatus },
-    ItemAdded { item_id: ItemId, display_name: String, target_path: PathBuf, uri: String },
+    ItemAdded { job_id: JobId, item_id: ItemId, display_name: String, target_path: PathBuf, uri: String },
     ItemStatusChanged { item_id: ItemId, status: ItemStatus },
     Progress {
         item_id: ItemId,
         downloaded: u64,
         total: Option<u64>,
         speed_bps: u64,
+        #[serde(serialize_with = "secs_opt")]
         eta: Option<Duration>,
     },
     FragmentDone { item_id: ItemId, completed: u64, total: u64 },
-    Error { scope: String, message: String },
-    Info { scope: String, message: String },
+    /// job_id / item_id 标明错误属于哪个任务 / item（与之无关时为 None）；
+    /// retryable 表示同样的操作再试一次有可能成功
+    Error {
+        job_id: Option<JobId>,
+        item_id: Option<ItemId>,
+        scope: String,
+        message: String,
+        category: ErrorCategory,
+        retryable: bool,
+    },
+    Info { job_id: Option<JobId>, item_id: Option<ItemId>, scope: String, message: String },
+}
+
+/// Error 事件的分类
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    /// 连接失败、超时、连接被重置等
+    Network,
+    /// 远端返回了错误状态码（认证类除外）
+    HttpStatus,
+    /// 401/403/407、FTP 登录失败
+    Auth,
+    /// 本地文件读写
+    Disk,
+    /// 整体摘要或分片哈希不匹配
+    Checksum,
+    /// 链接无法解析成 item
+    Resolve,
+    /// adb、scp 等外部命令失败
+    ExternalTool,
+    /// 续传库、任务调度等内部错误
+    Internal,
+}
+
+/// Duration 按整秒输出（serde 默认会输出 `{"secs", "nanos"}`）
+fn secs_opt<S: Serializer>(d: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+    match d {
+        Some(d) => s.serialize_some(&d.as_secs()),
+        None => s.serialize_none(),
+    }
 }
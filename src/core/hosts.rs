@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{Duration, Instant};
+
+/// 没有 Retry-After 时的首次退避，之后每个退避窗口翻倍
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Retry-After 给得再大也只等这么久
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(600);
+/// 连续这么多个退避窗口都没有成功请求，就不再视为限流而按普通失败处理
+pub const MAX_BUSY_STREAK: u32 = 8;
+
+/// 按主机（host:port）限制连接数，跨 item / job 共享。
+/// 主机返回 429/503/421 时整个主机暂停到退避结束，并把连接数减半；
+/// 之后每成功 limit 次加 1（AIMD），直到回到 max_per_host。
+pub struct HostSlots {
+    max_per_host: usize,
+    hosts: Mutex<HashMap<String, Arc<HostState>>>,
+}
+
+struct HostState {
+    sem: Arc<Semaphore>,
+    inner: Mutex<HostInner>,
+}
+
+struct HostInner {
+    /// 当前允许的连接数（<= max_per_host）
+    limit: usize,
+    /// 收缩时还没收回的许可：归还时直接丢弃
+    debt: usize,
+    busy_until: Option<Instant>,
+    busy_streak: u32,
+    /// 上次调整 limit 之后的成功次数
+    successes: usize,
+}
+
+/// on_busy 的结果
+pub struct Backoff {
+    pub wait: Duration,
+    pub limit: usize,
+    pub streak: u32,
+    /// 是否开启了新的退避窗口（同一窗口内的后续 429 不再重复上报）
+    pub new_window: bool,
+}
+
+/// 持有期间占用主机的一个连接名额
+pub struct HostPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    host: Arc<HostState>,
+}
+
+impl Drop for HostPermit {
+    fn drop(&mut self) {
+        if let Some(p) = self.permit.take() {
+            let mut i = self.host.inner.lock().unwrap();
+            if i.debt > 0 {
+                i.debt -= 1;
+                p.forget();
+            }
+        }
+    }
+}
+
+impl HostSlots {
+    /// max_per_host = 0 表示不限连接数（仍然遵守退避）
+    pub fn new(max_per_host: usize) -> Self {
+        Self { max_per_host, hosts: Mutex::new(HashMap::new()) }
+    }
+
+    fn host(&self, key: &str) -> Arc<HostState> {
+        let mut m = self.hosts.lock().unwrap();
+        m.entry(key.to_string())
+            .or_insert_with(|| {
+                Arc::new(HostState {
+                    sem: Arc::new(Semaphore::new(self.max_per_host)),
+                    inner: Mutex::new(HostInner {
+                        limit: self.max_per_host,
+                        debt: 0,
+                        busy_until: None,
+                        busy_streak: 0,
+                        successes: 0,
+                    }),
+                })
+            })
+            .clone()
+    }
+
+    /// 等待退避结束并取得一个连接名额
+    pub async fn acquire(&self, key: &str) -> HostPermit {
+        let host = self.host(key);
+        loop {
+            let until = host.inner.lock().unwrap().busy_until;
+            match until {
+                Some(t) if t > Instant::now() => tokio::time::sleep_until(t).await,
+                _ => break,
+            }
+        }
+
+        let permit = if self.max_per_host > 0 {
+            Some(host.sem.clone().acquire_owned().await.expect("host semaphore closed"))
+        } else {
+            None
+        };
+        HostPermit { permit, host }
+    }
+
+    /// 记录一次“主机忙”。
+    /// 同一退避窗口内的多次 429 只算一次，避免并发请求把上限一下减到 1。
+    pub fn on_busy(&self, key: &str, retry_after: Option<Duration>) -> Backoff {
+        let host = self.host(key);
+        let mut i = host.inner.lock().unwrap();
+        let now = Instant::now();
+        if let Some(t) = i.busy_until.filter(|t| *t > now) {
+            return Backoff { wait: t - now, limit: i.limit, streak: i.busy_streak, new_window: false };
+        }
+
+        i.busy_streak += 1;
+        let wait = retry_after
+            .map(|d| d.min(MAX_RETRY_AFTER))
+            .unwrap_or_else(|| (BASE_BACKOFF * 2u32.saturating_pow(i.busy_streak - 1)).min(MAX_BACKOFF));
+        i.busy_until = Some(now + wait);
+
+        if self.max_per_host > 0 && i.limit > 1 {
+            let new_limit = (i.limit / 2).max(1);
+            i.debt += i.limit - new_limit;
+            i.limit = new_limit;
+            // 空闲的许可立即收回，剩下的等在用的连接归还时再丢弃
+            let reclaimed = host.sem.forget_permits(i.debt);
+            i.debt -= reclaimed;
+        }
+        i.successes = 0;
+        Backoff { wait, limit: i.limit, streak: i.busy_streak, new_window: true }
+    }
+
+    /// 请求成功：清零退避计数，连接上限逐步恢复
+    pub fn on_success(&self, key: &str) {
+        let host = self.host(key);
+        let mut i = host.inner.lock().unwrap();
+        i.busy_streak = 0;
+        if self.max_per_host == 0 || i.limit >= self.max_per_host {
+            return;
+        }
+        i.successes += 1;
+        if i.successes >= i.limit {
+            i.successes = 0;
+            i.limit += 1;
+            if i.debt > 0 {
+                i.debt -= 1;
+            } else {
+                host.sem.add_permits(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST: &str = "example.com:443";
+
+    /// 当前实际能同时拿到的连接名额数
+    async fn usable_permits(slots: &HostSlots, upto: usize) -> usize {
+        let mut held = vec![];
+        while held.len() < upto {
+            match tokio::time::timeout(Duration::from_millis(10), slots.acquire(HOST)).await {
+                Ok(p) => held.push(p),
+                Err(_) => break,
+            }
+        }
+        held.len()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn busy_halves_limit_once_per_window_down_to_one() {
+        let slots = HostSlots::new(8);
+        let b = slots.on_busy(HOST, None);
+        assert!(b.new_window);
+        assert_eq!((b.limit, b.streak, b.wait), (4, 1, BASE_BACKOFF));
+        // 同一窗口内的后续 429 不再减半
+        let b = slots.on_busy(HOST, None);
+        assert!(!b.new_window);
+        assert_eq!(b.limit, 4);
+
+        for expected in [2, 1, 1] {
+            tokio::time::advance(MAX_BACKOFF).await;
+            assert_eq!(slots.on_busy(HOST, None).limit, expected);
+        }
+        tokio::time::advance(MAX_BACKOFF).await;
+        assert_eq!(usable_permits(&slots, 8).await, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backoff_doubles_and_is_capped() {
+        let slots = HostSlots::new(4);
+        let mut waits = vec![];
+        for _ in 0..8 {
+            let b = slots.on_busy(HOST, None);
+            waits.push(b.wait.as_secs());
+            tokio::time::advance(b.wait).await;
+        }
+        assert_eq!(waits, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+
+        // Retry-After 优先，但有上限；成功一次后 streak 清零
+        let b = slots.on_busy(HOST, Some(Duration::from_secs(3600)));
+        assert_eq!((b.wait, b.streak), (MAX_RETRY_AFTER, 9));
+        tokio::time::advance(b.wait).await;
+        slots.on_success(HOST);
+        assert_eq!(slots.on_busy(HOST, None).streak, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn success_grows_limit_back_to_max() {
+        let slots = HostSlots::new(4);
+        tokio::time::advance(slots.on_busy(HOST, None).wait).await;
+        tokio::time::advance(slots.on_busy(HOST, None).wait).await;
+        assert_eq!(usable_permits(&slots, 4).await, 1);
+
+        // 每成功 limit 次加 1：1 -> 2 需要 1 次，2 -> 3 需要 2 次，3 -> 4 需要 3 次
+        slots.on_success(HOST);
+        assert_eq!(usable_permits(&slots, 4).await, 2);
+        slots.on_success(HOST);
+        assert_eq!(usable_permits(&slots, 4).await, 2);
+        slots.on_success(HOST);
+        assert_eq!(usable_permits(&slots, 4).await, 3);
+        for _ in 0..10 {
+            slots.on_success(HOST);
+        }
+        assert_eq!(usable_permits(&slots, 8).await, 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shrink_takes_effect_as_permits_are_returned() {
+        let slots = HostSlots::new(4);
+        let held: Vec<_> = futures::future::join_all((0..4).map(|_| slots.acquire(HOST))).await;
+        assert_eq!(slots.on_busy(HOST, None).limit, 2);
+        drop(held);
+        tokio::time::advance(BASE_BACKOFF).await;
+        assert_eq!(usable_permits(&slots, 4).await, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unlimited_hosts_still_back_off() {
+        let slots = HostSlots::new(0);
+        let b = slots.on_busy(HOST, None);
+        assert_eq!(b.limit, 0);
+        let t = Instant::now();
+        let _p = slots.acquire(HOST).await;
+        assert_eq!(t.elapsed(), BASE_BACKOFF);
+        assert_eq!(usable_permits(&slots, 16).await, 16);
+    }
+}
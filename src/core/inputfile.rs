@@ -0,0 +1,176 @@
+//! `download --input-file` 的清单格式（兼容 aria2 -i）：
+//!
+//! ```text
+//! # 注释
+//! https://example.com/a.iso
+//!   out=b.iso
+//!   dir=isos
+//!   header=Authorization: Bearer xxx
+//!   checksum=sha-256=...
+//! {"url": "https://example.com/c.bin", "out": "c.bin", "headers": {"Referer": "https://example.com"}}
+//! ```
+//!
+//! 不缩进的行是一个链接；其后缩进的 `key=value` 行是该链接的选项，`header` / `checksum` 可重复。
+//! 其余 key 原样放进 LinkInput.options（如 `ftp_user`）。以 `{` 开头的行按 JSON 解析。
+
+use crate::core::checksum::parse_checksum_list;
+use crate::core::model::LinkInput;
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// JSON Lines 条目
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonEntry {
+    url: String,
+    out: Option<String>,
+    dir: Option<String>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    checksum: Option<String>,
+    #[serde(default)]
+    options: HashMap<String, String>,
+}
+
+/// 解析清单；返回的 LinkInput 只含清单里写明的 headers / options，默认值由调用方合并
+pub fn parse_input_file(text: &str) -> anyhow::Result<Vec<LinkInput>> {
+    let mut out: Vec<LinkInput> = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let lineno = n + 1;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with([' ', '\t']) {
+            let input = out
+                .last_mut()
+                .with_context(|| format!("line {}: option before any link", lineno))?;
+            let (k, v) = trimmed
+                .split_once('=')
+                .with_context(|| format!("line {}: expected key=value, got {}", lineno, trimmed))?;
+            apply_option(input, k.trim(), v.trim()).with_context(|| format!("line {}", lineno))?;
+        } else if trimmed.starts_with('{') {
+            let e: JsonEntry = serde_json::from_str(trimmed).with_context(|| format!("line {}", lineno))?;
+            let mut input = LinkInput { raw: e.url, headers: e.headers, options: e.options };
+            for (k, v) in [("out", e.out), ("dir", e.dir), ("checksum", e.checksum)] {
+                if let Some(v) = v {
+                    apply_option(&mut input, k, &v).with_context(|| format!("line {}", lineno))?;
+                }
+            }
+            out.push(input);
+        } else {
+            // aria2 用 TAB 分隔同一文件的多个镜像，这里不支持
+            if trimmed.contains('\t') {
+                anyhow::bail!("line {}: multiple URIs on one line are not supported", lineno);
+            }
+            out.push(LinkInput { raw: trimmed.to_string(), headers: HashMap::new(), options: HashMap::new() });
+        }
+    }
+    Ok(out)
+}
+
+fn apply_option(input: &mut LinkInput, key: &str, value: &str) -> anyhow::Result<()> {
+    match key {
+        "header" => {
+            let (k, v) = value
+                .split_once(':')
+                .ok_or_else(|| anyhow::anyhow!("invalid header format: {}", value))?;
+            input.headers.insert(k.trim().to_string(), v.trim().to_string());
+        }
+        "checksum" => {
+            parse_checksum_list(value)?;
+            let all = match input.options.remove("checksum") {
+                Some(prev) => format!("{},{}", prev, value),
+                None => value.to_string(),
+            };
+            input.options.insert("checksum".to_string(), all);
+        }
+        _ => {
+            input.options.insert(key.to_string(), value.to_string());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MD5: &str = "md5=d41d8cd98f00b204e9800998ecf8427e";
+    const SHA1: &str = "sha-1=da39a3ee5e6b4b0d3255bfef95601890afd80709";
+
+    fn err(text: &str) -> String {
+        format!("{:#}", parse_input_file(text).unwrap_err())
+    }
+
+    #[test]
+    fn indented_options_attach_to_previous_link() {
+        let text = format!(
+            "# 注释\n\nhttps://example.com/a.iso\n  out=b.iso\n\tdir = isos\n  header=Authorization: Bearer x\n  header=Referer: https://example.com\n  checksum={}\n  checksum={}\n  ftp_user=bob\nhttps://example.com/c.bin\n",
+            MD5, SHA1
+        );
+        let inputs = parse_input_file(&text).unwrap();
+        assert_eq!(inputs.len(), 2);
+
+        let a = &inputs[0];
+        assert_eq!(a.raw, "https://example.com/a.iso");
+        assert_eq!(a.options["out"], "b.iso");
+        assert_eq!(a.options["dir"], "isos");
+        assert_eq!(a.options["ftp_user"], "bob");
+        assert_eq!(a.headers["Authorization"], "Bearer x");
+        assert_eq!(a.headers["Referer"], "https://example.com");
+        // 重复的 checksum 合并成逗号分隔的列表
+        assert_eq!(a.options["checksum"], format!("{},{}", MD5, SHA1));
+        assert_eq!(parse_checksum_list(&a.options["checksum"]).unwrap().len(), 2);
+
+        assert_eq!(inputs[1].raw, "https://example.com/c.bin");
+        assert!(inputs[1].options.is_empty() && inputs[1].headers.is_empty());
+    }
+
+    #[test]
+    fn malformed_option_lines_are_rejected() {
+        let e = err("  out=a.iso\nhttps://example.com/a.iso\n");
+        assert!(e.contains("line 1: option before any link"), "{}", e);
+        let e = err("https://example.com/a.iso\n  out a.iso\n");
+        assert!(e.contains("line 2: expected key=value"), "{}", e);
+        let e = err("https://example.com/a.iso\n  header=no-colon\n");
+        assert!(e.contains("line 2") && e.contains("invalid header format"), "{}", e);
+        let e = err("https://example.com/a.iso\n  checksum=crc32=1234\n");
+        assert!(e.contains("unsupported checksum algorithm"), "{}", e);
+    }
+
+    #[test]
+    fn json_lines_and_indented_options_merge() {
+        let text = format!(
+            "{{\"url\": \"https://example.com/c.bin\", \"out\": \"c.bin\", \"headers\": {{\"Referer\": \"r\"}}, \"checksum\": \"{}\", \"options\": {{\"ftp_pass\": \"p\"}}}}\n  checksum={}\n  header=X-Token: t\n",
+            MD5, SHA1
+        );
+        let inputs = parse_input_file(&text).unwrap();
+        assert_eq!(inputs.len(), 1);
+        let c = &inputs[0];
+        assert_eq!(c.raw, "https://example.com/c.bin");
+        assert_eq!(c.options["out"], "c.bin");
+        assert_eq!(c.options["ftp_pass"], "p");
+        assert_eq!(c.headers["Referer"], "r");
+        assert_eq!(c.headers["X-Token"], "t");
+        assert_eq!(c.options["checksum"], format!("{},{}", MD5, SHA1));
+    }
+
+    #[test]
+    fn json_lines_reject_unknown_fields_and_bad_checksums() {
+        let e = err("{\"url\": \"https://example.com/c.bin\", \"outt\": \"c.bin\"}\n");
+        assert!(e.contains("line 1") && e.contains("unknown field `outt`"), "{}", e);
+        let e = err("{\"out\": \"c.bin\"}\n");
+        assert!(e.contains("missing field `url`"), "{}", e);
+        let e = err("{\"url\": \"https://example.com/c.bin\", \"checksum\": \"md5=xyz\"}\n");
+        assert!(e.contains("invalid md5 digest"), "{}", e);
+    }
+
+    #[test]
+    fn tab_separated_mirrors_are_rejected() {
+        let e = err("https://example.com/a.iso\n\nhttps://a.example/x\thttps://b.example/x\n");
+        assert!(e.contains("line 3: multiple URIs on one line are not supported"), "{}", e);
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+/// 统计一个 item 本次运行实际传输的字节数与峰值速度（按约 1 秒的窗口计算）。
+/// 只计下载中的时间：暂停期间调用 pause()，恢复时 resume()，平均速度不被暂停拉低。
+pub struct TransferMeter {
+    bytes: AtomicU64,
+    /// (此前各段下载累计的时长, 当前这段的开始时刻；暂停中为 None)
+    active: Mutex<(Duration, Option<Instant>)>,
+    window: Mutex<(Instant, u64)>,
+    peak_bps: AtomicU64,
+}
+
+impl TransferMeter {
+    /// 新建即开始计时
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            bytes: AtomicU64::new(0),
+            active: Mutex::new((Duration::ZERO, Some(now))),
+            window: Mutex::new((now, 0)),
+            peak_bps: AtomicU64::new(0),
+        }
+    }
+
+    pub fn add(&self, n: u64) {
+        self.bytes.fetch_add(n, Ordering::Relaxed);
+        let mut w = self.window.lock().unwrap();
+        w.1 += n;
+        let elapsed = w.0.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            let bps = (w.1 as f64 / elapsed.as_secs_f64()) as u64;
+            self.peak_bps.fetch_max(bps, Ordering::Relaxed);
+            *w = (Instant::now(), 0);
+        }
+    }
+
+    /// 停止计时（暂停）；重复调用无影响
+    pub fn pause(&self) {
+        let mut a = self.active.lock().unwrap();
+        if let Some(since) = a.1.take() {
+            a.0 += since.elapsed();
+        }
+    }
+
+    /// 继续计时；峰值窗口从现在重新开始，暂停的时长不算进窗口
+    pub fn resume(&self) {
+        let mut a = self.active.lock().unwrap();
+        if a.1.is_none() {
+            let now = Instant::now();
+            a.1 = Some(now);
+            *self.window.lock().unwrap() = (now, 0);
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// 实际下载的时长（不含暂停）
+    pub fn elapsed(&self) -> Duration {
+        let a = self.active.lock().unwrap();
+        a.0 + a.1.map(|since| since.elapsed()).unwrap_or_default()
+    }
+
+    /// 下载期间的平均速度
+    pub fn avg_bps(&self) -> u64 {
+        (self.bytes() as f64 / self.elapsed().as_secs_f64().max(0.001)) as u64
+    }
+
+    /// 不足一个窗口就结束的下载没有峰值样本，取平均速度
+    pub fn peak_bps(&self) -> u64 {
+        self.peak_bps.load(Ordering::Relaxed).max(self.avg_bps())
+    }
+}
+
+impl Default for TransferMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn paused_time_is_not_counted() {
+        let m = TransferMeter::new();
+        m.add(1000);
+        tokio::time::advance(Duration::from_secs(1)).await;
+        m.pause();
+        // 暂停中：时长不涨，重复 pause 无影响
+        tokio::time::advance(Duration::from_secs(60)).await;
+        m.pause();
+        assert_eq!(m.elapsed(), Duration::from_secs(1));
+
+        m.resume();
+        m.add(1000);
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(m.elapsed(), Duration::from_secs(2));
+        assert_eq!(m.avg_bps(), 1000);
+    }
+}
@@ -2,5 +2,14 @@ pub mod model;
 pub mod events;
 pub mod planner;
 pub mod assembler;
+pub mod checksum;
+pub mod inputfile;
+pub mod hosts;
+pub mod ratelimit;
+pub mod meter;
+pub mod sources;
 pub mod engine;
-pub mod store;
\ No newline at end of file
+pub mod recovery;
+pub mod store;
+#[cfg(test)]
+pub mod testutil;
@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use uuid::Uuid;
@@ -5,23 +6,24 @@ use uuid::Uuid;
 pub type JobId = Uuid;
 pub type ItemId = Uuid;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LinkInput {
     pub raw: String,
     pub headers: HashMap<String, String>,
     pub options: HashMap<String, String>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum JobStatus {
     Pending,
     Running,
     Paused,
     Completed,
     Failed,
+    Cancelled,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ItemStatus {
     Resolving,
     Ready,
@@ -32,6 +34,31 @@ pub enum ItemStatus {
     Failed,
 }
 
+/// 任务概况（daemon / 查询接口用）
+#[derive(Debug, Clone, Serialize)]
+pub struct JobSnapshot {
+    pub job_id: JobId,
+    pub status: JobStatus,
+    pub links: Vec<String>,
+    /// 是否有链接解析失败（没有对应的 item）
+    pub resolve_failed: bool,
+    /// unix 秒
+    pub created_at: i64,
+}
+
+/// item 概况；downloaded 为已完成分片的字节数，实时进度见 Progress 事件
+#[derive(Debug, Clone, Serialize)]
+pub struct ItemSnapshot {
+    pub item_id: ItemId,
+    pub job_id: JobId,
+    pub display_name: String,
+    pub target_path: PathBuf,
+    pub uri: String,
+    pub status: ItemStatus,
+    pub downloaded: u64,
+    pub total: Option<u64>,
+}
+
 #[derive(Debug, Clone)]
 pub struct DownloadJob {
     pub id: JobId,
@@ -50,9 +77,15 @@ pub struct DownloadItem {
     pub resources: Vec<ResourceDescriptor>,
     pub options: HashMap<String, String>,
     pub fragments: Vec<Fragment>,
+    /// 期望的摘要（CLI / resolver / Metalink 提供），下载完成后校验
+    pub checksums: Vec<Checksum>,
+    /// 分块哈希列表（Metalink `<pieces>` 等），有则逐分片校验
+    pub piece_hashes: Option<PieceHashes>,
+    /// 解析出该 item 的 resolver（记入下载历史）
+    pub resolver: String,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ResourceType {
     Http,
     GitHubResolvedHttp,
@@ -63,13 +96,13 @@ pub enum ResourceType {
     Adb,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Capabilities {
     pub supports_ranges: bool,
     pub max_parallel: u32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResourceDescriptor {
     pub rtype: ResourceType,
     pub uri: String,
@@ -78,6 +111,33 @@ pub struct ResourceDescriptor {
     pub caps: Capabilities,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashAlgo {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+    Blake3,
+    /// eD2k 哈希（MD4 分块哈希），来自 ed2k:// 链接
+    Ed2k,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checksum {
+    pub algo: HashAlgo,
+    /// 小写十六进制
+    pub hex: String,
+}
+
+/// 按固定长度切分的分块哈希（最后一块可以更短）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PieceHashes {
+    pub algo: HashAlgo,
+    pub piece_len: u64,
+    /// 小写十六进制，按 piece 顺序
+    pub hashes: Vec<String>,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FragmentState {
     Missing,
@@ -99,4 +159,6 @@ pub struct Fragment {
     pub retry: u8,
 }
 
+/// 服务器不配合 Range 时的回退方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FallbackMode { Full, SeqRange }
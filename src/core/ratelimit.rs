@@ -0,0 +1,151 @@
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+
+/// 令牌桶限速器（bytes/s，0 表示不限速），速率可在运行中调整。
+/// 允许透支：并发的 acquire 各自记账后按欠额睡眠，总体速率仍收敛到设定值。
+pub struct RateLimiter {
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    rate: u64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        // 桶容量 = 1 秒的量，空闲后最多突发这么多
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+    }
+}
+
+impl RateLimiter {
+    pub fn new(rate: u64) -> Self {
+        Self { bucket: Mutex::new(Bucket { rate, tokens: rate as f64, last: Instant::now() }) }
+    }
+
+    pub fn set_rate(&self, rate: u64) {
+        let mut b = self.bucket.lock().unwrap();
+        b.refill();
+        b.rate = rate;
+        b.tokens = b.tokens.min(rate as f64);
+    }
+
+    #[cfg(test)]
+    pub fn rate(&self) -> u64 {
+        self.bucket.lock().unwrap().rate
+    }
+
+    pub async fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut b = self.bucket.lock().unwrap();
+            if b.rate == 0 {
+                return;
+            }
+            b.refill();
+            b.tokens -= bytes as f64;
+            if b.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-b.tokens / b.rate as f64)
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// 依次向每一级（全局 / 主机 / item）申请额度
+pub async fn acquire_all(limiters: &[Arc<RateLimiter>], bytes: u64) {
+    for l in limiters {
+        l.acquire(bytes).await;
+    }
+}
+
+/// 解析速率：`500K`、`2M`、`1.5G`、纯数字为 bytes/s；`0` 表示不限速
+pub fn parse_rate(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let (num, mul) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => {
+            let mul = match c.to_ascii_uppercase() {
+                'K' => 1024.0,
+                'M' => 1024.0 * 1024.0,
+                'G' => 1024.0 * 1024.0 * 1024.0,
+                _ => anyhow::bail!("invalid rate unit in {:?} (use K, M or G)", s),
+            };
+            (&s[..i], mul)
+        }
+        _ => (s, 1.0),
+    };
+    let v: f64 = num.trim().parse().map_err(|_| anyhow::anyhow!("invalid rate: {:?}", s))?;
+    if !v.is_finite() || v < 0.0 {
+        anyhow::bail!("invalid rate: {:?}", s);
+    }
+    Ok((v * mul) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// acquire 实际等了多久（测试用暂停的时钟，sleep 会自动快进）
+    async fn timed_acquire(l: &RateLimiter, bytes: u64) -> Duration {
+        let t = Instant::now();
+        l.acquire(bytes).await;
+        t.elapsed()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn full_bucket_allows_one_second_burst() {
+        let l = RateLimiter::new(1000);
+        assert_eq!(timed_acquire(&l, 1000).await, Duration::ZERO);
+        // 桶空了：再要 500 需要等 0.5 秒
+        assert_eq!(timed_acquire(&l, 500).await, Duration::from_millis(500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refill_is_capped_at_one_second() {
+        let l = RateLimiter::new(1000);
+        l.acquire(1000).await;
+        // 空闲 10 秒也只攒下 1 秒的量
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(timed_acquire(&l, 1000).await, Duration::ZERO);
+        assert_eq!(timed_acquire(&l, 1000).await, Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn overdraft_is_paid_back_before_next_acquire() {
+        let l = RateLimiter::new(1000);
+        // 透支 2000：等 2 秒，之后桶里是 0
+        assert_eq!(timed_acquire(&l, 3000).await, Duration::from_secs(2));
+        assert_eq!(timed_acquire(&l, 100).await, Duration::from_millis(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_rate_never_waits_and_rate_can_change() {
+        let l = RateLimiter::new(0);
+        assert_eq!(timed_acquire(&l, u32::MAX as u64).await, Duration::ZERO);
+
+        l.set_rate(100);
+        // 从不限速切过来时桶是空的
+        assert_eq!(timed_acquire(&l, 100).await, Duration::from_secs(1));
+        l.set_rate(0);
+        assert_eq!(timed_acquire(&l, 1_000_000).await, Duration::ZERO);
+    }
+
+    #[test]
+    fn parse_rate_units() {
+        assert_eq!(parse_rate("0").unwrap(), 0);
+        assert_eq!(parse_rate(" 1500 ").unwrap(), 1500);
+        assert_eq!(parse_rate("500K").unwrap(), 500 * 1024);
+        assert_eq!(parse_rate("2m").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(parse_rate("1.5G").unwrap(), 3 * 512 * 1024 * 1024);
+        assert!(parse_rate("").is_err());
+        assert!(parse_rate("10X").is_err());
+        assert!(parse_rate("-1K").is_err());
+        assert!(parse_rate("K").is_err());
+        assert!(parse_rate("inf").is_err());
+    }
+}
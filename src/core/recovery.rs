@@ -0,0 +1,255 @@
+//! 崩溃恢复：核对 `.partial` 与分片表是否一致，并在有哈希时复查已完成的分片。
+//! 不一致的分片回退为 Missing，下次下载时重新获取。
+
+use crate::core::checksum::{Hasher, PieceVerifier};
+use crate::core::model::{Checksum, FragmentState, PieceHashes};
+use crate::core::store::SqliteStore;
+use anyhow::Context;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VerifyReport {
+    /// .partial 缺失或长度不足、数据不在文件里的 Done 分片
+    pub missing: usize,
+    /// 按分片哈希或 piece 列表复查过的 Done 分片
+    pub checked: usize,
+    /// 复查不通过的分片
+    pub bad: usize,
+    /// 没有任何哈希可供复查的 Done 分片
+    pub unverified: usize,
+}
+
+/// Done 分片的数据必须在 .partial 里：文件不存在时全部回退，文件比分片末尾短时回退越界的分片；
+/// 整流下载（len = 0）的分片要求文件长度等于已下载字节数。返回回退的分片数。
+/// 只看长度，预分配（稀疏）文件里没写进去的数据要靠 verify_partial 的哈希复查
+pub async fn check_partial(store: &SqliteStore, item_db_id: i64, partial_path: &Path) -> anyhow::Result<usize> {
+    let Some(item) = store.load_item_progress(Some(item_db_id)).await?.into_iter().next() else {
+        return Ok(0);
+    };
+    let partial_len = tokio::fs::metadata(partial_path).await.ok().map(|m| m.len() as i64);
+
+    let stale: Vec<i64> = store
+        .load_fragments(item_db_id)
+        .await?
+        .into_iter()
+        .filter(|f| f.state == FragmentState::Done)
+        .filter(|f| match partial_len {
+            None => true,
+            Some(l) if f.len > 0 => f.offset + f.len > l,
+            Some(l) => l != item.downloaded_bytes,
+        })
+        .map(|f| f.frag_db_id)
+        .collect();
+    store.reset_fragments(item_db_id, &stale).await?;
+    Ok(stale.len())
+}
+
+/// 先 check_partial，再用分片哈希（--fragment-hash 记录的）或 piece 列表重新计算每个 Done 分片，
+/// 不匹配的回退为 Missing
+pub async fn verify_partial(
+    store: &SqliteStore,
+    item_db_id: i64,
+    partial_path: &Path,
+    pieces: Option<&PieceHashes>,
+    total_size: Option<u64>,
+) -> anyhow::Result<VerifyReport> {
+    let mut report = VerifyReport { missing: check_partial(store, item_db_id, partial_path).await?, ..Default::default() };
+
+    let mut bad = vec![];
+    for f in store.load_fragments(item_db_id).await? {
+        if f.state != FragmentState::Done {
+            continue;
+        }
+        // piece 校验需要知道总长（最后一个 piece 可能较短）
+        let pieces = match (pieces, total_size) {
+            (Some(p), Some(t)) if p.piece_len > 0 && f.len > 0 => Some((p.clone(), t)),
+            _ => None,
+        };
+        if f.hash.is_none() && pieces.is_none() {
+            report.unverified += 1;
+            continue;
+        }
+
+        let path: PathBuf = partial_path.to_path_buf();
+        let (offset, len, hash) = (f.offset as u64, f.len as u64, f.hash.clone());
+        let ok = tokio::task::spawn_blocking(move || range_matches(&path, offset, len, hash.as_ref(), pieces.as_ref()))
+            .await
+            .context("verify task panicked")??;
+        report.checked += 1;
+        if !ok {
+            bad.push(f.frag_db_id);
+        }
+    }
+    report.bad = bad.len();
+    store.reset_fragments(item_db_id, &bad).await?;
+    Ok(report)
+}
+
+/// 读 [offset, offset+len)（len = 0 时读到文件末尾）并与分片哈希 / piece 哈希比对
+fn range_matches(
+    path: &Path,
+    offset: u64,
+    len: u64,
+    hash: Option<&Checksum>,
+    pieces: Option<&(PieceHashes, u64)>,
+) -> anyhow::Result<bool> {
+    let mut file = std::fs::File::open(path).with_context(|| format!("open {:?} for verification", path))?;
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut hasher = hash.map(|c| Hasher::new(c.algo));
+    let mut pv = pieces.map(|(p, total)| PieceVerifier::new(p, *total, offset));
+    let mut remaining = if len > 0 { Some(len) } else { None };
+
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let want = remaining.map(|r| (r as usize).min(buf.len())).unwrap_or(buf.len());
+        if want == 0 {
+            break;
+        }
+        let n = file.read(&mut buf[..want]).with_context(|| format!("read {:?}", path))?;
+        if n == 0 {
+            break;
+        }
+        if let Some(h) = hasher.as_mut() {
+            h.update(&buf[..n]);
+        }
+        if let Some(v) = pv.as_mut() {
+            if v.update(&buf[..n]).is_err() {
+                return Ok(false);
+            }
+        }
+        if let Some(r) = remaining.as_mut() {
+            *r -= n as u64;
+        }
+    }
+
+    // 文件在分片中途结束
+    if remaining.is_some_and(|r| r > 0) {
+        return Ok(false);
+    }
+    Ok(match (hasher, hash) {
+        (Some(h), Some(c)) => h.finalize_hex() == c.hex,
+        _ => true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::model::HashAlgo;
+
+    fn sha1(data: &[u8]) -> String {
+        let mut h = Hasher::new(HashAlgo::Sha1);
+        h.update(data);
+        h.finalize_hex()
+    }
+
+    /// 10 字节的 item，分片 [0,4) [4,8) [8,10) 全部完成；with_hash 时记录每个分片的 sha-1
+    async fn done_item(dir: &Path, data: &[u8], with_hash: bool) -> (SqliteStore, i64, PathBuf) {
+        let store = SqliteStore::open(&dir.join("state.db")).await.unwrap();
+        let target = dir.join("x.bin");
+        let partial = dir.join("x.partial");
+        let rec = store.upsert_item("mock://host/x.bin", &target, &partial, 4, Some(10), true).await.unwrap();
+        store.ensure_fragments_for_ranges(rec.item_db_id, &[(0, 4), (4, 4), (8, 2)]).await.unwrap();
+        for f in store.load_fragments(rec.item_db_id).await.unwrap() {
+            let range = &data[f.offset as usize..(f.offset + f.len) as usize];
+            let hash = with_hash.then(|| Checksum::new(HashAlgo::Sha1, &sha1(range)).unwrap());
+            store.mark_fragment_done_and_add_bytes(f.frag_db_id, rec.item_db_id, f.len, hash.as_ref()).await.unwrap();
+        }
+        std::fs::write(&partial, data).unwrap();
+        (store, rec.item_db_id, partial)
+    }
+
+    /// 各分片 (offset, 是否 Done)，按 offset 排序
+    async fn states(store: &SqliteStore, item_db_id: i64) -> Vec<(i64, bool)> {
+        let mut v: Vec<(i64, bool)> = store
+            .load_fragments(item_db_id)
+            .await
+            .unwrap()
+            .iter()
+            .map(|f| (f.offset, f.state == FragmentState::Done))
+            .collect();
+        v.sort();
+        v
+    }
+
+    #[tokio::test]
+    async fn check_partial_resets_fragments_past_file_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, item_db_id, partial) = done_item(dir.path(), b"0123456789", false).await;
+        assert_eq!(check_partial(&store, item_db_id, &partial).await.unwrap(), 0);
+
+        // 文件被截短到 6 字节：[4,8) 和 [8,10) 的数据不在文件里
+        std::fs::write(&partial, b"012345").unwrap();
+        assert_eq!(check_partial(&store, item_db_id, &partial).await.unwrap(), 2);
+        assert_eq!(states(&store, item_db_id).await, [(0, true), (4, false), (8, false)]);
+        let progress = store.load_item_progress(Some(item_db_id)).await.unwrap();
+        assert_eq!(progress[0].downloaded_bytes, 4);
+    }
+
+    #[tokio::test]
+    async fn check_partial_resets_everything_without_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, item_db_id, partial) = done_item(dir.path(), b"0123456789", false).await;
+        std::fs::remove_file(&partial).unwrap();
+        assert_eq!(check_partial(&store, item_db_id, &partial).await.unwrap(), 3);
+        assert_eq!(states(&store, item_db_id).await, [(0, false), (4, false), (8, false)]);
+        // 没有这个 item 时什么也不做
+        assert_eq!(check_partial(&store, item_db_id + 1, &partial).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn verify_partial_resets_fragments_with_wrong_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, item_db_id, partial) = done_item(dir.path(), b"0123456789", true).await;
+        let r = verify_partial(&store, item_db_id, &partial, None, Some(10)).await.unwrap();
+        assert_eq!((r.missing, r.checked, r.bad, r.unverified), (0, 3, 0, 0));
+
+        // 长度没变但 [4,8) 的内容坏了
+        std::fs::write(&partial, b"0123x56789").unwrap();
+        let r = verify_partial(&store, item_db_id, &partial, None, Some(10)).await.unwrap();
+        assert_eq!((r.missing, r.checked, r.bad, r.unverified), (0, 3, 1, 0));
+        assert_eq!(states(&store, item_db_id).await, [(0, true), (4, false), (8, true)]);
+    }
+
+    #[tokio::test]
+    async fn verify_partial_uses_pieces_or_reports_unverified() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = b"0123456789";
+        let (store, item_db_id, partial) = done_item(dir.path(), data, false).await;
+        std::fs::write(&partial, b"01234567x9").unwrap();
+
+        // 既没有分片哈希也没有 piece 列表：只能报告未校验
+        let r = verify_partial(&store, item_db_id, &partial, None, Some(10)).await.unwrap();
+        assert_eq!((r.checked, r.bad, r.unverified), (0, 0, 3));
+
+        let pieces = PieceHashes { algo: HashAlgo::Sha1, piece_len: 4, hashes: data.chunks(4).map(sha1).collect() };
+        let r = verify_partial(&store, item_db_id, &partial, Some(&pieces), Some(10)).await.unwrap();
+        assert_eq!((r.checked, r.bad, r.unverified), (3, 1, 0));
+        assert_eq!(states(&store, item_db_id).await, [(0, true), (4, true), (8, false)]);
+    }
+
+    #[test]
+    fn range_matches_pieces_and_fragment_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("x.partial");
+        let data = b"0123456789";
+        std::fs::write(&path, data).unwrap();
+        let pieces = (PieceHashes { algo: HashAlgo::Sha1, piece_len: 4, hashes: data.chunks(4).map(sha1).collect() }, 10);
+
+        // 对齐的分片、从 piece 中间开始的分片、含较短最后 piece 的分片
+        assert!(range_matches(&path, 0, 4, None, Some(&pieces)).unwrap());
+        assert!(range_matches(&path, 6, 4, None, Some(&pieces)).unwrap());
+        assert!(range_matches(&path, 8, 2, None, Some(&pieces)).unwrap());
+        let hash = Checksum::new(HashAlgo::Sha1, &sha1(&data[2..7])).unwrap();
+        assert!(range_matches(&path, 2, 5, Some(&hash), None).unwrap());
+        assert!(!range_matches(&path, 3, 5, Some(&hash), None).unwrap());
+        // 文件在分片中途结束
+        assert!(!range_matches(&path, 8, 4, None, None).unwrap());
+
+        std::fs::write(&path, b"0123456x89").unwrap();
+        assert!(!range_matches(&path, 4, 4, None, Some(&pieces)).unwrap());
+        assert!(range_matches(&path, 8, 2, None, Some(&pieces)).unwrap());
+    }
+}
@@ -0,0 +1,252 @@
+use crate::core::model::ResourceDescriptor;
+use crate::plugins::registry::TransferDriver;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 连续失败达到该次数的镜像被降级（不再分配新分片）
+const DEMOTE_AFTER_FAILURES: u32 = 3;
+
+/// 同一 item 的一个下载来源（主资源或镜像）
+#[derive(Clone)]
+pub struct Source {
+    pub res: ResourceDescriptor,
+    pub driver: Arc<dyn TransferDriver>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SourceStats {
+    pub bytes: u64,
+    pub busy: Duration,
+    pub successes: u32,
+    pub failures: u32,
+    pub consecutive_failures: u32,
+    pub in_flight: u32,
+    pub demoted: bool,
+}
+
+impl SourceStats {
+    /// 只统计成功分片的吞吐（bytes/s）
+    pub fn throughput(&self) -> f64 {
+        let secs = self.busy.as_secs_f64();
+        if secs <= 0.0 {
+            return 0.0;
+        }
+        self.bytes as f64 / secs
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        let attempts = self.successes + self.failures;
+        if attempts == 0 {
+            return 0.0;
+        }
+        self.failures as f64 / attempts as f64
+    }
+}
+
+/// 多来源调度：按吞吐和在途分片数给分片挑来源，失败多的镜像降级
+pub struct SourcePool {
+    sources: Vec<Source>,
+    stats: Mutex<Vec<SourceStats>>,
+}
+
+impl SourcePool {
+    pub fn new(sources: Vec<Source>) -> Self {
+        let stats = vec![SourceStats::default(); sources.len()];
+        Self { sources, stats: Mutex::new(stats) }
+    }
+
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    pub fn get(&self, idx: usize) -> &Source {
+        &self.sources[idx]
+    }
+
+    /// 挑一个来源并计入在途数。
+    /// 还没有成功记录的来源优先（先让每个镜像都跑一次），之后按 吞吐 / (在途+1) 取最大。
+    pub fn acquire(&self) -> usize {
+        let mut stats = self.stats.lock().unwrap();
+        let score = |s: &SourceStats| {
+            if s.successes == 0 {
+                f64::MAX / (s.in_flight as f64 + 1.0)
+            } else {
+                s.throughput() / (s.in_flight as f64 + 1.0)
+            }
+        };
+
+        let idx = stats
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !s.demoted)
+            .max_by(|(_, a), (_, b)| score(a).total_cmp(&score(b)))
+            .map(|(i, _)| i)
+            .unwrap_or(0);
+        stats[idx].in_flight += 1;
+        idx
+    }
+
+    pub fn record_success(&self, idx: usize, bytes: u64, elapsed: Duration) {
+        let mut stats = self.stats.lock().unwrap();
+        let s = &mut stats[idx];
+        s.in_flight = s.in_flight.saturating_sub(1);
+        s.bytes += bytes;
+        s.busy += elapsed;
+        s.successes += 1;
+        s.consecutive_failures = 0;
+    }
+
+    /// 记录失败；返回 true 表示该来源刚被降级。最后一个可用来源不会被降级。
+    pub fn record_failure(&self, idx: usize) -> bool {
+        let mut stats = self.stats.lock().unwrap();
+        let active = stats.iter().filter(|s| !s.demoted).count();
+        let s = &mut stats[idx];
+        s.in_flight = s.in_flight.saturating_sub(1);
+        s.failures += 1;
+        s.consecutive_failures += 1;
+
+        if !s.demoted && active > 1 && s.consecutive_failures >= DEMOTE_AFTER_FAILURES {
+            s.demoted = true;
+            return true;
+        }
+        false
+    }
+
+    /// 立即降级（例如镜像忽略 Range 或内容已变化）；返回 false 表示它是最后一个可用来源
+    pub fn demote(&self, idx: usize) -> bool {
+        let mut stats = self.stats.lock().unwrap();
+        let active = stats.iter().filter(|s| !s.demoted).count();
+        if stats[idx].demoted {
+            return true;
+        }
+        if active <= 1 {
+            return false;
+        }
+        stats[idx].demoted = true;
+        true
+    }
+
+    /// 分片没有真正下载（例如主机要求退避）：只归还在途计数，不计成败
+    pub fn release(&self, idx: usize) {
+        let mut stats = self.stats.lock().unwrap();
+        stats[idx].in_flight = stats[idx].in_flight.saturating_sub(1);
+    }
+
+    /// 在途分片被丢弃（暂停/取消/回退）时调用
+    pub fn release_all(&self) {
+        for s in self.stats.lock().unwrap().iter_mut() {
+            s.in_flight = 0;
+        }
+    }
+
+    pub fn stats(&self) -> Vec<SourceStats> {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::model::{Capabilities, ResourceType};
+    use crate::plugins::registry::{ByteStream, DriverContext};
+    use async_trait::async_trait;
+    use std::collections::HashMap;
+
+    struct NullDriver;
+
+    #[async_trait]
+    impl TransferDriver for NullDriver {
+        fn name(&self) -> &'static str {
+            "null"
+        }
+
+        fn supports(&self, _res: &ResourceDescriptor) -> bool {
+            true
+        }
+
+        async fn download_range(&self, _: &ResourceDescriptor, _: &DriverContext, _: u64, _: u64) -> anyhow::Result<ByteStream> {
+            anyhow::bail!("not used")
+        }
+
+        async fn download_all(&self, _: &ResourceDescriptor, _: &DriverContext) -> anyhow::Result<ByteStream> {
+            anyhow::bail!("not used")
+        }
+    }
+
+    fn pool(n: usize) -> SourcePool {
+        let sources = (0..n)
+            .map(|i| Source {
+                res: ResourceDescriptor {
+                    rtype: ResourceType::Http,
+                    uri: format!("http://mirror{}.test/f", i),
+                    headers: HashMap::new(),
+                    meta: HashMap::new(),
+                    caps: Capabilities::default(),
+                },
+                driver: Arc::new(NullDriver),
+            })
+            .collect();
+        SourcePool::new(sources)
+    }
+
+    #[test]
+    fn untried_sources_are_picked_first() {
+        let p = pool(3);
+        let mut picked: Vec<usize> = (0..3).map(|_| p.acquire()).collect();
+        picked.sort();
+        assert_eq!(picked, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn faster_source_wins_once_all_are_measured() {
+        let p = pool(2);
+        let (a, b) = (p.acquire(), p.acquire());
+        p.record_success(a, 1_000_000, Duration::from_secs(1));
+        p.record_success(b, 4_500_000, Duration::from_secs(1));
+        assert_eq!(p.acquire(), b);
+        // 在途分片摊薄得分：快镜像已有 4 个在途时轮到慢镜像
+        p.acquire();
+        p.acquire();
+        p.acquire();
+        assert_eq!(p.acquire(), a);
+    }
+
+    #[test]
+    fn mirror_is_demoted_after_consecutive_failures() {
+        let p = pool(2);
+        assert!(!p.record_failure(1));
+        assert!(!p.record_failure(1));
+        // 成功一次清零连续失败
+        p.record_success(1, 10, Duration::from_millis(10));
+        assert!(!p.record_failure(1));
+        assert!(!p.record_failure(1));
+        assert!(p.record_failure(1));
+        assert!(p.stats()[1].demoted);
+
+        // 之后所有分片都落到剩下的来源上
+        for _ in 0..10 {
+            assert_eq!(p.acquire(), 0);
+        }
+    }
+
+    #[test]
+    fn last_active_source_is_never_demoted() {
+        let p = pool(2);
+        assert!(p.demote(0));
+        assert!(!p.demote(1));
+        for _ in 0..DEMOTE_AFTER_FAILURES * 2 {
+            assert!(!p.record_failure(1));
+        }
+        assert!(!p.stats()[1].demoted);
+        assert_eq!(p.acquire(), 1);
+    }
+
+    #[test]
+    fn release_does_not_count_as_failure() {
+        let p = pool(2);
+        let idx = p.acquire();
+        p.release(idx);
+        let s = &p.stats()[idx];
+        assert_eq!((s.in_flight, s.failures, s.successes), (0, 0, 0));
+    }
+}
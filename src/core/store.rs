@@ -1,7 +1,23 @@
-use crate::core::model::FragmentState;
+use crate::core::model::{
+    Checksum, DownloadItem, FragmentState, HashAlgo, ItemId, ItemStatus, JobId, JobStatus, LinkInput, PieceHashes,
+    ResourceDescriptor,
+};
 use anyhow::Context;
-use sqlx::{Row, SqlitePool};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqliteSynchronous};
+use sqlx::pool::PoolConnection;
+use sqlx::{Row, Sqlite, SqlitePool};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// 续传库在 out_dir 下的文件名
+pub const DB_FILE_NAME: &str = ".downloader.sqlite";
+
+/// 运行任务的进程刷新 jobs.owner_seen_at 的间隔
+pub const OWNER_HEARTBEAT_SECS: u64 = 10;
+/// owner_seen_at 超过这么久没有刷新，视为该进程已退出，任务可以被接管
+const OWNER_STALE_SECS: i64 = 3 * OWNER_HEARTBEAT_SECS as i64;
 
 #[derive(Clone)]
 pub struct SqliteStore {
@@ -13,6 +29,8 @@ pub struct ItemRecord {
     pub item_db_id: i64,
     pub downloaded_bytes: i64,
     pub total_size: Option<i64>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -21,8 +39,115 @@ pub struct FragmentRecord {
     pub offset: i64,
     pub len: i64,
     pub state: FragmentState,
+    pub retry: i64,
+    /// 完成时计算的分片哈希（--fragment-hash 或按之前记录的算法）
+    pub hash: Option<Checksum>,
+}
+
+/// 持久化的任务：原始输入，以及是否已经解析出 item
+#[derive(Debug, Clone)]
+pub struct JobRecord {
+    pub job_id: JobId,
+    pub status: JobStatus,
+    pub inputs: Vec<LinkInput>,
+    pub resolved: bool,
+    /// 解析阶段有输入失败（任务最终状态为 Failed）
+    pub resolve_failed: bool,
+    pub created_at: i64,
+}
+
+/// 一个 item 的一次运行（从开始下载到完成 / 失败 / 取消）
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryRecord {
+    pub job_id: JobId,
+    pub item_id: ItemId,
+    pub source_uri: String,
+    /// 小写主机名（不含端口）
+    pub host: Option<String>,
+    pub target_path: PathBuf,
+    pub resolver: Option<String>,
+    pub driver: Option<String>,
+    /// done / failed / cancelled
+    pub status: String,
+    pub error: Option<String>,
+    pub total_size: Option<i64>,
+    pub bytes: i64,
+    pub avg_speed: i64,
+    pub peak_speed: i64,
+    pub started_at: i64,
+    pub finished_at: i64,
+}
+
+/// history 查询条件；时间为 unix 秒，区间为 [since, until)
+#[derive(Debug, Clone, Default)]
+pub struct HistoryFilter {
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub host: Option<String>,
+    pub status: Option<String>,
+}
+
+/// 按天（UTC）或主机汇总的 history
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryTotal {
+    pub key: String,
+    pub runs: i64,
+    pub failed: i64,
+    pub bytes: i64,
+}
+
+/// 解析得到的 item（resolver 结果 + 输入的 options/校验信息），恢复时不必重新解析
+#[derive(Debug, Clone)]
+pub struct JobItemRecord {
+    pub item: DownloadItem,
+    pub status: ItemStatus,
+}
+
+/// items 表的一行及其分片统计（list / status 子命令用）
+#[derive(Debug, Clone)]
+pub struct ItemProgress {
+    pub item_db_id: i64,
+    pub source_uri: String,
+    pub target_path: PathBuf,
+    pub partial_path: PathBuf,
+    pub total_size: Option<i64>,
+    pub downloaded_bytes: i64,
+    pub supports_ranges: bool,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub fragments: i64,
+    pub fragments_done: i64,
+    pub fragments_downloading: i64,
+    pub fragments_bad: i64,
+    pub retries: i64,
+    pub updated_at: i64,
+}
+
+impl ItemProgress {
+    /// 分片全部完成（有分片计划时）
+    pub fn is_complete(&self) -> bool {
+        self.fragments > 0 && self.fragments_done == self.fragments
+    }
 }
 
+/// job_items.draft 列的 JSON 结构
+#[derive(Serialize, Deserialize)]
+struct StoredDraft {
+    display_name: String,
+    target_path: PathBuf,
+    total_size: Option<u64>,
+    resources: Vec<ResourceDescriptor>,
+    options: HashMap<String, String>,
+    checksums: Vec<Checksum>,
+    piece_hashes: Option<PieceHashes>,
+    /// 较早的库里没有这一项
+    #[serde(default)]
+    resolver: String,
+}
+
+/// 一条 INSERT 写入的分片行数（每行 5 个参数，远低于 SQLite 的参数上限）
+const FRAGMENT_INSERT_BATCH: usize = 500;
+
 impl SqliteStore {
     pub async fn open(db_path: &Path) -> anyhow::Result<Self> {
         use anyhow::Context;
@@ -54,81 +179,624 @@ impl SqliteStore {
             format!("sqlite:///{}?mode=rwc", p)
         };
 
+        // WAL：下载中频繁的小事务不再阻塞 list / status 等读者；
+        // synchronous=NORMAL 在断电时最多丢掉最后几个事务，打开时会按分片重算进度
+        let opts = SqliteConnectOptions::from_str(&url)
+            .with_context(|| format!("sqlite url {}", url))?
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(std::time::Duration::from_secs(10));
+
         let pool = sqlx::sqlite::SqlitePoolOptions::new()
             .max_connections(5)
-            .connect(&url)
+            .connect_with(opts)
             .await
             .with_context(|| format!("connect sqlite url={} (file={})", url, abs.display()))?;
 
         let store = Self { pool };
         store.migrate().await?;
+        Self::recompute_downloaded_bytes(&mut *store.pool.acquire().await?, None).await?;
         Ok(store)
     }
 
+    /// 以 Done 分片为准重算 downloaded_bytes（item_db_id 为 None 时重算全部 item；
+    /// 早期版本的两步更新可能在崩溃时不一致）。整流下载的分片没有长度（len = 0），这类 item 保留原值
+    async fn recompute_downloaded_bytes(conn: &mut SqliteConnection, item_db_id: Option<i64>) -> anyhow::Result<()> {
+        let filter = if item_db_id.is_some() { "WHERE id = ?" } else { "" };
+        let sql = format!(
+            r#"
+            UPDATE items
+            SET downloaded_bytes = (
+              SELECT CASE WHEN SUM(f.len = 0) > 0 THEN items.downloaded_bytes
+                          ELSE COALESCE(SUM(f.len), 0) END
+              FROM fragments f
+              WHERE f.item_id = items.id AND f.state = ?
+            )
+            {};
+            "#,
+            filter
+        );
+        let mut q = sqlx::query(&sql).bind(state_to_int(FragmentState::Done));
+        if let Some(id) = item_db_id {
+            q = q.bind(id);
+        }
+        q.execute(conn).await.context("recompute downloaded_bytes")?;
+        Ok(())
+    }
 
-
+    /// 按版本号依次执行尚未应用的迁移；库的版本比本程序新时拒绝打开。
+    /// 读版本与迁移在同一个 BEGIN IMMEDIATE 事务里：同时打开库的进程（daemon 与 CLI）在此排队，
+    /// 后来者读到的已是迁移后的版本
     async fn migrate(&self) -> anyhow::Result<()> {
+        let mut conn = self.begin_immediate().await?;
+        let r = Self::apply_migrations(&mut conn).await;
+        Self::finish(conn, r).await
+    }
+
+    /// 开一个 BEGIN IMMEDIATE 事务：一开始就拿写锁。
+    /// 先读后写的事务用普通 BEGIN 时，读完后别的连接先提交了写入，升级写锁会直接返回
+    /// SQLITE_BUSY（busy_timeout 不会重试），并发下载多个 item 时就会遇到。
+    async fn begin_immediate(&self) -> anyhow::Result<PoolConnection<Sqlite>> {
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut *conn).await?;
+        Ok(conn)
+    }
+
+    /// 按 r 提交或回滚 begin_immediate 开的事务
+    async fn finish<T>(mut conn: PoolConnection<Sqlite>, r: anyhow::Result<T>) -> anyhow::Result<T> {
+        let end = if r.is_ok() { "COMMIT" } else { "ROLLBACK" };
+        if let Err(e) = sqlx::query(end).execute(&mut *conn).await {
+            // 事务状态不明的连接不放回连接池
+            drop(conn.detach());
+            return Err(r.err().unwrap_or_else(|| e.into()));
+        }
+        r
+    }
+
+    async fn apply_migrations(conn: &mut SqliteConnection) -> anyhow::Result<()> {
         sqlx::query(
             r#"
-            CREATE TABLE IF NOT EXISTS items (
-              id INTEGER PRIMARY KEY AUTOINCREMENT,
-              source_uri TEXT NOT NULL,
-              target_path TEXT NOT NULL,
-              partial_path TEXT NOT NULL,
-              total_size INTEGER NULL,
-              chunk_size INTEGER NOT NULL,
-              supports_ranges INTEGER NOT NULL,
-              downloaded_bytes INTEGER NOT NULL DEFAULT 0,
-              updated_at INTEGER NOT NULL
+            CREATE TABLE IF NOT EXISTS schema_version (
+              version INTEGER PRIMARY KEY,
+              description TEXT NOT NULL,
+              applied_at INTEGER NOT NULL
             );
             "#,
         )
-            .execute(&self.pool)
+            .execute(&mut *conn)
+            .await?;
+
+        let latest = MIGRATIONS.last().map(|m| m.version).unwrap_or(0);
+        let current = Self::schema_version(&mut *conn).await?;
+        if current > latest {
+            return Err(SchemaTooNew { found: current, supported: latest }.into());
+        }
+
+        for m in MIGRATIONS.iter().filter(|m| m.version > current) {
+            sqlx::raw_sql(m.sql)
+                .execute(&mut *conn)
+                .await
+                .with_context(|| format!("migration {} ({})", m.version, m.description))?;
+            for (table, column, decl) in m.add_columns {
+                Self::ensure_column(&mut *conn, table, column, decl).await?;
+            }
+            sqlx::query("INSERT INTO schema_version(version, description, applied_at) VALUES(?, ?, ?)")
+                .bind(m.version)
+                .bind(m.description)
+                .bind(Self::now_epoch())
+                .execute(&mut *conn)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// 已应用的最高迁移版本；新库为 0
+    async fn schema_version(conn: &mut SqliteConnection) -> anyhow::Result<i64> {
+        let row = sqlx::query("SELECT COALESCE(MAX(version), 0) AS v FROM schema_version")
+            .fetch_one(conn)
+            .await?;
+        Ok(row.get::<i64, _>("v"))
+    }
+
+    /// 表里没有该列时补上（CREATE TABLE IF NOT EXISTS 不会修改已有表）
+    async fn ensure_column(conn: &mut SqliteConnection, table: &str, column: &str, decl: &str) -> anyhow::Result<()> {
+        let rows = sqlx::query(&format!("PRAGMA table_info({})", table))
+            .fetch_all(&mut *conn)
             .await?;
+        if rows.iter().any(|r| r.get::<String, _>("name") == column) {
+            return Ok(());
+        }
+
+        sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl))
+            .execute(conn)
+            .await
+            .with_context(|| format!("add column {}.{}", table, column))?;
+        Ok(())
+    }
 
+    pub async fn insert_history(&self, h: &HistoryRecord) -> anyhow::Result<()> {
         sqlx::query(
             r#"
-            CREATE UNIQUE INDEX IF NOT EXISTS idx_items_unique
-            ON items(source_uri, target_path);
+            INSERT INTO history(job_id, item_id, source_uri, host, target_path, resolver, driver, status, error,
+                                total_size, bytes, avg_speed, peak_speed, started_at, finished_at)
+            VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);
             "#,
         )
+            .bind(h.job_id.to_string())
+            .bind(h.item_id.to_string())
+            .bind(&h.source_uri)
+            .bind(&h.host)
+            .bind(h.target_path.to_string_lossy().to_string())
+            .bind(&h.resolver)
+            .bind(&h.driver)
+            .bind(&h.status)
+            .bind(&h.error)
+            .bind(h.total_size)
+            .bind(h.bytes)
+            .bind(h.avg_speed)
+            .bind(h.peak_speed)
+            .bind(h.started_at)
+            .bind(h.finished_at)
             .execute(&self.pool)
             .await?;
+        Ok(())
+    }
+
+    /// 条件拼成 WHERE 子句；按出现顺序绑定 filter_binds 的结果
+    fn history_where(f: &HistoryFilter) -> String {
+        let mut conds = vec!["1 = 1"];
+        if f.since.is_some() {
+            conds.push("finished_at >= ?");
+        }
+        if f.until.is_some() {
+            conds.push("finished_at < ?");
+        }
+        if f.host.is_some() {
+            conds.push("host = ?");
+        }
+        if f.status.is_some() {
+            conds.push("status = ?");
+        }
+        format!("WHERE {}", conds.join(" AND "))
+    }
+
+    fn filter_binds<'q>(
+        mut q: sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>>,
+        f: &'q HistoryFilter,
+    ) -> sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>> {
+        if let Some(v) = f.since {
+            q = q.bind(v);
+        }
+        if let Some(v) = f.until {
+            q = q.bind(v);
+        }
+        if let Some(v) = &f.host {
+            q = q.bind(v.to_ascii_lowercase());
+        }
+        if let Some(v) = &f.status {
+            q = q.bind(v);
+        }
+        q
+    }
+
+    /// 最近的记录在前；limit 为 None 时不限条数
+    pub async fn load_history(&self, f: &HistoryFilter, limit: Option<i64>) -> anyhow::Result<Vec<HistoryRecord>> {
+        let sql = format!(
+            r#"
+            SELECT job_id, item_id, source_uri, host, target_path, resolver, driver, status, error,
+                   total_size, bytes, avg_speed, peak_speed, started_at, finished_at
+            FROM history
+            {}
+            ORDER BY finished_at DESC, id DESC
+            LIMIT ?;
+            "#,
+            Self::history_where(f)
+        );
+        let rows = Self::filter_binds(sqlx::query(&sql), f)
+            .bind(limit.unwrap_or(-1))
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut out = vec![];
+        for r in rows {
+            let (job_id, item_id): (String, String) = (r.get("job_id"), r.get("item_id"));
+            out.push(HistoryRecord {
+                job_id: job_id.parse().with_context(|| format!("invalid job id {}", job_id))?,
+                item_id: item_id.parse().with_context(|| format!("invalid item id {}", item_id))?,
+                source_uri: r.get("source_uri"),
+                host: r.get("host"),
+                target_path: PathBuf::from(r.get::<String, _>("target_path")),
+                resolver: r.get("resolver"),
+                driver: r.get("driver"),
+                status: r.get("status"),
+                error: r.get("error"),
+                total_size: r.get("total_size"),
+                bytes: r.get("bytes"),
+                avg_speed: r.get("avg_speed"),
+                peak_speed: r.get("peak_speed"),
+                started_at: r.get("started_at"),
+                finished_at: r.get("finished_at"),
+            });
+        }
+        Ok(out)
+    }
 
+    /// 按天（by_host = false，UTC 日期）或主机汇总
+    pub async fn history_totals(&self, f: &HistoryFilter, by_host: bool) -> anyhow::Result<Vec<HistoryTotal>> {
+        let key = if by_host {
+            "COALESCE(host, '-')"
+        } else {
+            "strftime('%Y-%m-%d', finished_at, 'unixepoch')"
+        };
+        let sql = format!(
+            r#"
+            SELECT {key} AS k, COUNT(1) AS runs, SUM(status = 'failed') AS failed, SUM(bytes) AS bytes
+            FROM history
+            {}
+            GROUP BY k
+            ORDER BY {};
+            "#,
+            Self::history_where(f),
+            if by_host { "bytes DESC" } else { "k ASC" },
+        );
+        let rows = Self::filter_binds(sqlx::query(&sql), f).fetch_all(&self.pool).await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| HistoryTotal {
+                key: r.get("k"),
+                runs: r.get("runs"),
+                failed: r.get("failed"),
+                bytes: r.get("bytes"),
+            })
+            .collect())
+    }
+
+    pub fn now_epoch() -> i64 {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64
+    }
+
+    /// 新任务直接归 owner 所有
+    pub async fn insert_job(&self, job_id: JobId, inputs: &[LinkInput], owner: &str) -> anyhow::Result<()> {
+        let now = Self::now_epoch();
         sqlx::query(
             r#"
-            CREATE TABLE IF NOT EXISTS fragments (
-              id INTEGER PRIMARY KEY AUTOINCREMENT,
-              item_id INTEGER NOT NULL,
-              offset INTEGER NOT NULL,
-              len INTEGER NOT NULL,
-              state INTEGER NOT NULL, -- 0 Missing,1 Downloading,2 Done,3 Bad
-              updated_at INTEGER NOT NULL,
-              FOREIGN KEY(item_id) REFERENCES items(id)
-            );
+            INSERT INTO jobs(id, status, inputs, created_at, updated_at, owner, owner_seen_at)
+            VALUES(?, ?, ?, ?, ?, ?, ?);
             "#,
         )
+            .bind(job_id.to_string())
+            .bind(job_status_to_int(JobStatus::Pending))
+            .bind(serde_json::to_string(inputs)?)
+            .bind(now)
+            .bind(now)
+            .bind(owner)
+            .bind(now)
             .execute(&self.pool)
             .await?;
+        Ok(())
+    }
 
+    /// 结束状态（完成 / 失败 / 取消）同时释放归属
+    pub async fn set_job_status(&self, job_id: JobId, status: JobStatus) -> anyhow::Result<()> {
+        let finished = matches!(status, JobStatus::Completed | JobStatus::Failed | JobStatus::Cancelled);
         sqlx::query(
             r#"
-            CREATE INDEX IF NOT EXISTS idx_frag_item
-            ON fragments(item_id);
+            UPDATE jobs
+            SET status = ?, updated_at = ?,
+                owner = CASE WHEN ? THEN NULL ELSE owner END
+            WHERE id = ?
+            "#,
+        )
+            .bind(job_status_to_int(status))
+            .bind(Self::now_epoch())
+            .bind(finished)
+            .bind(job_id.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// 把任务归到 owner 名下；任务正由另一个仍在刷新心跳的进程运行时返回 false
+    pub async fn claim_job(&self, job_id: JobId, owner: &str) -> anyhow::Result<bool> {
+        let now = Self::now_epoch();
+        let r = sqlx::query(
+            r#"
+            UPDATE jobs SET owner = ?, owner_seen_at = ?
+            WHERE id = ? AND (owner IS NULL OR owner = ? OR owner_seen_at IS NULL OR owner_seen_at < ?)
             "#,
         )
+            .bind(owner)
+            .bind(now)
+            .bind(job_id.to_string())
+            .bind(owner)
+            .bind(now - OWNER_STALE_SECS)
             .execute(&self.pool)
             .await?;
+        Ok(r.rows_affected() > 0)
+    }
 
+    /// 刷新 owner 名下所有任务的心跳
+    pub async fn touch_owned_jobs(&self, owner: &str) -> anyhow::Result<()> {
+        sqlx::query(r#"UPDATE jobs SET owner_seen_at = ? WHERE owner = ?"#)
+            .bind(Self::now_epoch())
+            .bind(owner)
+            .execute(&self.pool)
+            .await?;
         Ok(())
     }
 
-    fn now_epoch() -> i64 {
-        use std::time::{SystemTime, UNIX_EPOCH};
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs() as i64
+    /// 是否有别的进程（心跳未过期）正在运行库里的任务
+    pub async fn has_live_owner_except(&self, owner: &str) -> anyhow::Result<bool> {
+        let row = sqlx::query(
+            r#"
+            SELECT EXISTS(
+              SELECT 1 FROM jobs WHERE owner IS NOT NULL AND owner <> ? AND owner_seen_at >= ?
+            ) AS live
+            "#,
+        )
+            .bind(owner)
+            .bind(Self::now_epoch() - OWNER_STALE_SECS)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.get::<i64, _>("live") != 0)
+    }
+
+    /// 保存解析结果并把任务标记为已解析（同一事务）
+    pub async fn save_job_items(&self, job_id: JobId, items: &[DownloadItem], resolve_failed: bool) -> anyhow::Result<()> {
+        let now = Self::now_epoch();
+        let mut tx = self.pool.begin().await?;
+
+        for (seq, item) in items.iter().enumerate() {
+            let draft = StoredDraft {
+                display_name: item.display_name.clone(),
+                target_path: item.target_path.clone(),
+                total_size: item.total_size,
+                resources: item.resources.clone(),
+                options: item.options.clone(),
+                checksums: item.checksums.clone(),
+                piece_hashes: item.piece_hashes.clone(),
+                resolver: item.resolver.clone(),
+            };
+            sqlx::query(
+                r#"
+                INSERT INTO job_items(id, job_id, seq, status, draft, updated_at)
+                VALUES(?, ?, ?, ?, ?, ?);
+                "#,
+            )
+                .bind(item.id.to_string())
+                .bind(job_id.to_string())
+                .bind(seq as i64)
+                .bind(item_status_to_int(item.status))
+                .bind(serde_json::to_string(&draft)?)
+                .bind(now)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        sqlx::query(r#"UPDATE jobs SET resolved = 1, resolve_failed = ?, updated_at = ? WHERE id = ?"#)
+            .bind(resolve_failed as i64)
+            .bind(now)
+            .bind(job_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn set_job_item_status(&self, item_id: ItemId, status: ItemStatus) -> anyhow::Result<()> {
+        sqlx::query(r#"UPDATE job_items SET status = ?, updated_at = ? WHERE id = ?"#)
+            .bind(item_status_to_int(status))
+            .bind(Self::now_epoch())
+            .bind(item_id.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// 上次进程退出时还没结束的任务（Pending / Running / Paused），按创建顺序
+    pub async fn load_unfinished_jobs(&self) -> anyhow::Result<Vec<JobRecord>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, status, inputs, resolved, resolve_failed, created_at
+            FROM jobs
+            WHERE status IN (?, ?, ?)
+            ORDER BY created_at ASC, rowid ASC;
+            "#,
+        )
+            .bind(job_status_to_int(JobStatus::Pending))
+            .bind(job_status_to_int(JobStatus::Running))
+            .bind(job_status_to_int(JobStatus::Paused))
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter().map(job_from_row).collect()
+    }
+
+    /// 全部任务（含已结束的），按创建顺序
+    pub async fn load_jobs(&self) -> anyhow::Result<Vec<JobRecord>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, status, inputs, resolved, resolve_failed, created_at
+            FROM jobs
+            ORDER BY created_at ASC, rowid ASC;
+            "#,
+        )
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter().map(job_from_row).collect()
+    }
+
+    /// item 所属的任务（job_items 的主键就是 item id）
+    pub async fn find_item_job(&self, item_id: ItemId) -> anyhow::Result<Option<JobId>> {
+        let row = sqlx::query(r#"SELECT job_id FROM job_items WHERE id = ?"#)
+            .bind(item_id.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        row.map(|r| {
+            let id: String = r.get("job_id");
+            id.parse().with_context(|| format!("invalid job id {}", id))
+        })
+        .transpose()
+    }
+
+    pub async fn load_job_items(&self, job_id: JobId) -> anyhow::Result<Vec<JobItemRecord>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, status, draft
+            FROM job_items
+            WHERE job_id = ?
+            ORDER BY seq ASC;
+            "#,
+        )
+            .bind(job_id.to_string())
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.into_iter()
+            .map(|r| {
+                let id: String = r.get("id");
+                let d: StoredDraft = serde_json::from_str(&r.get::<String, _>("draft"))
+                    .with_context(|| format!("decode job item {}", id))?;
+                let status = int_to_item_status(r.get::<i64, _>("status"));
+                Ok(JobItemRecord {
+                    item: DownloadItem {
+                        id: id.parse().with_context(|| format!("invalid item id {}", id))?,
+                        job_id,
+                        status,
+                        display_name: d.display_name,
+                        target_path: d.target_path,
+                        total_size: d.total_size,
+                        resources: d.resources,
+                        options: d.options,
+                        fragments: vec![],
+                        checksums: d.checksums,
+                        piece_hashes: d.piece_hashes,
+                        resolver: d.resolver,
+                    },
+                    status,
+                })
+            })
+            .collect()
+    }
+
+    /// 重新打开已结束（失败 / 取消）或中断的任务：任务回到 Pending，失败的 item 回到 Ready。
+    /// 已完成的任务返回错误。
+    pub async fn reopen_job(&self, job_id: JobId) -> anyhow::Result<()> {
+        // 先读状态再改，用 IMMEDIATE 避免与正在下载的任务的写入冲突
+        let mut conn = self.begin_immediate().await?;
+        let r = Self::reopen_job_in(&mut conn, job_id).await;
+        Self::finish(conn, r).await
+    }
+
+    async fn reopen_job_in(conn: &mut SqliteConnection, job_id: JobId) -> anyhow::Result<()> {
+        let now = Self::now_epoch();
+        let row = sqlx::query(r#"SELECT status FROM jobs WHERE id = ?"#)
+            .bind(job_id.to_string())
+            .fetch_optional(&mut *conn)
+            .await?
+            .with_context(|| format!("no such job: {}", job_id))?;
+        if int_to_job_status(row.get::<i64, _>("status")) == JobStatus::Completed {
+            anyhow::bail!("job {} already completed", job_id);
+        }
+
+        sqlx::query(r#"UPDATE jobs SET status = ?, updated_at = ? WHERE id = ?"#)
+            .bind(job_status_to_int(JobStatus::Pending))
+            .bind(now)
+            .bind(job_id.to_string())
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query(r#"UPDATE job_items SET status = ?, updated_at = ? WHERE job_id = ? AND status = ?"#)
+            .bind(item_status_to_int(ItemStatus::Ready))
+            .bind(now)
+            .bind(job_id.to_string())
+            .bind(item_status_to_int(ItemStatus::Failed))
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    /// 删除任务及其 item 记录（不影响 items / fragments 表里的续传数据）
+    pub async fn delete_job(&self, job_id: JobId) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(r#"DELETE FROM job_items WHERE job_id = ?"#)
+            .bind(job_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(r#"DELETE FROM jobs WHERE id = ?"#)
+            .bind(job_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// items 表（item_db_id 为 None 时全部）及各自的分片统计，按 id 排序
+    pub async fn load_item_progress(&self, item_db_id: Option<i64>) -> anyhow::Result<Vec<ItemProgress>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT i.id, i.source_uri, i.target_path, i.partial_path, i.total_size, i.downloaded_bytes,
+                   i.supports_ranges, i.etag, i.last_modified, i.updated_at,
+                   COUNT(f.id) AS frags,
+                   COALESCE(SUM(f.state = ?), 0) AS frags_done,
+                   COALESCE(SUM(f.state = ?), 0) AS frags_downloading,
+                   COALESCE(SUM(f.state = ?), 0) AS frags_bad,
+                   COALESCE(SUM(f.retry), 0) AS retries
+            FROM items i
+            LEFT JOIN fragments f ON f.item_id = i.id
+            WHERE ? IS NULL OR i.id = ?
+            GROUP BY i.id
+            ORDER BY i.id ASC;
+            "#,
+        )
+            .bind(state_to_int(FragmentState::Done))
+            .bind(state_to_int(FragmentState::Downloading))
+            .bind(state_to_int(FragmentState::Bad))
+            .bind(item_db_id)
+            .bind(item_db_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|r| ItemProgress {
+                item_db_id: r.get("id"),
+                source_uri: r.get("source_uri"),
+                target_path: PathBuf::from(r.get::<String, _>("target_path")),
+                partial_path: PathBuf::from(r.get::<String, _>("partial_path")),
+                total_size: r.get("total_size"),
+                downloaded_bytes: r.get("downloaded_bytes"),
+                supports_ranges: r.get::<i64, _>("supports_ranges") != 0,
+                etag: r.get("etag"),
+                last_modified: r.get("last_modified"),
+                fragments: r.get("frags"),
+                fragments_done: r.get("frags_done"),
+                fragments_downloading: r.get("frags_downloading"),
+                fragments_bad: r.get("frags_bad"),
+                retries: r.get("retries"),
+                updated_at: r.get("updated_at"),
+            })
+            .collect())
+    }
+
+    /// 删除 items 表的一行及其分片
+    pub async fn delete_item(&self, item_db_id: i64) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(r#"DELETE FROM fragments WHERE item_id = ?"#)
+            .bind(item_db_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(r#"DELETE FROM items WHERE id = ?"#)
+            .bind(item_db_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
     }
 
     pub async fn upsert_item(
@@ -184,32 +852,58 @@ impl SqliteStore {
     }
 
     pub async fn get_item(&self, source_uri: &str, target_path: &Path) -> anyhow::Result<ItemRecord> {
+        self.find_item(source_uri, target_path)
+            .await?
+            .context("fetch item")
+    }
+
+    pub async fn find_item(&self, source_uri: &str, target_path: &Path) -> anyhow::Result<Option<ItemRecord>> {
         let row = sqlx::query(
             r#"
-            SELECT id, downloaded_bytes, total_size
+            SELECT id, downloaded_bytes, total_size, etag, last_modified
             FROM items
             WHERE source_uri = ? AND target_path = ?;
             "#,
         )
             .bind(source_uri)
             .bind(target_path.to_string_lossy().to_string())
-            .fetch_one(&self.pool)
+            .fetch_optional(&self.pool)
             .await
             .context("fetch item")?;
 
-        Ok(ItemRecord {
+        Ok(row.map(|row| ItemRecord {
             item_db_id: row.get::<i64, _>("id"),
             downloaded_bytes: row.get::<i64, _>("downloaded_bytes"),
             total_size: row.try_get::<i64, _>("total_size").ok(),
-        })
+            etag: row.try_get::<String, _>("etag").ok(),
+            last_modified: row.try_get::<String, _>("last_modified").ok(),
+        }))
     }
 
-    pub async fn load_fragments(&self, item_db_id: i64) -> anyhow::Result<Vec<FragmentRecord>> {
-        let rows = sqlx::query(
-            r#"
-            SELECT id, offset, len, state
-            FROM fragments
-            WHERE item_id = ?
+    pub async fn set_item_validators(
+        &self,
+        item_db_id: i64,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"UPDATE items SET etag = ?, last_modified = ?, updated_at = ? WHERE id = ?"#,
+        )
+            .bind(etag)
+            .bind(last_modified)
+            .bind(Self::now_epoch())
+            .bind(item_db_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn load_fragments(&self, item_db_id: i64) -> anyhow::Result<Vec<FragmentRecord>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, offset, len, state, retry, hash_algo, hash
+            FROM fragments
+            WHERE item_id = ?
             ORDER BY offset ASC;
             "#,
         )
@@ -224,6 +918,11 @@ impl SqliteStore {
                 offset: r.get::<i64, _>("offset"),
                 len: r.get::<i64, _>("len"),
                 state: int_to_state(r.get::<i64, _>("state")),
+                retry: r.get::<i64, _>("retry"),
+                hash: match (r.try_get::<String, _>("hash_algo"), r.try_get::<String, _>("hash")) {
+                    (Ok(algo), Ok(hex)) => HashAlgo::parse(&algo).and_then(|a| Checksum::new(a, &hex).ok()),
+                    _ => None,
+                },
             })
             .collect())
     }
@@ -233,9 +932,16 @@ impl SqliteStore {
         item_db_id: i64,
         ranges: &[(u64, u64)], // (offset,len)
     ) -> anyhow::Result<()> {
+        // 检查与插入在同一事务中，整个计划要么全部写入要么都不写
+        let mut conn = self.begin_immediate().await?;
+        let r = Self::insert_fragment_plan(&mut conn, item_db_id, ranges).await;
+        Self::finish(conn, r).await
+    }
+
+    async fn insert_fragment_plan(conn: &mut SqliteConnection, item_db_id: i64, ranges: &[(u64, u64)]) -> anyhow::Result<()> {
         let row = sqlx::query(r#"SELECT COUNT(1) as cnt FROM fragments WHERE item_id = ?"#)
             .bind(item_db_id)
-            .fetch_one(&self.pool)
+            .fetch_one(&mut *conn)
             .await?;
         let existing: i64 = row.get::<i64, _>("cnt");
         if existing > 0 {
@@ -243,59 +949,115 @@ impl SqliteStore {
         }
 
         let now = Self::now_epoch();
-        for (offset, len) in ranges {
-            sqlx::query(
-                r#"
-                INSERT INTO fragments(item_id, offset, len, state, updated_at)
-                VALUES(?, ?, ?, ?, ?);
-                "#,
-            )
-                .bind(item_db_id)
-                .bind(*offset as i64)
-                .bind(*len as i64)
-                .bind(state_to_int(FragmentState::Missing))
-                .bind(now)
-                .execute(&self.pool)
-                .await?;
+        for chunk in ranges.chunks(FRAGMENT_INSERT_BATCH) {
+            let mut qb = sqlx::QueryBuilder::<sqlx::Sqlite>::new(
+                "INSERT INTO fragments(item_id, offset, len, state, updated_at) ",
+            );
+            qb.push_values(chunk, |mut b, (offset, len)| {
+                b.push_bind(item_db_id)
+                    .push_bind(*offset as i64)
+                    .push_bind(*len as i64)
+                    .push_bind(state_to_int(FragmentState::Missing))
+                    .push_bind(now);
+            });
+            qb.build().execute(&mut *conn).await?;
         }
         Ok(())
     }
 
-    pub async fn mark_fragment_done_and_add_bytes(
+    /// 动态切分：把分片截短为 keep_len，剩余部分作为新分片（Missing）插入；返回新分片 id
+    pub async fn split_fragment(
         &self,
         frag_db_id: i64,
         item_db_id: i64,
-        bytes: i64,
-    ) -> anyhow::Result<()> {
+        keep_len: i64,
+        new_offset: i64,
+        new_len: i64,
+    ) -> anyhow::Result<i64> {
         let now = Self::now_epoch();
+        let mut tx = self.pool.begin().await?;
 
         sqlx::query(
             r#"
             UPDATE fragments
-            SET state = ?, updated_at = ?
+            SET len = ?, updated_at = ?
             WHERE id = ?;
             "#,
         )
-            .bind(state_to_int(FragmentState::Done))
+            .bind(keep_len)
             .bind(now)
             .bind(frag_db_id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
 
-        sqlx::query(
+        let row = sqlx::query(
             r#"
-            UPDATE items
-            SET downloaded_bytes = downloaded_bytes + ?,
-                updated_at = ?
-            WHERE id = ?;
+            INSERT INTO fragments(item_id, offset, len, state, updated_at)
+            VALUES(?, ?, ?, ?, ?)
+            RETURNING id;
             "#,
         )
-            .bind(bytes)
-            .bind(now)
             .bind(item_db_id)
-            .execute(&self.pool)
+            .bind(new_offset)
+            .bind(new_len)
+            .bind(state_to_int(FragmentState::Missing))
+            .bind(now)
+            .fetch_one(&mut *tx)
             .await?;
 
+        tx.commit().await?;
+        Ok(row.get::<i64, _>("id"))
+    }
+
+    pub async fn mark_fragment_done_and_add_bytes(
+        &self,
+        frag_db_id: i64,
+        item_db_id: i64,
+        bytes: i64,
+        hash: Option<&Checksum>,
+    ) -> anyhow::Result<()> {
+        let now = Self::now_epoch();
+
+        let mut tx = self.pool.begin().await?;
+
+        // 没有新哈希时保留旧值（COALESCE）；已是 Done 的分片不重复计入字节数
+        let done = sqlx::query(
+            r#"
+            UPDATE fragments
+            SET state = ?,
+                hash_algo = COALESCE(?, hash_algo),
+                hash = COALESCE(?, hash),
+                updated_at = ?
+            WHERE id = ? AND state <> ?;
+            "#,
+        )
+            .bind(state_to_int(FragmentState::Done))
+            .bind(hash.map(|c| c.algo.name()))
+            .bind(hash.map(|c| c.hex.as_str()))
+            .bind(now)
+            .bind(frag_db_id)
+            .bind(state_to_int(FragmentState::Done))
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+        if done > 0 {
+            sqlx::query(
+                r#"
+                UPDATE items
+                SET downloaded_bytes = downloaded_bytes + ?,
+                    updated_at = ?
+                WHERE id = ?;
+                "#,
+            )
+                .bind(bytes)
+                .bind(now)
+                .bind(item_db_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
         Ok(())
     }
 
@@ -316,18 +1078,97 @@ impl SqliteStore {
         Ok(())
     }
 
-    pub async fn read_partial_path(&self, source_uri: &str, target_path: &Path) -> anyhow::Result<PathBuf> {
+    /// 分片下载失败：标记为 Bad 并累加重试计数，返回新的计数
+    pub async fn mark_fragment_bad(&self, frag_db_id: i64) -> anyhow::Result<i64> {
+        let now = Self::now_epoch();
         let row = sqlx::query(
             r#"
-            SELECT partial_path FROM items
-            WHERE source_uri = ? AND target_path = ?;
+            UPDATE fragments
+            SET state = ?, retry = retry + 1, updated_at = ?
+            WHERE id = ?
+            RETURNING retry;
             "#,
         )
-            .bind(source_uri)
-            .bind(target_path.to_string_lossy().to_string())
+            .bind(state_to_int(FragmentState::Bad))
+            .bind(now)
+            .bind(frag_db_id)
             .fetch_one(&self.pool)
             .await?;
-        Ok(PathBuf::from(row.get::<String, _>("partial_path")))
+        Ok(row.get::<i64, _>("retry"))
+    }
+
+    /// 清零分片的失败次数（重试预算用尽、item 已失败之后）
+    pub async fn reset_fragment_retry(&self, frag_db_id: i64) -> anyhow::Result<()> {
+        sqlx::query(r#"UPDATE fragments SET retry = 0, updated_at = ? WHERE id = ?"#)
+            .bind(Self::now_epoch())
+            .bind(frag_db_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// 把中断时仍处于 Downloading 的分片回退为 Missing
+    pub async fn reset_downloading_fragments(&self, item_db_id: i64) -> anyhow::Result<()> {
+        let now = Self::now_epoch();
+        sqlx::query(
+            r#"
+            UPDATE fragments
+            SET state = ?, updated_at = ?
+            WHERE item_id = ? AND state = ?;
+            "#,
+        )
+            .bind(state_to_int(FragmentState::Missing))
+            .bind(now)
+            .bind(item_db_id)
+            .bind(state_to_int(FragmentState::Downloading))
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// 启动时调用：上次进程被杀时正在下载的分片仍是 Downloading，全部回退为 Missing；返回回退的分片数
+    pub async fn reset_all_downloading_fragments(&self) -> anyhow::Result<u64> {
+        let n = sqlx::query(
+            r#"
+            UPDATE fragments
+            SET state = ?, updated_at = ?
+            WHERE state = ?;
+            "#,
+        )
+            .bind(state_to_int(FragmentState::Missing))
+            .bind(Self::now_epoch())
+            .bind(state_to_int(FragmentState::Downloading))
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        Ok(n)
+    }
+
+    /// 数据已不可信的分片回退为 Missing，并同步重算该 item 的 downloaded_bytes
+    pub async fn reset_fragments(&self, item_db_id: i64, frag_db_ids: &[i64]) -> anyhow::Result<()> {
+        if frag_db_ids.is_empty() {
+            return Ok(());
+        }
+        let now = Self::now_epoch();
+        let mut tx = self.pool.begin().await?;
+        for id in frag_db_ids {
+            sqlx::query(
+                r#"
+                UPDATE fragments
+                SET state = ?, updated_at = ?
+                WHERE id = ? AND item_id = ?;
+                "#,
+            )
+                .bind(state_to_int(FragmentState::Missing))
+                .bind(now)
+                .bind(id)
+                .bind(item_db_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        Self::recompute_downloaded_bytes(&mut tx, Some(item_db_id)).await?;
+        tx.commit().await?;
+        Ok(())
     }
 
     pub async fn set_item_supports_ranges(&self, item_db_id: i64, supports_ranges: bool) -> anyhow::Result<()> {
@@ -344,16 +1185,149 @@ impl SqliteStore {
         Ok(())
     }
 
+    /// 删除分片计划（用于重新规划），已下载字节随之清零
     pub async fn delete_fragments(&self, item_db_id: i64) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
         sqlx::query(r#"DELETE FROM fragments WHERE item_id = ?"#)
             .bind(item_db_id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
+        sqlx::query(r#"UPDATE items SET downloaded_bytes = 0, updated_at = ? WHERE id = ?"#)
+            .bind(Self::now_epoch())
+            .bind(item_db_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
         Ok(())
     }
 
 }
 
+/// 库是由更新版本的程序创建 / 升级的，本程序不认识其结构
+#[derive(Debug, thiserror::Error)]
+#[error("database schema version {found} is newer than this build supports ({supported}); upgrade the downloader")]
+pub struct SchemaTooNew {
+    pub found: i64,
+    pub supported: i64,
+}
+
+/// 一次结构变更。只能在末尾追加新版本，已发布的迁移不要再改
+struct Migration {
+    version: i64,
+    description: &'static str,
+    sql: &'static str,
+    /// 缺失时补上的列 (表, 列, 定义)
+    add_columns: &'static [(&'static str, &'static str, &'static str)],
+}
+
+const MIGRATIONS: &[Migration] = &[
+    // 引入 schema_version 之前的库结构。旧库里表已存在，CREATE 都是 IF NOT EXISTS，
+    // 早期版本缺少的列由 add_columns 补上
+    Migration {
+        version: 1,
+        description: "initial schema",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS items (
+              id INTEGER PRIMARY KEY AUTOINCREMENT,
+              source_uri TEXT NOT NULL,
+              target_path TEXT NOT NULL,
+              partial_path TEXT NOT NULL,
+              total_size INTEGER NULL,
+              chunk_size INTEGER NOT NULL,
+              supports_ranges INTEGER NOT NULL,
+              downloaded_bytes INTEGER NOT NULL DEFAULT 0,
+              etag TEXT NULL,
+              last_modified TEXT NULL,
+              updated_at INTEGER NOT NULL
+            );
+
+            CREATE UNIQUE INDEX IF NOT EXISTS idx_items_unique
+            ON items(source_uri, target_path);
+
+            CREATE TABLE IF NOT EXISTS fragments (
+              id INTEGER PRIMARY KEY AUTOINCREMENT,
+              item_id INTEGER NOT NULL,
+              offset INTEGER NOT NULL,
+              len INTEGER NOT NULL,
+              state INTEGER NOT NULL, -- 0 Missing,1 Downloading,2 Done,3 Bad
+              retry INTEGER NOT NULL DEFAULT 0,
+              hash_algo TEXT NULL,
+              hash TEXT NULL,
+              updated_at INTEGER NOT NULL,
+              FOREIGN KEY(item_id) REFERENCES items(id)
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_frag_item
+            ON fragments(item_id);
+
+            CREATE TABLE IF NOT EXISTS jobs (
+              id TEXT PRIMARY KEY,
+              status INTEGER NOT NULL, -- 0 Pending,1 Running,2 Paused,3 Completed,4 Failed,5 Cancelled
+              inputs TEXT NOT NULL, -- JSON: [LinkInput]
+              resolved INTEGER NOT NULL DEFAULT 0,
+              resolve_failed INTEGER NOT NULL DEFAULT 0,
+              created_at INTEGER NOT NULL,
+              updated_at INTEGER NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS job_items (
+              id TEXT PRIMARY KEY,
+              job_id TEXT NOT NULL,
+              seq INTEGER NOT NULL,
+              status INTEGER NOT NULL, -- 见 item_status_to_int
+              draft TEXT NOT NULL, -- JSON: StoredDraft
+              updated_at INTEGER NOT NULL,
+              FOREIGN KEY(job_id) REFERENCES jobs(id)
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_job_items_job
+            ON job_items(job_id);
+        "#,
+        add_columns: &[
+            ("fragments", "retry", "INTEGER NOT NULL DEFAULT 0"),
+            ("fragments", "hash_algo", "TEXT NULL"),
+            ("fragments", "hash", "TEXT NULL"),
+            ("items", "etag", "TEXT NULL"),
+            ("items", "last_modified", "TEXT NULL"),
+        ],
+    },
+    // 下载历史：每个 item 每次运行一行，不随 clean 删除
+    Migration {
+        version: 2,
+        description: "download history",
+        sql: r#"
+            CREATE TABLE history (
+              id INTEGER PRIMARY KEY AUTOINCREMENT,
+              job_id TEXT NOT NULL,
+              item_id TEXT NOT NULL,
+              source_uri TEXT NOT NULL,
+              host TEXT NULL,
+              target_path TEXT NOT NULL,
+              resolver TEXT NULL,
+              driver TEXT NULL,
+              status TEXT NOT NULL, -- done / failed / cancelled
+              error TEXT NULL,
+              total_size INTEGER NULL,
+              bytes INTEGER NOT NULL, -- 本次运行实际传输的字节数
+              avg_speed INTEGER NOT NULL, -- bytes/s
+              peak_speed INTEGER NOT NULL,
+              started_at INTEGER NOT NULL,
+              finished_at INTEGER NOT NULL
+            );
+
+            CREATE INDEX idx_history_finished ON history(finished_at);
+        "#,
+        add_columns: &[],
+    },
+    // 任务归属：正在运行任务的进程定期刷新 owner_seen_at，其它进程据此不去接管
+    Migration {
+        version: 3,
+        description: "job owners",
+        sql: "",
+        add_columns: &[("jobs", "owner", "TEXT NULL"), ("jobs", "owner_seen_at", "INTEGER NULL")],
+    },
+];
+
 fn state_to_int(s: FragmentState) -> i64 {
     match s {
         FragmentState::Missing => 0,
@@ -363,6 +1337,65 @@ fn state_to_int(s: FragmentState) -> i64 {
     }
 }
 
+fn job_from_row(r: &sqlx::sqlite::SqliteRow) -> anyhow::Result<JobRecord> {
+    let id: String = r.get("id");
+    Ok(JobRecord {
+        job_id: id.parse().with_context(|| format!("invalid job id {}", id))?,
+        status: int_to_job_status(r.get::<i64, _>("status")),
+        inputs: serde_json::from_str(&r.get::<String, _>("inputs"))
+            .with_context(|| format!("decode inputs of job {}", id))?,
+        resolved: r.get::<i64, _>("resolved") != 0,
+        resolve_failed: r.get::<i64, _>("resolve_failed") != 0,
+        created_at: r.get::<i64, _>("created_at"),
+    })
+}
+
+fn job_status_to_int(s: JobStatus) -> i64 {
+    match s {
+        JobStatus::Pending => 0,
+        JobStatus::Running => 1,
+        JobStatus::Paused => 2,
+        JobStatus::Completed => 3,
+        JobStatus::Failed => 4,
+        JobStatus::Cancelled => 5,
+    }
+}
+
+fn int_to_job_status(v: i64) -> JobStatus {
+    match v {
+        0 => JobStatus::Pending,
+        1 => JobStatus::Running,
+        2 => JobStatus::Paused,
+        3 => JobStatus::Completed,
+        4 => JobStatus::Failed,
+        _ => JobStatus::Cancelled,
+    }
+}
+
+fn item_status_to_int(s: ItemStatus) -> i64 {
+    match s {
+        ItemStatus::Resolving => 0,
+        ItemStatus::Ready => 1,
+        ItemStatus::Downloading => 2,
+        ItemStatus::Verifying => 3,
+        ItemStatus::Assembling => 4,
+        ItemStatus::Done => 5,
+        ItemStatus::Failed => 6,
+    }
+}
+
+fn int_to_item_status(v: i64) -> ItemStatus {
+    match v {
+        0 => ItemStatus::Resolving,
+        2 => ItemStatus::Downloading,
+        3 => ItemStatus::Verifying,
+        4 => ItemStatus::Assembling,
+        5 => ItemStatus::Done,
+        6 => ItemStatus::Failed,
+        _ => ItemStatus::Ready,
+    }
+}
+
 fn int_to_state(v: i64) -> FragmentState {
     match v {
         0 => FragmentState::Missing,
@@ -372,3 +1405,324 @@ fn int_to_state(v: i64) -> FragmentState {
         _ => FragmentState::Missing,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 临时目录里的库文件路径；目录随返回的 TempDir 一起删除
+    fn test_db_path() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DB_FILE_NAME);
+        (dir, path)
+    }
+
+    /// 不经过 migrate，直接在库里执行 SQL（模拟旧版本留下的库）
+    async fn raw_db(path: &Path, sql: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let opts = SqliteConnectOptions::new().filename(path).create_if_missing(true);
+        let pool = SqlitePool::connect_with(opts).await.unwrap();
+        sqlx::raw_sql(sql).execute(&pool).await.unwrap();
+        pool.close().await;
+    }
+
+    async fn columns(store: &SqliteStore, table: &str) -> Vec<String> {
+        sqlx::query(&format!("PRAGMA table_info({})", table))
+            .fetch_all(&store.pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.get::<String, _>("name"))
+            .collect()
+    }
+
+    async fn current_version(store: &SqliteStore) -> i64 {
+        SqliteStore::schema_version(&mut store.pool.acquire().await.unwrap()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn upgrades_schema_without_version_table() {
+        let (_dir, path) = test_db_path();
+        // 引入 schema_version 之前的库：只有 items / fragments，且缺少后来加的列
+        raw_db(
+            &path,
+            r#"
+            CREATE TABLE items (
+              id INTEGER PRIMARY KEY AUTOINCREMENT,
+              source_uri TEXT NOT NULL,
+              target_path TEXT NOT NULL,
+              partial_path TEXT NOT NULL,
+              total_size INTEGER NULL,
+              chunk_size INTEGER NOT NULL,
+              supports_ranges INTEGER NOT NULL,
+              downloaded_bytes INTEGER NOT NULL DEFAULT 0,
+              updated_at INTEGER NOT NULL
+            );
+            CREATE UNIQUE INDEX idx_items_unique ON items(source_uri, target_path);
+            CREATE TABLE fragments (
+              id INTEGER PRIMARY KEY AUTOINCREMENT,
+              item_id INTEGER NOT NULL,
+              offset INTEGER NOT NULL,
+              len INTEGER NOT NULL,
+              state INTEGER NOT NULL,
+              updated_at INTEGER NOT NULL,
+              FOREIGN KEY(item_id) REFERENCES items(id)
+            );
+            CREATE INDEX idx_frag_item ON fragments(item_id);
+
+            INSERT INTO items(source_uri, target_path, partial_path, total_size, chunk_size, supports_ranges, downloaded_bytes, updated_at)
+            VALUES('http://example.test/a.bin', 'out/a.bin', 'out/a.partial', 200, 100, 1, 0, 0);
+            INSERT INTO fragments(item_id, offset, len, state, updated_at) VALUES(1, 0, 100, 2, 0);
+            INSERT INTO fragments(item_id, offset, len, state, updated_at) VALUES(1, 100, 100, 0, 0);
+            "#,
+        )
+        .await;
+
+        let store = SqliteStore::open(&path).await.unwrap();
+        assert_eq!(current_version(&store).await, MIGRATIONS.last().unwrap().version);
+
+        let frag_cols = columns(&store, "fragments").await;
+        for c in ["retry", "hash_algo", "hash"] {
+            assert!(frag_cols.iter().any(|n| n == c), "fragments.{} missing", c);
+        }
+        let item_cols = columns(&store, "items").await;
+        for c in ["etag", "last_modified"] {
+            assert!(item_cols.iter().any(|n| n == c), "items.{} missing", c);
+        }
+        for t in ["jobs", "job_items", "history"] {
+            assert!(!columns(&store, t).await.is_empty(), "table {} missing", t);
+        }
+
+        // 旧数据保留，进度按 Done 分片重算
+        let item = store.find_item("http://example.test/a.bin", Path::new("out/a.bin")).await.unwrap().unwrap();
+        assert_eq!(item.downloaded_bytes, 100);
+        let frags = store.load_fragments(item.item_db_id).await.unwrap();
+        assert_eq!(frags.len(), 2);
+        assert_eq!(frags[0].retry, 0);
+
+        // 再次打开不重复迁移
+        drop(store);
+        let store = SqliteStore::open(&path).await.unwrap();
+        assert_eq!(current_version(&store).await, MIGRATIONS.last().unwrap().version);
+    }
+
+    #[tokio::test]
+    async fn refuses_newer_schema() {
+        let (_dir, path) = test_db_path();
+        raw_db(
+            &path,
+            r#"
+            CREATE TABLE schema_version (
+              version INTEGER PRIMARY KEY,
+              description TEXT NOT NULL,
+              applied_at INTEGER NOT NULL
+            );
+            INSERT INTO schema_version(version, description, applied_at) VALUES(999, 'from the future', 0);
+            "#,
+        )
+        .await;
+
+        let err = SqliteStore::open(&path).await.err().expect("newer schema must be rejected");
+        let too_new = err.downcast_ref::<SchemaTooNew>().expect("SchemaTooNew");
+        assert_eq!(too_new.found, 999);
+        assert_eq!(too_new.supported, MIGRATIONS.last().unwrap().version);
+    }
+
+    #[tokio::test]
+    async fn live_owner_blocks_claims() {
+        let (_dir, path) = test_db_path();
+        let store = SqliteStore::open(&path).await.unwrap();
+        let job = uuid::Uuid::new_v4();
+        store.insert_job(job, &[], "daemon").await.unwrap();
+
+        assert!(!store.claim_job(job, "cli").await.unwrap());
+        assert!(store.claim_job(job, "daemon").await.unwrap());
+        assert!(store.has_live_owner_except("cli").await.unwrap());
+        assert!(!store.has_live_owner_except("daemon").await.unwrap());
+
+        // 心跳过期：原进程已退出，可以接管
+        sqlx::query("UPDATE jobs SET owner_seen_at = ?")
+            .bind(SqliteStore::now_epoch() - OWNER_STALE_SECS - 1)
+            .execute(&store.pool)
+            .await
+            .unwrap();
+        assert!(!store.has_live_owner_except("cli").await.unwrap());
+        assert!(store.claim_job(job, "cli").await.unwrap());
+
+        // 结束状态释放归属
+        store.set_job_status(job, JobStatus::Failed).await.unwrap();
+        assert!(store.claim_job(job, "daemon").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn concurrent_opens_migrate_once() {
+        let (_dir, path) = test_db_path();
+        let (a, b) = tokio::join!(SqliteStore::open(&path), SqliteStore::open(&path));
+        let (a, _b) = (a.unwrap(), b.unwrap());
+
+        let applied: i64 = sqlx::query("SELECT COUNT(*) AS n FROM schema_version")
+            .fetch_one(&a.pool)
+            .await
+            .unwrap()
+            .get("n");
+        assert_eq!(applied, MIGRATIONS.len() as i64);
+    }
+
+    async fn test_item(store: &SqliteStore, name: &str, total: i64) -> i64 {
+        let target = PathBuf::from("out").join(name);
+        store
+            .upsert_item(&format!("http://example.test/{}", name), &target, &target.with_extension("partial"), 100, Some(total), true)
+            .await
+            .unwrap()
+            .item_db_id
+    }
+
+    async fn downloaded_bytes(store: &SqliteStore, item_db_id: i64) -> i64 {
+        store.load_item_progress(Some(item_db_id)).await.unwrap()[0].downloaded_bytes
+    }
+
+    #[tokio::test]
+    async fn fragment_plan_is_inserted_once_across_batches() {
+        let (_dir, path) = test_db_path();
+        let store = SqliteStore::open(&path).await.unwrap();
+        let n = FRAGMENT_INSERT_BATCH * 2 + 34;
+        let id = test_item(&store, "big.bin", n as i64 * 100).await;
+        let ranges: Vec<(u64, u64)> = (0..n as u64).map(|i| (i * 100, 100)).collect();
+
+        store.ensure_fragments_for_ranges(id, &ranges).await.unwrap();
+        let mut got: Vec<(u64, u64)> = store
+            .load_fragments(id)
+            .await
+            .unwrap()
+            .iter()
+            .map(|f| (f.offset as u64, f.len as u64))
+            .collect();
+        got.sort();
+        assert_eq!(got, ranges);
+
+        // 已有计划时不再插入
+        store.ensure_fragments_for_ranges(id, &[(0, n as u64 * 100)]).await.unwrap();
+        assert_eq!(store.load_fragments(id).await.unwrap().len(), n);
+    }
+
+    #[tokio::test]
+    async fn concurrent_fragment_plans_do_not_hit_busy() {
+        let (_dir, path) = test_db_path();
+        let store = SqliteStore::open(&path).await.unwrap();
+        let mut tasks = vec![];
+        for i in 0..16 {
+            let id = test_item(&store, &format!("f{}.bin", i), 1000).await;
+            let store = store.clone();
+            tasks.push(tokio::spawn(async move {
+                let ranges: Vec<(u64, u64)> = (0..10).map(|j| (j * 100, 100)).collect();
+                store.ensure_fragments_for_ranges(id, &ranges).await?;
+                for f in store.load_fragments(id).await? {
+                    store.mark_fragment_done_and_add_bytes(f.frag_db_id, id, f.len, None).await?;
+                }
+                anyhow::Ok(id)
+            }));
+        }
+        for t in tasks {
+            let id = t.await.unwrap().unwrap();
+            assert_eq!(downloaded_bytes(&store, id).await, 1000);
+        }
+    }
+
+    #[tokio::test]
+    async fn downloaded_bytes_are_recomputed_on_open() {
+        let (_dir, path) = test_db_path();
+        let store = SqliteStore::open(&path).await.unwrap();
+        let id = test_item(&store, "a.bin", 300).await;
+        store.ensure_fragments_for_ranges(id, &[(0, 100), (100, 100), (200, 100)]).await.unwrap();
+        let frags = store.load_fragments(id).await.unwrap();
+        store.mark_fragment_done_and_add_bytes(frags[0].frag_db_id, id, 100, None).await.unwrap();
+        store.mark_fragment_done_and_add_bytes(frags[1].frag_db_id, id, 100, None).await.unwrap();
+        // 重复标记不重复计数
+        store.mark_fragment_done_and_add_bytes(frags[1].frag_db_id, id, 100, None).await.unwrap();
+        assert_eq!(downloaded_bytes(&store, id).await, 200);
+
+        // 模拟崩溃留下的不一致计数，重新打开后按已完成分片纠正
+        sqlx::query("UPDATE items SET downloaded_bytes = 999 WHERE id = ?").bind(id).execute(&store.pool).await.unwrap();
+        store.pool.close().await;
+        let store = SqliteStore::open(&path).await.unwrap();
+        assert_eq!(downloaded_bytes(&store, id).await, 200);
+    }
+
+    /// 2024-03-01 / 2024-03-02 00:00 UTC
+    const DAY1: i64 = 1_709_251_200;
+    const DAY2: i64 = 1_709_337_600;
+
+    async fn history_store() -> (tempfile::TempDir, SqliteStore) {
+        use crate::core::testutil::test_history;
+        let (dir, path) = test_db_path();
+        let store = SqliteStore::open(&path).await.unwrap();
+        for h in [
+            test_history(Some("a.example"), "done", 100, DAY1 + 10),
+            test_history(Some("a.example"), "failed", 50, DAY1 + 3600),
+            test_history(Some("b.example"), "done", 300, DAY2),
+            test_history(None, "cancelled", 7, DAY2 + 20),
+        ] {
+            store.insert_history(&h).await.unwrap();
+        }
+        (dir, store)
+    }
+
+    async fn history_bytes(store: &SqliteStore, f: HistoryFilter, limit: Option<i64>) -> Vec<i64> {
+        store.load_history(&f, limit).await.unwrap().iter().map(|h| h.bytes).collect()
+    }
+
+    #[tokio::test]
+    async fn history_filters() {
+        let (_dir, store) = history_store().await;
+        // 最近的在前
+        assert_eq!(history_bytes(&store, HistoryFilter::default(), None).await, [7, 300, 50, 100]);
+        assert_eq!(history_bytes(&store, HistoryFilter::default(), Some(2)).await, [7, 300]);
+
+        // [since, until)：正好在 DAY2 结束的记录属于第二天
+        let day2 = HistoryFilter { since: Some(DAY2), ..Default::default() };
+        assert_eq!(history_bytes(&store, day2, None).await, [7, 300]);
+        let day1 = HistoryFilter { since: Some(DAY1), until: Some(DAY2), ..Default::default() };
+        assert_eq!(history_bytes(&store, day1, None).await, [50, 100]);
+
+        // 主机名不区分大小写
+        let host = HistoryFilter { host: Some("A.Example".to_string()), ..Default::default() };
+        assert_eq!(history_bytes(&store, host, None).await, [50, 100]);
+        let failed = HistoryFilter { status: Some("failed".to_string()), ..Default::default() };
+        let records = store.load_history(&failed, None).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].error.as_deref(), Some("connection reset"));
+        let all = HistoryFilter {
+            since: Some(DAY1),
+            until: Some(DAY2 + 86_400),
+            host: Some("a.example".to_string()),
+            status: Some("done".to_string()),
+        };
+        assert_eq!(history_bytes(&store, all, None).await, [100]);
+        let none = HistoryFilter { host: Some("c.example".to_string()), ..Default::default() };
+        assert!(history_bytes(&store, none, None).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn history_totals_by_day_and_host() {
+        let (_dir, store) = history_store().await;
+        let rows = |totals: Vec<HistoryTotal>| -> Vec<(String, i64, i64, i64)> {
+            totals.into_iter().map(|t| (t.key, t.runs, t.failed, t.bytes)).collect()
+        };
+
+        let by_day = store.history_totals(&HistoryFilter::default(), false).await.unwrap();
+        assert_eq!(rows(by_day), [("2024-03-01".to_string(), 2, 1, 150), ("2024-03-02".to_string(), 2, 0, 307)]);
+
+        // 按字节数从多到少；没有主机的记录归到 "-"
+        let by_host = store.history_totals(&HistoryFilter::default(), true).await.unwrap();
+        assert_eq!(
+            rows(by_host),
+            [("b.example".to_string(), 1, 0, 300), ("a.example".to_string(), 2, 1, 150), ("-".to_string(), 1, 0, 7)]
+        );
+
+        let done = HistoryFilter { status: Some("done".to_string()), ..Default::default() };
+        let by_host = store.history_totals(&done, true).await.unwrap();
+        assert_eq!(rows(by_host), [("b.example".to_string(), 1, 0, 300), ("a.example".to_string(), 1, 0, 100)]);
+        let empty = HistoryFilter { since: Some(DAY2 + 86_400), ..Default::default() };
+        assert!(store.history_totals(&empty, false).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,272 @@
+//! 测试用的内存“服务器”（MockDriver / MockResolver）和引擎构造，供各模块的单元测试共用
+
+use crate::core::engine::Engine;
+use crate::core::model::{Capabilities, DownloadItem, ItemStatus, LinkInput, ResourceDescriptor, ResourceType};
+use crate::core::store::HistoryRecord;
+use crate::plugins::http::driver::HttpDriverError;
+use crate::plugins::registry::{
+    ByteStream, DownloadItemDraft, DriverContext, LinkResolver, PluginRegistry, ProbeInfo, ResolveContext, ResolveResult,
+    TransferDriver,
+};
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// 服务器对 Range 请求的表现
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RangeMode {
+    /// 正常返回请求的范围
+    Serve,
+    /// 忽略 Range，返回 200 + 全量
+    Ignore,
+    /// 第一个 Range 请求返回 416，之后正常
+    RejectFirst,
+    /// 所有 Range 请求都以普通错误失败
+    Fail,
+    /// 比请求的范围多返回 1 字节
+    Long,
+    /// 比请求的范围少返回 1 字节（连接提前关闭）
+    Short,
+}
+
+/// 内存里的“服务器”：按 64 KiB 分块返回 data，并记录同时打开的连接数
+pub struct MockDriver {
+    pub data: Vec<u8>,
+    pub mode: RangeMode,
+    /// 每个分块之间的延迟，让并发的下载真正重叠、或留出暂停 / 取消的时间
+    pub chunk_delay: Duration,
+    pub range_calls: AtomicUsize,
+    pub full_calls: AtomicUsize,
+    /// 已经交给引擎的字节数
+    pub served: Arc<AtomicUsize>,
+    pub open_streams: Arc<AtomicUsize>,
+    pub peak_streams: Arc<AtomicUsize>,
+    /// 下一次 Range 失败时调用一次（模拟用户在重试中途暂停）
+    pub on_fail: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+}
+
+/// 流结束或被丢弃时归还连接计数
+struct StreamGuard(Arc<AtomicUsize>);
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl MockDriver {
+    pub fn new(data: Vec<u8>, mode: RangeMode) -> Arc<Self> {
+        Self::with_delay(data, mode, Duration::ZERO)
+    }
+
+    pub fn with_delay(data: Vec<u8>, mode: RangeMode, chunk_delay: Duration) -> Arc<Self> {
+        Arc::new(Self {
+            data,
+            mode,
+            chunk_delay,
+            range_calls: AtomicUsize::new(0),
+            full_calls: AtomicUsize::new(0),
+            served: Arc::new(AtomicUsize::new(0)),
+            open_streams: Arc::new(AtomicUsize::new(0)),
+            peak_streams: Arc::new(AtomicUsize::new(0)),
+            on_fail: Mutex::new(None),
+        })
+    }
+
+    fn stream(&self, start: usize, end: usize) -> ByteStream {
+        let open = self.open_streams.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak_streams.fetch_max(open, Ordering::SeqCst);
+        let guard = StreamGuard(self.open_streams.clone());
+        let delay = self.chunk_delay;
+        let served = self.served.clone();
+        let chunks: Vec<anyhow::Result<bytes::Bytes>> = self.data[start..end]
+            .chunks(64 * 1024)
+            .map(|c| Ok(bytes::Bytes::copy_from_slice(c)))
+            .collect();
+        futures::stream::iter(chunks)
+            .then(move |c| {
+                let _held = &guard;
+                let served = served.clone();
+                async move {
+                    if !delay.is_zero() {
+                        tokio::time::sleep(delay).await;
+                    }
+                    if let Ok(b) = &c {
+                        served.fetch_add(b.len(), Ordering::SeqCst);
+                    }
+                    c
+                }
+            })
+            .boxed()
+    }
+}
+
+#[async_trait]
+impl TransferDriver for MockDriver {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn supports(&self, res: &ResourceDescriptor) -> bool {
+        res.uri.starts_with("mock://")
+    }
+
+    async fn probe(&self, _res: &ResourceDescriptor, _ctx: &DriverContext) -> anyhow::Result<ProbeInfo> {
+        Ok(ProbeInfo { total_size: Some(self.data.len() as u64), supports_ranges: true, ..Default::default() })
+    }
+
+    async fn download_range(
+        &self,
+        _res: &ResourceDescriptor,
+        _ctx: &DriverContext,
+        start: u64,
+        end_inclusive: u64,
+    ) -> anyhow::Result<ByteStream> {
+        let n = self.range_calls.fetch_add(1, Ordering::SeqCst);
+        match self.mode {
+            RangeMode::Ignore => Err(HttpDriverError::RangeIgnoredFull.into()),
+            RangeMode::RejectFirst if n == 0 => Err(HttpDriverError::RangeNotSupported.into()),
+            RangeMode::Fail => {
+                let hook = self.on_fail.lock().unwrap().take();
+                match hook {
+                    Some(f) => f(),
+                    // 钩子触发之后的请求晚一点失败，保证引擎先看到暂停
+                    None if n > 0 => tokio::time::sleep(Duration::from_millis(50)).await,
+                    None => {}
+                }
+                Err(anyhow::anyhow!("connection reset"))
+            }
+            RangeMode::Long => Ok(self.stream(start as usize, (end_inclusive as usize + 2).min(self.data.len()))),
+            RangeMode::Short => Ok(self.stream(start as usize, end_inclusive as usize)),
+            _ => Ok(self.stream(start as usize, end_inclusive as usize + 1)),
+        }
+    }
+
+    async fn download_all(&self, _res: &ResourceDescriptor, _ctx: &DriverContext) -> anyhow::Result<ByteStream> {
+        self.full_calls.fetch_add(1, Ordering::SeqCst);
+        Ok(self.stream(0, self.data.len()))
+    }
+}
+
+/// `mock://host/<name>` 解析为一个 item，目标文件名取最后一段
+pub struct MockResolver;
+
+#[async_trait]
+impl LinkResolver for MockResolver {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn can_handle(&self, input: &LinkInput) -> u8 {
+        if input.raw.starts_with("mock://") {
+            100
+        } else {
+            0
+        }
+    }
+
+    async fn resolve(&self, input: &LinkInput, ctx: &ResolveContext) -> anyhow::Result<ResolveResult> {
+        let name = input.raw.rsplit('/').next().unwrap_or("file").to_string();
+        Ok(ResolveResult {
+            drafts: vec![DownloadItemDraft {
+                display_name: name.clone(),
+                suggested_path: ctx.out_dir.join(&name),
+                total_size: None,
+                resources: vec![mock_resource(&input.raw)],
+                checksums: vec![],
+                piece_hashes: None,
+            }],
+            warnings: vec![],
+        })
+    }
+}
+
+fn mock_resource(uri: &str) -> ResourceDescriptor {
+    ResourceDescriptor {
+        rtype: ResourceType::Http,
+        uri: uri.to_string(),
+        headers: HashMap::new(),
+        meta: HashMap::new(),
+        caps: Capabilities::default(),
+    }
+}
+
+pub fn test_dir() -> tempfile::TempDir {
+    tempfile::tempdir().unwrap()
+}
+
+/// 分片 1 MiB、fragment_retries = 2 的引擎，只认 mock:// 链接
+pub async fn test_engine(driver: Arc<dyn TransferDriver>, out_dir: &Path, concurrency: usize) -> Engine {
+    test_engine_with_connections(driver, out_dir, concurrency, 0).await
+}
+
+pub async fn test_engine_with_connections(
+    driver: Arc<dyn TransferDriver>,
+    out_dir: &Path,
+    concurrency: usize,
+    max_connections: usize,
+) -> Engine {
+    let ctx = DriverContext {
+        user_agent: "test".to_string(),
+        timeout_secs: 5,
+        retries: 0,
+        retry_backoff_ms: 1,
+        item_speed_limit: 0,
+        host_speed_limit: 0,
+        max_conns_per_host: 0,
+        max_connections,
+    };
+    let registry = PluginRegistry::for_tests(vec![Box::new(MockResolver)], vec![driver]);
+    Engine::new(registry, out_dir.to_path_buf(), concurrency, 1024 * 1024, 2, None, ctx).await.unwrap()
+}
+
+pub fn test_item(out_dir: &Path, name: &str) -> DownloadItem {
+    DownloadItem {
+        id: Uuid::new_v4(),
+        job_id: Uuid::new_v4(),
+        status: ItemStatus::Ready,
+        display_name: name.to_string(),
+        target_path: out_dir.join(name),
+        total_size: None,
+        resources: vec![mock_resource(&format!("mock://host/{}", name))],
+        options: HashMap::new(),
+        fragments: vec![],
+        checksums: vec![],
+        piece_hashes: None,
+        resolver: "test".to_string(),
+    }
+}
+
+pub fn test_input(name: &str) -> LinkInput {
+    LinkInput { raw: format!("mock://host/{}", name), headers: HashMap::new(), options: HashMap::new() }
+}
+
+pub fn test_data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+/// 一条 history 记录；只有主机、状态、字节数和结束时间有意义
+pub fn test_history(host: Option<&str>, status: &str, bytes: i64, finished_at: i64) -> HistoryRecord {
+    HistoryRecord {
+        job_id: Uuid::new_v4(),
+        item_id: Uuid::new_v4(),
+        source_uri: format!("https://{}/f", host.unwrap_or("unknown")),
+        host: host.map(str::to_string),
+        target_path: PathBuf::from("out/f"),
+        resolver: Some("http-resolver".to_string()),
+        driver: Some("http-driver".to_string()),
+        status: status.to_string(),
+        error: (status == "failed").then(|| "connection reset".to_string()),
+        total_size: Some(bytes),
+        bytes,
+        avg_speed: bytes,
+        peak_speed: bytes,
+        started_at: finished_at - 1,
+        finished_at,
+    }
+}
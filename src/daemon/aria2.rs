@@ -0,0 +1,524 @@
+//! aria2 兼容的 JSON-RPC（HTTP POST / WebSocket，路径 `/jsonrpc`），供现有的 aria2 客户端和浏览器扩展使用。
+//! 一个 aria2 下载（GID）对应一个 job，job 的 item 对应 aria2 的 files。
+
+use crate::core::engine::Engine;
+use crate::core::events::EngineEvent;
+use crate::core::model::{ItemId, ItemStatus, JobId, JobSnapshot, JobStatus, LinkInput};
+use crate::core::ratelimit::parse_rate;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::Router;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+
+const METHODS: &[&str] = &[
+    "aria2.addUri",
+    "aria2.remove",
+    "aria2.forceRemove",
+    "aria2.pause",
+    "aria2.forcePause",
+    "aria2.unpause",
+    "aria2.tellStatus",
+    "aria2.getFiles",
+    "aria2.tellActive",
+    "aria2.tellWaiting",
+    "aria2.tellStopped",
+    "aria2.changeGlobalOption",
+    "aria2.getGlobalStat",
+    "aria2.getVersion",
+    "system.multicall",
+    "system.listMethods",
+];
+
+/// aria2 的错误统一用 code 1
+struct Aria2Error(String);
+
+impl From<anyhow::Error> for Aria2Error {
+    fn from(e: anyhow::Error) -> Self {
+        Self(format!("{:#}", e))
+    }
+}
+
+type CallResult = Result<Value, Aria2Error>;
+
+/// Progress 事件里的实时进度；item 结束后移除，改用 store 中的数据
+#[derive(Default, Clone, Copy)]
+struct LiveItem {
+    downloaded: u64,
+    speed: u64,
+}
+
+struct Aria2 {
+    engine: Engine,
+    secret: Option<String>,
+    headers: HashMap<String, String>,
+    options: HashMap<String, String>,
+    live: Mutex<HashMap<ItemId, LiveItem>>,
+    /// 已编码的 aria2 通知，转发给所有 WebSocket 连接
+    notify_tx: broadcast::Sender<String>,
+}
+
+/// 在 addr 上提供 aria2 RPC；没有 secret 时只允许回环地址。
+/// 没有 secret 时也不开放 CORS，并拒绝带 Origin 的（浏览器发起的）请求：
+/// 否则用户打开的任何网页都能对本机 daemon 调用 addUri / remove。
+pub async fn serve(
+    engine: Engine,
+    addr: SocketAddr,
+    secret: Option<String>,
+    headers: HashMap<String, String>,
+    options: HashMap<String, String>,
+) -> anyhow::Result<()> {
+    if secret.is_none() && !addr.ip().is_loopback() {
+        anyhow::bail!("--aria2-listen on a non-loopback address requires --aria2-secret");
+    }
+    let listener = TcpListener::bind(addr).await?;
+    println!("aria2 RPC listening on http://{}/jsonrpc", listener.local_addr()?);
+
+    let (notify_tx, _) = broadcast::channel(256);
+    let state = Arc::new(Aria2 { engine, secret, headers, options, live: Mutex::new(HashMap::new()), notify_tx });
+    tokio::spawn(track_events(state.clone()));
+
+    let app = Router::new()
+        .route("/jsonrpc", post(http_rpc).get(ws_rpc).options(preflight))
+        .with_state(state);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+/// 维护实时进度，并把任务状态变化转成 aria2 通知
+async fn track_events(a: Arc<Aria2>) {
+    let mut rx = a.engine.subscribe();
+    loop {
+        let evt = match rx.recv().await {
+            Ok(e) => e,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        match evt {
+            EngineEvent::Progress { item_id, downloaded, speed_bps, .. } => {
+                a.live.lock().unwrap().insert(item_id, LiveItem { downloaded, speed: speed_bps });
+            }
+            EngineEvent::ItemStatusChanged { item_id, status: ItemStatus::Done | ItemStatus::Failed } => {
+                a.live.lock().unwrap().remove(&item_id);
+            }
+            EngineEvent::JobStatusChanged { job_id, status } => {
+                // 取消的任务不会再有 item 结束事件，在这里清掉它的实时进度
+                if status == JobStatus::Cancelled {
+                    if let Ok(items) = a.engine.list_items(Some(job_id)).await {
+                        let mut live = a.live.lock().unwrap();
+                        for it in items {
+                            live.remove(&it.item_id);
+                        }
+                    }
+                }
+                let method = match status {
+                    JobStatus::Running => "aria2.onDownloadStart",
+                    JobStatus::Paused => "aria2.onDownloadPause",
+                    JobStatus::Completed => "aria2.onDownloadComplete",
+                    JobStatus::Failed => "aria2.onDownloadError",
+                    JobStatus::Cancelled => "aria2.onDownloadStop",
+                    JobStatus::Pending => continue,
+                };
+                let note = json!({ "jsonrpc": "2.0", "method": method, "params": [{ "gid": gid(job_id) }] });
+                let _ = a.notify_tx.send(note.to_string());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 浏览器发起的请求（带 Origin）只在设置了 secret 时受理
+fn browser_allowed(a: &Aria2, headers: &HeaderMap) -> bool {
+    a.secret.is_some() || !headers.contains_key(header::ORIGIN)
+}
+
+fn forbidden() -> Response {
+    (StatusCode::FORBIDDEN, "cross-origin requests require --aria2-secret").into_response()
+}
+
+/// 只在设置了 secret 时附加 CORS 头
+fn with_cors(a: &Aria2, mut resp: Response) -> Response {
+    if a.secret.is_none() {
+        return resp;
+    }
+    let h = resp.headers_mut();
+    h.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+    h.insert(header::ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static("POST, GET, OPTIONS"));
+    h.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, HeaderValue::from_static("Content-Type"));
+    resp
+}
+
+async fn preflight(State(a): State<Arc<Aria2>>) -> Response {
+    if a.secret.is_none() {
+        return forbidden();
+    }
+    with_cors(&a, ().into_response())
+}
+
+async fn http_rpc(State(a): State<Arc<Aria2>>, headers: HeaderMap, body: String) -> Response {
+    if !browser_allowed(&a, &headers) {
+        return forbidden();
+    }
+    let resp = a.handle_text(&body).await;
+    with_cors(&a, ([(header::CONTENT_TYPE, "application/json")], resp).into_response())
+}
+
+/// WebSocket 不受 CORS 约束，同样按 Origin 判断
+async fn ws_rpc(State(a): State<Arc<Aria2>>, headers: HeaderMap, ws: WebSocketUpgrade) -> Response {
+    if !browser_allowed(&a, &headers) {
+        return forbidden();
+    }
+    ws.on_upgrade(move |socket| ws_session(a, socket))
+}
+
+async fn ws_session(a: Arc<Aria2>, mut socket: WebSocket) {
+    let mut notes = a.notify_tx.subscribe();
+    loop {
+        tokio::select! {
+            msg = socket.recv() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(t))) => t,
+                    Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                    Some(Ok(_)) => continue,
+                };
+                if socket.send(Message::Text(a.handle_text(&text).await)).await.is_err() {
+                    break;
+                }
+            }
+            note = notes.recv() => match note {
+                Ok(n) => {
+                    if socket.send(Message::Text(n)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+}
+
+/// GID 取 job id 的前 16 个十六进制字符（aria2 的 GID 是 64 位）
+fn gid(job_id: JobId) -> String {
+    job_id.simple().to_string()[..16].to_string()
+}
+
+fn aria2_status(s: JobStatus) -> &'static str {
+    match s {
+        JobStatus::Pending => "waiting",
+        JobStatus::Running => "active",
+        JobStatus::Paused => "paused",
+        JobStatus::Completed => "complete",
+        JobStatus::Failed => "error",
+        JobStatus::Cancelled => "removed",
+    }
+}
+
+fn error_response(id: Value, e: Aria2Error) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": 1, "message": e.0 } })
+}
+
+impl Aria2 {
+    /// 处理一条请求或批量请求
+    async fn handle_text(&self, text: &str) -> String {
+        let req: Value = match serde_json::from_str(text) {
+            Ok(v) => v,
+            Err(e) => {
+                return json!({ "jsonrpc": "2.0", "id": null, "error": { "code": -32700, "message": e.to_string() } })
+                    .to_string()
+            }
+        };
+        match req {
+            Value::Array(batch) => {
+                let mut out = vec![];
+                for r in batch {
+                    out.push(self.handle_request(r).await);
+                }
+                Value::Array(out).to_string()
+            }
+            r => self.handle_request(r).await.to_string(),
+        }
+    }
+
+    async fn handle_request(&self, req: Value) -> Value {
+        let id = req.get("id").cloned().unwrap_or(Value::Null);
+        let Some(method) = req.get("method").and_then(Value::as_str) else {
+            return json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32600, "message": "Invalid Request." } });
+        };
+        let params = match req.get("params") {
+            Some(Value::Array(p)) => p.clone(),
+            None => vec![],
+            Some(_) => return error_response(id, Aria2Error("params must be an array".to_string())),
+        };
+        match self.call(method, params).await {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(e) => error_response(id, e),
+        }
+    }
+
+    /// 去掉并校验 `token:<secret>` 参数
+    fn check_token(&self, params: &mut Vec<Value>) -> Result<(), Aria2Error> {
+        let token = match params.first().and_then(Value::as_str) {
+            Some(t) if t.starts_with("token:") => {
+                let t = t["token:".len()..].to_string();
+                params.remove(0);
+                Some(t)
+            }
+            _ => None,
+        };
+        match &self.secret {
+            Some(s) if !token.is_some_and(|t| bool::from(t.as_bytes().ct_eq(s.as_bytes()))) => {
+                Err(Aria2Error("Unauthorized".to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn call(&self, method: &str, params: Vec<Value>) -> CallResult {
+        // system.multicall 里的每个调用各自带 token
+        match method {
+            "system.listMethods" => return Ok(json!(METHODS)),
+            "system.multicall" => return self.multicall(params).await,
+            _ => {}
+        }
+        let mut params = params;
+        self.check_token(&mut params)?;
+
+        match method {
+            "aria2.addUri" => self.add_uri(&params).await,
+            "aria2.remove" | "aria2.forceRemove" => {
+                let job = self.find_job(&params).await?;
+                self.engine.cancel_job(job.job_id).await?;
+                Ok(json!(gid(job.job_id)))
+            }
+            "aria2.pause" | "aria2.forcePause" => {
+                let job = self.find_job(&params).await?;
+                self.engine.pause_job(job.job_id).await?;
+                Ok(json!(gid(job.job_id)))
+            }
+            "aria2.unpause" => {
+                let job = self.find_job(&params).await?;
+                self.engine.resume_job(job.job_id).await?;
+                Ok(json!(gid(job.job_id)))
+            }
+            "aria2.tellStatus" => {
+                let job = self.find_job(&params).await?;
+                self.status(&job, params.get(1)).await
+            }
+            "aria2.getFiles" => {
+                let job = self.find_job(&params).await?;
+                let st = self.status(&job, None).await?;
+                Ok(st["files"].clone())
+            }
+            "aria2.tellActive" => {
+                let jobs = self.jobs_where(|s| s == JobStatus::Running).await?;
+                self.statuses(&jobs, params.first()).await
+            }
+            "aria2.tellWaiting" => {
+                let jobs = self.jobs_where(|s| matches!(s, JobStatus::Pending | JobStatus::Paused)).await?;
+                self.statuses(&page(&jobs, &params)?, params.get(2)).await
+            }
+            "aria2.tellStopped" => {
+                let jobs = self
+                    .jobs_where(|s| matches!(s, JobStatus::Completed | JobStatus::Failed | JobStatus::Cancelled))
+                    .await?;
+                self.statuses(&page(&jobs, &params)?, params.get(2)).await
+            }
+            "aria2.changeGlobalOption" => {
+                let opts = params.first().and_then(Value::as_object).ok_or_else(|| Aria2Error("options required".to_string()))?;
+                if let Some(v) = opts.get("max-overall-download-limit").and_then(Value::as_str) {
+                    self.engine.set_speed_limit(parse_rate(v)?);
+                }
+                // 一个 aria2 下载是一个 job，这里限制的是同时运行的 job 数
+                if let Some(v) = opts.get("max-concurrent-downloads").and_then(Value::as_str) {
+                    let n = v.parse().map_err(|_| Aria2Error(format!("invalid max-concurrent-downloads: {}", v)))?;
+                    self.engine.set_max_active_jobs(n);
+                }
+                Ok(json!("OK"))
+            }
+            "aria2.getGlobalStat" => self.global_stat().await,
+            "aria2.getVersion" => Ok(json!({ "version": env!("CARGO_PKG_VERSION"), "enabledFeatures": [] })),
+            _ => Err(Aria2Error(format!("No such method: {}", method))),
+        }
+    }
+
+    async fn multicall(&self, params: Vec<Value>) -> CallResult {
+        let calls = params.first().and_then(Value::as_array).ok_or_else(|| Aria2Error("expected an array of calls".to_string()))?;
+        let mut out = vec![];
+        for c in calls {
+            let name = c.get("methodName").and_then(Value::as_str).unwrap_or_default();
+            let p = c.get("params").and_then(Value::as_array).cloned().unwrap_or_default();
+            // 与 aria2 一致：成功结果包一层数组，失败返回错误对象
+            let r = match name {
+                "system.multicall" => Err(Aria2Error("Recursive system.multicall forbidden.".to_string())),
+                _ => Box::pin(self.call(name, p)).await,
+            };
+            out.push(match r {
+                Ok(v) => json!([v]),
+                Err(e) => json!({ "code": 1, "message": e.0 }),
+            });
+        }
+        Ok(Value::Array(out))
+    }
+
+    /// aria2.addUri(uris[, options[, position]])：uris 指向同一个文件，第一个为主地址，其余作为镜像
+    async fn add_uri(&self, params: &[Value]) -> CallResult {
+        let uris: Vec<&str> = params
+            .first()
+            .and_then(Value::as_array)
+            .map(|u| u.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        let (uri, mirrors) = uris.split_first().ok_or_else(|| Aria2Error("No URI to download.".to_string()))?;
+        let opts = params.get(1).and_then(Value::as_object);
+
+        let mut headers = self.headers.clone();
+        let mut options = self.options.clone();
+        if !mirrors.is_empty() {
+            options.insert("mirrors".to_string(), mirrors.join(" "));
+        }
+        if let Some(opts) = opts {
+            // header 可以是字符串或字符串数组，格式 "Name: value"
+            let hs: Vec<&str> = match opts.get("header") {
+                Some(Value::String(h)) => vec![h.as_str()],
+                Some(Value::Array(a)) => a.iter().filter_map(Value::as_str).collect(),
+                _ => vec![],
+            };
+            for h in hs {
+                let (k, v) = h.split_once(':').ok_or_else(|| Aria2Error(format!("invalid header: {}", h)))?;
+                headers.insert(k.trim().to_string(), v.trim().to_string());
+            }
+            // aria2 的 checksum 选项同样是 "sha-256=<hex>"
+            if let Some(c) = opts.get("checksum").and_then(Value::as_str) {
+                crate::core::checksum::parse_checksum_list(c)?;
+                options.insert("checksum".to_string(), c.to_string());
+            }
+            // 目标文件名 / 目录，engine 按 options["out"] / options["dir"] 处理
+            for key in ["out", "dir"] {
+                if let Some(v) = opts.get(key).and_then(Value::as_str) {
+                    options.insert(key.to_string(), v.to_string());
+                }
+            }
+        }
+
+        let job_id = self
+            .engine
+            .add_and_start(vec![LinkInput { raw: uri.to_string(), headers, options }])
+            .await?;
+        Ok(json!(gid(job_id)))
+    }
+
+    async fn find_job(&self, params: &[Value]) -> Result<JobSnapshot, Aria2Error> {
+        let g = params.first().and_then(Value::as_str).ok_or_else(|| Aria2Error("GID required".to_string()))?;
+        self.engine
+            .list_jobs()
+            .await?
+            .into_iter()
+            .find(|j| gid(j.job_id) == g)
+            .ok_or_else(|| Aria2Error(format!("GID {} is not found", g)))
+    }
+
+    async fn jobs_where(&self, f: impl Fn(JobStatus) -> bool) -> Result<Vec<JobSnapshot>, Aria2Error> {
+        Ok(self.engine.list_jobs().await?.into_iter().filter(|j| f(j.status)).collect())
+    }
+
+    async fn statuses(&self, jobs: &[JobSnapshot], keys: Option<&Value>) -> CallResult {
+        let mut out = vec![];
+        for j in jobs {
+            out.push(self.status(j, keys).await?);
+        }
+        Ok(Value::Array(out))
+    }
+
+    /// aria2 的数字字段都是十进制字符串；keys 给出时只返回这些字段
+    async fn status(&self, job: &JobSnapshot, keys: Option<&Value>) -> CallResult {
+        let items = self.engine.list_items(Some(job.job_id)).await?;
+        let live = self.live.lock().unwrap().clone();
+
+        let (mut total, mut completed, mut speed, mut connections) = (0u64, 0u64, 0u64, 0u64);
+        let mut files = vec![];
+        for (i, it) in items.iter().enumerate() {
+            let l = live.get(&it.item_id);
+            let done = l.map(|l| l.downloaded).unwrap_or(it.downloaded);
+            let length = it.total.unwrap_or(0);
+            total += length;
+            completed += done;
+            if let Some(l) = l {
+                speed += l.speed;
+                connections += 1;
+            }
+            files.push(json!({
+                "index": (i + 1).to_string(),
+                "path": it.target_path.display().to_string(),
+                "length": length.to_string(),
+                "completedLength": done.to_string(),
+                "selected": "true",
+                "uris": [{ "uri": it.uri, "status": "used" }],
+            }));
+        }
+
+        let mut st = json!({
+            "gid": gid(job.job_id),
+            "status": aria2_status(job.status),
+            "totalLength": total.to_string(),
+            "completedLength": completed.to_string(),
+            "uploadLength": "0",
+            "downloadSpeed": speed.to_string(),
+            "uploadSpeed": "0",
+            "connections": connections.to_string(),
+            "dir": self.engine.out_dir().display().to_string(),
+            "files": files,
+        });
+        if job.status == JobStatus::Failed {
+            st["errorCode"] = json!("1");
+        }
+
+        if let Some(keys) = keys.and_then(Value::as_array) {
+            let obj = st.as_object().cloned().unwrap_or_default();
+            let filtered: Map<String, Value> = obj
+                .into_iter()
+                .filter(|(k, _)| keys.iter().any(|x| x.as_str() == Some(k.as_str())))
+                .collect();
+            return Ok(Value::Object(filtered));
+        }
+        Ok(st)
+    }
+
+    async fn global_stat(&self) -> CallResult {
+        let jobs = self.engine.list_jobs().await?;
+        let count = |f: &dyn Fn(JobStatus) -> bool| jobs.iter().filter(|j| f(j.status)).count().to_string();
+        let speed: u64 = self.live.lock().unwrap().values().map(|l| l.speed).sum();
+        let stopped = count(&|s| matches!(s, JobStatus::Completed | JobStatus::Failed | JobStatus::Cancelled));
+        Ok(json!({
+            "downloadSpeed": speed.to_string(),
+            "uploadSpeed": "0",
+            "numActive": count(&|s| s == JobStatus::Running),
+            "numWaiting": count(&|s| matches!(s, JobStatus::Pending | JobStatus::Paused)),
+            "numStopped": stopped,
+            "numStoppedTotal": stopped,
+        }))
+    }
+}
+
+/// tellWaiting / tellStopped 的 (offset, num)；负 offset 从末尾倒数
+fn page(jobs: &[JobSnapshot], params: &[Value]) -> Result<Vec<JobSnapshot>, Aria2Error> {
+    let offset = params.first().and_then(Value::as_i64).ok_or_else(|| Aria2Error("offset required".to_string()))?;
+    let num = params.get(1).and_then(Value::as_u64).ok_or_else(|| Aria2Error("num required".to_string()))? as usize;
+    if offset >= 0 {
+        Ok(jobs.iter().skip(offset as usize).take(num).cloned().collect())
+    } else {
+        // 负 offset：从末尾往前取，顺序也倒过来
+        let start = jobs.len() as i64 + offset;
+        if start < 0 {
+            return Ok(vec![]);
+        }
+        Ok(jobs[..=start as usize].iter().rev().take(num).cloned().collect())
+    }
+}
@@ -0,0 +1,10 @@
+//! daemon 子命令：常驻一个 Engine，通过 JSON-RPC 2.0 控制（Unix socket，可选本机 TCP）。
+//! 每行一个 JSON 请求 / 响应；subscribe 之后该连接还会收到 `event` 通知。
+//! 另可开启 aria2 兼容的 HTTP/WebSocket 接口（见 aria2.rs）和 REST + SSE 接口（见 rest.rs）。
+
+mod aria2;
+mod rest;
+mod rpc;
+mod server;
+
+pub use server::{serve, DaemonOptions};
@@ -0,0 +1,336 @@
+//! REST + Server-Sent Events 接口，供 web 控制台使用。
+//! 所有请求都要带 `Authorization: Bearer <token>`；浏览器的 EventSource 不能设置请求头，
+//! 因此 `GET /events` 也接受 `?token=<token>`。
+
+use crate::core::engine::Engine;
+use crate::core::model::{ItemId, JobId, JobSnapshot, LinkInput};
+use axum::extract::{Path, Request, State};
+use axum::http::{header, HeaderValue, Method, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::Stream;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+
+struct Rest {
+    engine: Engine,
+    token: String,
+    /// daemon 启动参数给出的默认值，请求里的同名项优先
+    headers: HashMap<String, String>,
+    options: HashMap<String, String>,
+}
+
+/// 以 `{"error": "..."}` 返回的错误
+struct ApiError(StatusCode, String);
+
+impl ApiError {
+    fn bad_request(msg: impl Into<String>) -> Self {
+        Self(StatusCode::BAD_REQUEST, msg.into())
+    }
+
+    fn not_found(what: &str, id: impl std::fmt::Display) -> Self {
+        Self(StatusCode::NOT_FOUND, format!("{} not found: {}", what, id))
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        Self(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+type ApiResult = Result<Response, ApiError>;
+
+#[derive(Deserialize)]
+struct AddJob {
+    links: Vec<String>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    options: HashMap<String, String>,
+}
+
+/// 在 addr 上提供 REST API
+pub async fn serve(
+    engine: Engine,
+    addr: SocketAddr,
+    token: String,
+    headers: HashMap<String, String>,
+    options: HashMap<String, String>,
+) -> anyhow::Result<()> {
+    if token.is_empty() {
+        anyhow::bail!("--http-token must not be empty");
+    }
+    let listener = TcpListener::bind(addr).await?;
+    println!("HTTP API listening on http://{}", listener.local_addr()?);
+
+    let app = router(Rest { engine, token, headers, options });
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+fn router(rest: Rest) -> Router {
+    let state = Arc::new(rest);
+    Router::new()
+        .route("/jobs", get(list_jobs).post(add_job))
+        .route("/jobs/:id", get(get_job).delete(cancel_job))
+        .route("/jobs/:id/pause", post(pause_job))
+        .route("/jobs/:id/resume", post(resume_job))
+        .route("/items/:id", get(get_item))
+        .route("/events", get(events))
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth))
+        .layer(middleware::from_fn(cors))
+        .with_state(state)
+}
+
+async fn auth(State(r): State<Arc<Rest>>, req: Request, next: Next) -> Response {
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::to_string);
+    // ?token= 按 application/x-www-form-urlencoded 解码，token 里可以有保留字符
+    let query = req.uri().query().and_then(|q| {
+        url::form_urlencoded::parse(q.as_bytes())
+            .find(|(k, _)| k == "token")
+            .map(|(_, v)| v.into_owned())
+    });
+    if !bearer.or(query).is_some_and(|t| token_matches(&t, &r.token)) {
+        return ApiError(StatusCode::UNAUTHORIZED, "missing or invalid token".to_string()).into_response();
+    }
+    next.run(req).await
+}
+
+/// 常数时间比较，不从响应时间泄露 token 的前缀
+fn token_matches(given: &str, expected: &str) -> bool {
+    given.as_bytes().ct_eq(expected.as_bytes()).into()
+}
+
+/// 控制台可能不和 daemon 同源；预检请求不需要 token
+async fn cors(req: Request, next: Next) -> Response {
+    let mut resp = if req.method() == Method::OPTIONS {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        next.run(req).await
+    };
+    let h = resp.headers_mut();
+    h.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+    h.insert(header::ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static("GET, POST, DELETE, OPTIONS"));
+    h.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, HeaderValue::from_static("Authorization, Content-Type"));
+    resp
+}
+
+fn parse_id(s: &str) -> Result<uuid::Uuid, ApiError> {
+    uuid::Uuid::parse_str(s).map_err(|e| ApiError::bad_request(format!("invalid id {}: {}", s, e)))
+}
+
+impl Rest {
+    async fn find_job(&self, id: &str) -> Result<JobSnapshot, ApiError> {
+        let job_id: JobId = parse_id(id)?;
+        self.engine
+            .list_jobs()
+            .await?
+            .into_iter()
+            .find(|j| j.job_id == job_id)
+            .ok_or_else(|| ApiError::not_found("job", job_id))
+    }
+
+    /// pause / resume / cancel 只对本进程中运行的任务有效，已结束的任务返回 409
+    async fn control(&self, id: &str, action: &str) -> ApiResult {
+        let job = self.find_job(id).await?;
+        let r = match action {
+            "pause" => self.engine.pause_job(job.job_id).await,
+            "resume" => self.engine.resume_job(job.job_id).await,
+            _ => self.engine.cancel_job(job.job_id).await,
+        };
+        r.map_err(|e| ApiError(StatusCode::CONFLICT, format!("{:#}", e)))?;
+        let status = self.engine.job_status(job.job_id).await.unwrap_or(job.status);
+        Ok(Json(json!({ "job_id": job.job_id, "status": status })).into_response())
+    }
+}
+
+async fn add_job(State(r): State<Arc<Rest>>, body: String) -> ApiResult {
+    let p: AddJob = serde_json::from_str(&body).map_err(|e| ApiError::bad_request(e.to_string()))?;
+    if p.links.is_empty() {
+        return Err(ApiError::bad_request("links must not be empty"));
+    }
+    let mut headers = r.headers.clone();
+    headers.extend(p.headers);
+    let mut options = r.options.clone();
+    options.extend(p.options);
+
+    let inputs = p
+        .links
+        .into_iter()
+        .map(|raw| LinkInput { raw, headers: headers.clone(), options: options.clone() })
+        .collect();
+    let job_id = r.engine.add_and_start(inputs).await?;
+    Ok((StatusCode::CREATED, Json(json!({ "job_id": job_id }))).into_response())
+}
+
+async fn list_jobs(State(r): State<Arc<Rest>>) -> ApiResult {
+    Ok(Json(r.engine.list_jobs().await?).into_response())
+}
+
+/// 任务本身及其已解析出的 item
+async fn get_job(State(r): State<Arc<Rest>>, Path(id): Path<String>) -> ApiResult {
+    let job = r.find_job(&id).await?;
+    let items = r.engine.list_items(Some(job.job_id)).await?;
+    let mut v = serde_json::to_value(job).map_err(anyhow::Error::from)?;
+    v["items"] = json!(items);
+    Ok(Json(v).into_response())
+}
+
+async fn cancel_job(State(r): State<Arc<Rest>>, Path(id): Path<String>) -> ApiResult {
+    r.control(&id, "cancel").await
+}
+
+async fn pause_job(State(r): State<Arc<Rest>>, Path(id): Path<String>) -> ApiResult {
+    r.control(&id, "pause").await
+}
+
+async fn resume_job(State(r): State<Arc<Rest>>, Path(id): Path<String>) -> ApiResult {
+    r.control(&id, "resume").await
+}
+
+async fn get_item(State(r): State<Arc<Rest>>, Path(id): Path<String>) -> ApiResult {
+    let item_id: ItemId = parse_id(&id)?;
+    let item = r.engine.get_item(item_id).await?.ok_or_else(|| ApiError::not_found("item", item_id))?;
+    Ok(Json(item).into_response())
+}
+
+/// 引擎事件流：SSE 的 event 名即事件的 `type`；客户端读得慢时发送 `lagged` 并跳过积压的事件
+async fn events(State(r): State<Arc<Rest>>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let rx = r.engine.subscribe();
+    let stream = futures::stream::unfold(rx, |mut rx| async move {
+        let event = match rx.recv().await {
+            Ok(evt) => {
+                let v = serde_json::to_value(&evt).unwrap_or(Value::Null);
+                let name = v["type"].as_str().unwrap_or("event").to_string();
+                Event::default().event(name).data(v.to_string())
+            }
+            Err(RecvError::Lagged(n)) => Event::default().event("lagged").data(json!({ "skipped": n }).to_string()),
+            Err(RecvError::Closed) => return None,
+        };
+        Some((Ok(event), rx))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::model::JobStatus;
+    use crate::core::testutil::{test_data, test_dir, test_engine, MockDriver, RangeMode};
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    /// token 里带 `+` `/` `=`，放进查询串时必须百分号编码
+    const TOKEN: &str = "s3cr+t/=";
+
+    async fn app() -> (tempfile::TempDir, Engine, Router) {
+        let dir = test_dir();
+        let engine = test_engine(MockDriver::new(test_data(1000), RangeMode::Serve), dir.path(), 1).await;
+        let app = router(Rest { engine: engine.clone(), token: TOKEN.to_string(), headers: HashMap::new(), options: HashMap::new() });
+        (dir, engine, app)
+    }
+
+    async fn send(app: &Router, method: Method, uri: &str, token: Option<&str>, body: &str) -> (StatusCode, Value) {
+        let mut req = Request::builder().method(method).uri(uri);
+        if let Some(t) = token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {}", t));
+        }
+        let resp = app.clone().oneshot(req.body(Body::from(body.to_string())).unwrap()).await.unwrap();
+        let status = resp.status();
+        let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn requests_need_a_valid_token() {
+        let (_dir, _engine, app) = app().await;
+        assert_eq!(send(&app, Method::GET, "/jobs", None, "").await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(send(&app, Method::GET, "/jobs", Some("s3cr+t/"), "").await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(send(&app, Method::GET, "/jobs", Some(TOKEN), "").await, (StatusCode::OK, json!([])));
+
+        // ?token= 按表单编码解码；未编码的 `+` 会变成空格，不能通过
+        assert_eq!(send(&app, Method::GET, "/jobs?token=s3cr+t/=", None, "").await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(send(&app, Method::GET, "/jobs?token=s3cr%2Bt%2F%3D", None, "").await.0, StatusCode::OK);
+        let resp = app
+            .clone()
+            .oneshot(Request::builder().uri("/events?x=1&token=s3cr%2Bt%2F%3D").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "text/event-stream");
+    }
+
+    #[tokio::test]
+    async fn cors_preflight_needs_no_token() {
+        let (_dir, _engine, app) = app().await;
+        let req = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/jobs")
+            .header(header::ORIGIN, "http://console.test")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(resp.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(resp.headers()[header::ACCESS_CONTROL_ALLOW_HEADERS].to_str().unwrap().contains("Authorization"));
+    }
+
+    #[tokio::test]
+    async fn unknown_and_malformed_ids() {
+        let (_dir, _engine, app) = app().await;
+        let missing = uuid::Uuid::new_v4();
+        for uri in [format!("/jobs/{}", missing), format!("/items/{}", missing)] {
+            assert_eq!(send(&app, Method::GET, &uri, Some(TOKEN), "").await.0, StatusCode::NOT_FOUND, "{}", uri);
+        }
+        assert_eq!(send(&app, Method::POST, &format!("/jobs/{}/pause", missing), Some(TOKEN), "").await.0, StatusCode::NOT_FOUND);
+        let (status, body) = send(&app, Method::GET, "/jobs/not-a-uuid", Some(TOKEN), "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains("invalid id"));
+        assert_eq!(send(&app, Method::DELETE, "/jobs/123", Some(TOKEN), "").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(send(&app, Method::POST, "/jobs", Some(TOKEN), r#"{"links":[]}"#).await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn pausing_a_finished_job_conflicts() {
+        let (_dir, engine, app) = app().await;
+        let (status, body) = send(&app, Method::POST, "/jobs", Some(TOKEN), r#"{"links":["mock://host/done.bin"]}"#).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let job_id: JobId = serde_json::from_value(body["job_id"].clone()).unwrap();
+
+        for _ in 0..200 {
+            if engine.job_status(job_id).await == Some(JobStatus::Completed) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let (status, body) = send(&app, Method::GET, &format!("/jobs/{}", job_id), Some(TOKEN), "").await;
+        assert_eq!((status, body["status"].as_str()), (StatusCode::OK, Some("Completed")));
+        assert_eq!(body["items"].as_array().map(Vec::len), Some(1));
+
+        let (status, body) = send(&app, Method::POST, &format!("/jobs/{}/pause", job_id), Some(TOKEN), "").await;
+        assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+    }
+}
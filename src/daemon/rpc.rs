@@ -0,0 +1,389 @@
+use crate::core::engine::Engine;
+use crate::core::model::{ItemId, JobId, LinkInput};
+use crate::core::ratelimit::parse_rate;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// JSON-RPC 2.0 错误码
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// 引擎返回的错误（任务不存在等）
+const ENGINE_ERROR: i64 = -32000;
+
+#[derive(Debug)]
+pub struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::new(ENGINE_ERROR, format!("{:#}", e))
+    }
+}
+
+/// 一条连接的状态：响应和事件通知都经 out 写回
+pub struct Session {
+    out: mpsc::Sender<String>,
+    events: Option<JoinHandle<()>>,
+}
+
+impl Session {
+    pub fn new(out: mpsc::Sender<String>) -> Self {
+        Self { out, events: None }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Some(h) = self.events.take() {
+            h.abort();
+        }
+    }
+}
+
+/// 速率既可以是字节数，也可以是 `2M` 这样的字符串
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Rate {
+    Bytes(u64),
+    Text(String),
+}
+
+impl Rate {
+    fn bytes_per_sec(&self) -> Result<u64, RpcError> {
+        match self {
+            Rate::Bytes(n) => Ok(*n),
+            Rate::Text(s) => parse_rate(s).map_err(|e| RpcError::new(INVALID_PARAMS, format!("{:#}", e))),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AddParams {
+    links: Vec<String>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    options: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct JobParams {
+    job_id: JobId,
+}
+
+#[derive(Deserialize)]
+struct ListItemsParams {
+    job_id: Option<JobId>,
+}
+
+#[derive(Deserialize)]
+struct SetLimitsParams {
+    max_speed: Option<Rate>,
+    max_active_items: Option<usize>,
+    #[serde(default)]
+    hosts: HashMap<String, Rate>,
+    #[serde(default)]
+    items: HashMap<ItemId, Rate>,
+}
+
+pub struct Rpc {
+    engine: Engine,
+    /// daemon 启动参数给出的默认值，add 请求里的同名项优先
+    headers: HashMap<String, String>,
+    options: HashMap<String, String>,
+}
+
+impl Rpc {
+    pub fn new(engine: Engine, headers: HashMap<String, String>, options: HashMap<String, String>) -> Self {
+        Self { engine, headers, options }
+    }
+
+    /// 处理一行输入（单个请求或批量请求）；全是通知时没有响应
+    pub async fn handle_line(&self, line: &str, session: &mut Session) -> Option<String> {
+        let req: Value = match serde_json::from_str(line) {
+            Ok(v) => v,
+            Err(e) => return Some(error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string())).to_string()),
+        };
+
+        match req {
+            Value::Array(batch) if !batch.is_empty() => {
+                let mut out = vec![];
+                for r in batch {
+                    if let Some(resp) = self.handle_request(r, session).await {
+                        out.push(resp);
+                    }
+                }
+                (!out.is_empty()).then(|| Value::Array(out).to_string())
+            }
+            r => self.handle_request(r, session).await.map(|v| v.to_string()),
+        }
+    }
+
+    async fn handle_request(&self, req: Value, session: &mut Session) -> Option<Value> {
+        let Value::Object(mut obj) = req else {
+            return Some(error_response(Value::Null, RpcError::new(INVALID_REQUEST, "request must be an object")));
+        };
+        // 没有 id 的是通知：照常执行，但不回复
+        let id = obj.remove("id");
+        let method = match obj.remove("method") {
+            Some(Value::String(m)) if obj.get("jsonrpc").and_then(Value::as_str) == Some("2.0") => m,
+            _ => {
+                return Some(error_response(
+                    id.unwrap_or(Value::Null),
+                    RpcError::new(INVALID_REQUEST, "expected jsonrpc 2.0 request with a method"),
+                ))
+            }
+        };
+        let params = obj.remove("params").unwrap_or(Value::Null);
+
+        let r = self.call(&method, params, session).await;
+        let id = id?;
+        Some(match r {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(e) => error_response(id, e),
+        })
+    }
+
+    async fn call(&self, method: &str, params: Value, session: &mut Session) -> Result<Value, RpcError> {
+        match method {
+            "add" => {
+                let p: AddParams = parse_params(params)?;
+                if p.links.is_empty() {
+                    return Err(RpcError::new(INVALID_PARAMS, "links must not be empty"));
+                }
+                let mut headers = self.headers.clone();
+                headers.extend(p.headers);
+                let mut options = self.options.clone();
+                options.extend(p.options);
+
+                let inputs = p
+                    .links
+                    .into_iter()
+                    .map(|raw| LinkInput { raw, headers: headers.clone(), options: options.clone() })
+                    .collect();
+                let job_id = self.engine.add_and_start(inputs).await?;
+                Ok(json!({ "job_id": job_id }))
+            }
+            "list_jobs" => Ok(json!(self.engine.list_jobs().await?)),
+            "list_items" => {
+                let p: ListItemsParams = parse_params(params)?;
+                Ok(json!(self.engine.list_items(p.job_id).await?))
+            }
+            "pause" | "resume" | "cancel" => {
+                let p: JobParams = parse_params(params)?;
+                match method {
+                    "pause" => self.engine.pause_job(p.job_id).await?,
+                    "resume" => self.engine.resume_job(p.job_id).await?,
+                    _ => self.engine.cancel_job(p.job_id).await?,
+                }
+                Ok(json!({ "job_id": p.job_id, "status": self.engine.job_status(p.job_id).await }))
+            }
+            "set_limits" => {
+                let p: SetLimitsParams = parse_params(params)?;
+                // 先全部校验再生效，避免只改了一半
+                let max_speed = p.max_speed.as_ref().map(Rate::bytes_per_sec).transpose()?;
+                let hosts = p
+                    .hosts
+                    .iter()
+                    .map(|(h, r)| Ok((h, r.bytes_per_sec()?)))
+                    .collect::<Result<Vec<_>, RpcError>>()?;
+                let items = p
+                    .items
+                    .iter()
+                    .map(|(id, r)| Ok((*id, r.bytes_per_sec()?)))
+                    .collect::<Result<Vec<_>, RpcError>>()?;
+
+                if let Some(bps) = max_speed {
+                    self.engine.set_speed_limit(bps);
+                }
+                if let Some(n) = p.max_active_items {
+                    self.engine.set_max_active_items(n);
+                }
+                for (host, bps) in hosts {
+                    self.engine.set_host_speed_limit(host, bps).await;
+                }
+                for (item_id, bps) in items {
+                    self.engine.set_item_speed_limit(item_id, bps).await;
+                }
+                Ok(json!(true))
+            }
+            "subscribe" => {
+                if session.events.is_none() {
+                    session.events = Some(forward_events(&self.engine, session.out.clone()));
+                }
+                Ok(json!(true))
+            }
+            "unsubscribe" => {
+                if let Some(h) = session.events.take() {
+                    h.abort();
+                }
+                Ok(json!(true))
+            }
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("method not found: {}", method))),
+        }
+    }
+}
+
+/// 把引擎事件作为 `event` 通知写回连接；客户端读得慢时跳过积压的事件
+fn forward_events(engine: &Engine, out: mpsc::Sender<String>) -> JoinHandle<()> {
+    let mut rx = engine.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let evt = match rx.recv().await {
+                Ok(e) => e,
+                Err(RecvError::Lagged(n)) => {
+                    let note = json!({ "jsonrpc": "2.0", "method": "events_lagged", "params": { "skipped": n } });
+                    if out.send(note.to_string()).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            let note = json!({ "jsonrpc": "2.0", "method": "event", "params": evt });
+            if out.send(note.to_string()).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// 省略 params 等同于 `{}`
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn error_response(id: Value, e: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": e.code, "message": e.message } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::{test_dir, test_engine, MockDriver, RangeMode};
+
+    async fn rpc() -> (tempfile::TempDir, Rpc, Session) {
+        let dir = test_dir();
+        let engine = test_engine(MockDriver::new(vec![], RangeMode::Serve), dir.path(), 1).await;
+        let (tx, _rx) = mpsc::channel(16);
+        (dir, Rpc::new(engine, HashMap::new(), HashMap::new()), Session::new(tx))
+    }
+
+    async fn call(rpc: &Rpc, session: &mut Session, line: &str) -> Value {
+        let resp = rpc.handle_line(line, session).await.expect("response");
+        serde_json::from_str(&resp).unwrap()
+    }
+
+    fn error_code(resp: &Value) -> i64 {
+        resp["error"]["code"].as_i64().unwrap_or_else(|| panic!("not an error: {}", resp))
+    }
+
+    #[tokio::test]
+    async fn malformed_requests_get_protocol_errors() {
+        let (_dir, rpc, mut s) = rpc().await;
+
+        let r = call(&rpc, &mut s, r#"{"jsonrpc":"2.0","id":1,"method":"#).await;
+        assert_eq!((error_code(&r), &r["id"]), (PARSE_ERROR, &Value::Null));
+
+        let r = call(&rpc, &mut s, "42").await;
+        assert_eq!(error_code(&r), INVALID_REQUEST);
+        // 缺少 jsonrpc 字段：仍按原 id 回复
+        let r = call(&rpc, &mut s, r#"{"id":7,"method":"list_jobs"}"#).await;
+        assert_eq!((error_code(&r), r["id"].as_i64()), (INVALID_REQUEST, Some(7)));
+
+        let r = call(&rpc, &mut s, r#"{"jsonrpc":"2.0","id":2,"method":"frobnicate"}"#).await;
+        assert_eq!(error_code(&r), METHOD_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn bad_params_are_rejected() {
+        let (_dir, rpc, mut s) = rpc().await;
+        for line in [
+            r#"{"jsonrpc":"2.0","id":1,"method":"pause","params":{"job_id":"not-a-uuid"}}"#,
+            r#"{"jsonrpc":"2.0","id":1,"method":"pause"}"#,
+            r#"{"jsonrpc":"2.0","id":1,"method":"add","params":{"links":[]}}"#,
+            r#"{"jsonrpc":"2.0","id":1,"method":"add","params":["mock://host/a"]}"#,
+        ] {
+            assert_eq!(error_code(&call(&rpc, &mut s, line).await), INVALID_PARAMS, "{}", line);
+        }
+        // 参数合法但任务不存在：引擎错误
+        let line = format!(r#"{{"jsonrpc":"2.0","id":1,"method":"pause","params":{{"job_id":"{}"}}}}"#, uuid::Uuid::new_v4());
+        assert_eq!(error_code(&call(&rpc, &mut s, &line).await), ENGINE_ERROR);
+    }
+
+    #[tokio::test]
+    async fn notifications_get_no_response() {
+        let (_dir, rpc, mut s) = rpc().await;
+        assert!(rpc.handle_line(r#"{"jsonrpc":"2.0","method":"list_jobs"}"#, &mut s).await.is_none());
+        // 通知出错也不回复
+        assert!(rpc.handle_line(r#"{"jsonrpc":"2.0","method":"frobnicate"}"#, &mut s).await.is_none());
+        assert!(rpc
+            .handle_line(r#"[{"jsonrpc":"2.0","method":"list_jobs"},{"jsonrpc":"2.0","method":"unsubscribe"}]"#, &mut s)
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn batch_answers_each_request_in_order() {
+        let (_dir, rpc, mut s) = rpc().await;
+        let r = call(
+            &rpc,
+            &mut s,
+            r#"[
+                {"jsonrpc":"2.0","id":"a","method":"list_jobs"},
+                {"jsonrpc":"2.0","method":"list_jobs"},
+                5,
+                {"jsonrpc":"2.0","id":"c","method":"nope"}
+            ]"#,
+        )
+        .await;
+        let out = r.as_array().expect("batch response");
+        assert_eq!(out.len(), 3);
+        assert_eq!((out[0]["id"].as_str(), &out[0]["result"]), (Some("a"), &json!([])));
+        assert_eq!(error_code(&out[1]), INVALID_REQUEST);
+        assert_eq!((out[2]["id"].as_str(), error_code(&out[2])), (Some("c"), METHOD_NOT_FOUND));
+
+        // 空批量是无效请求
+        assert_eq!(error_code(&call(&rpc, &mut s, "[]").await), INVALID_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn set_limits_is_all_or_nothing() {
+        let (_dir, rpc, mut s) = rpc().await;
+        let r = call(
+            &rpc,
+            &mut s,
+            r#"{"jsonrpc":"2.0","id":1,"method":"set_limits",
+                "params":{"max_speed":"2M","max_active_items":3,"hosts":{"example.com":"fast"}}}"#,
+        )
+        .await;
+        assert_eq!(error_code(&r), INVALID_PARAMS);
+        assert_eq!((rpc.engine.speed_limit(), rpc.engine.max_active_items()), (0, 1));
+
+        let r = call(
+            &rpc,
+            &mut s,
+            r#"{"jsonrpc":"2.0","id":2,"method":"set_limits",
+                "params":{"max_speed":"2M","max_active_items":3,"hosts":{"example.com":1024}}}"#,
+        )
+        .await;
+        assert_eq!(r["result"], json!(true));
+        assert_eq!((rpc.engine.speed_limit(), rpc.engine.max_active_items()), (2 * 1024 * 1024, 3));
+    }
+}
@@ -0,0 +1,153 @@
+use crate::core::engine::Engine;
+use crate::daemon::{aria2, rest};
+use crate::daemon::rpc::{Rpc, Session};
+use anyhow::Context;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, UnixListener, UnixStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+
+pub struct DaemonOptions {
+    pub socket_path: PathBuf,
+    /// 仅允许回环地址：接口没有鉴权
+    pub tcp_listen: Option<SocketAddr>,
+    /// aria2 兼容接口的监听地址与 `token:` 密钥
+    pub aria2_listen: Option<SocketAddr>,
+    pub aria2_secret: Option<String>,
+    /// REST + SSE 接口的监听地址与 Bearer token（必填）
+    pub http_listen: Option<SocketAddr>,
+    pub http_token: Option<String>,
+    /// 每次 add 的默认 headers / options（来自插件参数）
+    pub headers: HashMap<String, String>,
+    pub options: HashMap<String, String>,
+}
+
+/// 恢复未完成的任务并提供 JSON-RPC，直到收到 SIGINT / SIGTERM。
+/// 退出时不取消任务：进行中的任务留在库里，下次启动时由 restore() 继续。
+pub async fn serve(engine: Engine, opts: DaemonOptions) -> anyhow::Result<()> {
+    if let Some(addr) = opts.tcp_listen {
+        if !addr.ip().is_loopback() {
+            anyhow::bail!("--rpc-listen must be a loopback address, got {}", addr);
+        }
+    }
+
+    if opts.http_listen.is_some() && opts.http_token.is_none() {
+        anyhow::bail!("--http-listen requires --http-token");
+    }
+
+    // 上次异常退出留下的 socket 文件：确认没有 daemon 在监听后再删除
+    if opts.socket_path.exists() {
+        if UnixStream::connect(&opts.socket_path).await.is_ok() {
+            anyhow::bail!("another daemon is already listening on {}", opts.socket_path.display());
+        }
+        std::fs::remove_file(&opts.socket_path)
+            .with_context(|| format!("remove stale socket {}", opts.socket_path.display()))?;
+    }
+    let unix = UnixListener::bind(&opts.socket_path)
+        .with_context(|| format!("bind {}", opts.socket_path.display()))?;
+    std::fs::set_permissions(&opts.socket_path, std::fs::Permissions::from_mode(0o600))?;
+    let tcp = match opts.tcp_listen {
+        Some(addr) => Some(TcpListener::bind(addr).await.with_context(|| format!("bind {}", addr))?),
+        None => None,
+    };
+
+    for job in engine.restore().await? {
+        println!("restored job {} ({:?})", job.job_id, job.status);
+    }
+    println!("JSON-RPC listening on {}", opts.socket_path.display());
+    if let Some(l) = &tcp {
+        println!("JSON-RPC listening on tcp://{}", l.local_addr()?);
+    }
+
+    if let Some(addr) = opts.aria2_listen {
+        let (engine, secret, headers, options) =
+            (engine.clone(), opts.aria2_secret.clone(), opts.headers.clone(), opts.options.clone());
+        tokio::spawn(async move {
+            if let Err(e) = aria2::serve(engine, addr, secret, headers, options).await {
+                eprintln!("aria2 RPC: {:#}", e);
+            }
+        });
+    }
+
+    if let (Some(addr), Some(token)) = (opts.http_listen, opts.http_token.clone()) {
+        let (engine, headers, options) = (engine.clone(), opts.headers.clone(), opts.options.clone());
+        tokio::spawn(async move {
+            if let Err(e) = rest::serve(engine, addr, token, headers, options).await {
+                eprintln!("HTTP API: {:#}", e);
+            }
+        });
+    }
+
+    let rpc = Arc::new(Rpc::new(engine, opts.headers, opts.options));
+    let mut sigterm = signal(SignalKind::terminate())?;
+    loop {
+        tokio::select! {
+            r = unix.accept() => match r {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_conn(rpc.clone(), stream));
+                }
+                Err(e) => eprintln!("accept unix socket: {}", e),
+            },
+            r = accept_tcp(tcp.as_ref()) => match r {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_conn(rpc.clone(), stream));
+                }
+                Err(e) => eprintln!("accept tcp: {}", e),
+            },
+            _ = tokio::signal::ctrl_c() => break,
+            _ = sigterm.recv() => break,
+        }
+    }
+
+    let _ = std::fs::remove_file(&opts.socket_path);
+    Ok(())
+}
+
+/// 没有配置 TCP 时永远挂起
+async fn accept_tcp(l: Option<&TcpListener>) -> std::io::Result<(tokio::net::TcpStream, SocketAddr)> {
+    match l {
+        Some(l) => l.accept().await,
+        None => futures::future::pending().await,
+    }
+}
+
+/// 按行读请求；响应与事件通知经同一个队列顺序写出
+async fn handle_conn<S>(rpc: Arc<Rpc>, stream: S)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (rd, mut wr) = tokio::io::split(stream);
+    let (out_tx, mut out_rx) = mpsc::channel::<String>(256);
+
+    let writer = tokio::spawn(async move {
+        while let Some(mut line) = out_rx.recv().await {
+            line.push('\n');
+            if wr.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut session = Session::new(out_tx.clone());
+    let mut lines = BufReader::new(rd).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(resp) = rpc.handle_line(&line, &mut session).await {
+            if out_tx.send(resp).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    // 停止事件转发后写完剩余响应
+    drop(session);
+    drop(out_tx);
+    let _ = writer.await;
+}
@@ -19,6 +19,7 @@ impl Locale {
 
 pub struct Messages {
     pub job_started: &'static str,
+    pub job_restored: &'static str,
     pub job_finished: &'static str,
     pub summary_header: &'static str,
     pub status_done: &'static str,
@@ -34,6 +35,7 @@ pub struct Messages {
 
 pub static EN: Messages = Messages {
     job_started: "Job started",
+    job_restored: "Job restored",
     job_finished: "Job finished",
     summary_header: "Summary",
     status_done: "done",
@@ -49,6 +51,7 @@ pub static EN: Messages = Messages {
 
 pub static ZH: Messages = Messages {
     job_started: "任务已启动",
+    job_restored: "任务已恢复",
     job_finished: "任务已完成",
     summary_header: "摘要",
     status_done: "完成",
@@ -1,12 +1,17 @@
 mod core;
+#[cfg(unix)]
+mod daemon;
 mod i18n;
+mod manage;
 mod plugins;
 
-use clap::{Arg, ArgAction, Command};
+use anyhow::Context;
+use clap::{Arg, ArgAction, ArgMatches, Command};
 use core::engine::Engine;
 use core::events::EngineEvent;
-use core::model::LinkInput;
-use i18n::{get_messages, Locale};
+use core::model::{HashAlgo, JobStatus, LinkInput};
+use core::ratelimit::parse_rate;
+use i18n::{get_messages, Locale, Messages};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use plugins::registry::PluginRegistry;
 use plugins::registry::DriverContext;
@@ -15,9 +20,98 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 use uuid::Uuid;
 
-fn build_cli(registry: &PluginRegistry) -> Command {
-    let download = Command::new("download")
-        .about("Download one or more links")
+/// download 的退出码（其他错误由 anyhow 返回 1）
+const EXIT_OK: i32 = 0;
+const EXIT_ITEMS_FAILED: i32 = 2;
+const EXIT_RESOLVE_FAILED: i32 = 3;
+const EXIT_CANCELLED: i32 = 130;
+
+fn out_dir_arg() -> Arg {
+    Arg::new("out_dir")
+        .long("out-dir")
+        .help("Output directory")
+        .default_value("./downloads")
+        .num_args(1)
+}
+
+/// download、resume 与 daemon 共用的引擎参数
+fn engine_args(cmd: Command) -> Command {
+    cmd
+        .arg(out_dir_arg())
+        .arg(
+            Arg::new("concurrency")
+                .long("concurrency")
+                .help("Max concurrent fragments per item")
+                .default_value("6")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("chunk_mb")
+                .long("chunk-mb")
+                .help("Chunk size in MB (for HTTP range)")
+                .default_value("8")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("fragment_retries")
+                .long("fragment-retries")
+                .help("Times a failed fragment is re-queued before the item fails")
+                .default_value("5")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("fragment_hash")
+                .long("fragment-hash")
+                .help("Hash each completed fragment with this algorithm (md5, sha-1, sha-256, sha-512, blake3) and keep it in the resume DB")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("max_speed")
+                .long("max-speed")
+                .help("Overall download speed cap, e.g. 500K, 2M (0 = unlimited)")
+                .default_value("0")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("max_item_speed")
+                .long("max-item-speed")
+                .help("Speed cap per item (0 = unlimited)")
+                .default_value("0")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("max_host_speed")
+                .long("max-host-speed")
+                .help("Speed cap per remote host (0 = unlimited)")
+                .default_value("0")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("max_conns_per_host")
+                .long("max-conns-per-host")
+                .help("Maximum simultaneous connections to one host across all items (0 = unlimited)")
+                .default_value("8")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("max_active_items")
+                .long("max-active-items")
+                .help("Items of a job downloaded at the same time")
+                .default_value("3")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("max_connections")
+                .long("max-connections")
+                .help("Maximum simultaneous connections across all items (0 = unlimited)")
+                .default_value("16")
+                .num_args(1),
+        )
+}
+
+/// download 与 resume 的界面参数
+fn ui_args(cmd: Command) -> Command {
+    cmd
         .arg(
             Arg::new("locale")
                 .long("locale")
@@ -25,42 +119,250 @@ fn build_cli(registry: &PluginRegistry) -> Command {
                 .default_value("en")
                 .num_args(1),
         )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .help("text: progress bars; json: one JSON object per event on stdout, then a summary object")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .num_args(1),
+        )
+        .after_help(
+            "Exit codes: 0 all items done, 2 some items failed, 3 some links could not be resolved, 130 cancelled, 1 other errors",
+        )
+}
+
+fn build_cli(registry: &PluginRegistry) -> Command {
+    let download = Command::new("download")
+        .about("Download one or more links")
         .arg(
             Arg::new("links")
                 .help("Links to download")
                 .action(ArgAction::Append)
                 .num_args(1..)
+                .required_unless_present("input_file"),
+        )
+        .arg(
+            Arg::new("input_file")
+                .long("input-file")
+                .short('i')
+                .help("Read links from a file (- for stdin): one per line with indented out=/dir=/header=/checksum= options, or JSON Lines")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("checksum")
+                .long("checksum")
+                .help("Expected digest as algo=hex (md5, sha-1, sha-256, sha-512, blake3); repeatable, applies to every link")
+                .action(ArgAction::Append)
+                .num_args(1),
+        );
+    let download = registry.augment_download_command(engine_args(ui_args(download)));
+
+    let resume = Command::new("resume")
+        .about("Continue stored jobs (including failed and cancelled ones) or re-download stored items")
+        .arg(
+            Arg::new("ids")
+                .help("Job ids (a unique prefix is enough) or item numbers as shown by list")
+                .action(ArgAction::Append)
+                .num_args(1..)
+                .required_unless_present("all"),
+        )
+        .arg(
+            Arg::new("all")
+                .long("all")
+                .help("Resume every job and item that has not finished")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("ids"),
+        );
+    let resume = registry.augment_download_command(engine_args(ui_args(resume)));
+
+    let list = Command::new("list")
+        .about("List stored jobs and items with their progress")
+        .arg(out_dir_arg());
+    let status = Command::new("status")
+        .about("Show one stored job or item in detail")
+        .arg(Arg::new("id").help("Job id (or unique prefix) or item number").required(true).num_args(1))
+        .arg(out_dir_arg());
+    let cancel = Command::new("cancel")
+        .about("Mark stored jobs as cancelled so they are no longer resumed (does not stop a job running in another process)")
+        .arg(
+            Arg::new("ids")
+                .help("Job ids (a unique prefix is enough)")
+                .action(ArgAction::Append)
+                .num_args(1..)
                 .required(true),
         )
+        .arg(out_dir_arg());
+    let verify = Command::new("verify-partial")
+        .about("Re-check completed fragments of unfinished items against the .partial file and known hashes; bad ones are downloaded again on resume")
         .arg(
-            Arg::new("out_dir")
-                .long("out-dir")
-                .help("Output directory")
-                .default_value("./downloads")
+            Arg::new("ids")
+                .help("Item numbers or job ids (default: every item with a .partial file)")
+                .action(ArgAction::Append)
+                .num_args(1..),
+        )
+        .arg(out_dir_arg());
+    let history = Command::new("history")
+        .about("Show download history (newest first) or totals per day / host")
+        .arg(
+            Arg::new("since")
+                .long("since")
+                .help("Only runs finished at or after this time: 7d, 12h, YYYY-MM-DD or \"YYYY-MM-DD HH:MM\" (UTC)")
                 .num_args(1),
         )
         .arg(
-            Arg::new("concurrency")
-                .long("concurrency")
-                .help("Max concurrent fragments per item")
-                .default_value("6")
+            Arg::new("until")
+                .long("until")
+                .help("Only runs finished before this time; a bare date includes that whole day")
                 .num_args(1),
         )
+        .arg(Arg::new("host").long("host").help("Only downloads from this host name").num_args(1))
         .arg(
-            Arg::new("chunk_mb")
-                .long("chunk-mb")
-                .help("Chunk size in MB (for HTTP range)")
-                .default_value("8")
+            Arg::new("status")
+                .long("status")
+                .help("Only runs that ended this way")
+                .value_parser(["done", "failed", "cancelled"])
                 .num_args(1),
-        );
+        )
+        .arg(
+            Arg::new("by")
+                .long("by")
+                .help("Print totals (runs, failures, bytes) per day or per host instead of individual runs")
+                .value_parser(["day", "host"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("limit")
+                .long("limit")
+                .help("Maximum number of runs to list (0 = all)")
+                .default_value("50")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .help("text or json (one object per line)")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .num_args(1),
+        )
+        .arg(out_dir_arg());
+    let clean = Command::new("clean")
+        .about("Remove completed jobs and items from the database and delete orphaned .partial files")
+        .arg(
+            Arg::new("dry_run")
+                .long("dry-run")
+                .help("Only print what would be removed")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(out_dir_arg());
 
-    let download = registry.augment_download_command(download);
+    // 插件参数（--header、--ftp-user 等）在 daemon 中作为每次 add 的默认值
+    let daemon = Command::new("daemon")
+        .about("Run one engine in the background and serve a JSON-RPC control API")
+        .arg(
+            Arg::new("rpc_socket")
+                .long("rpc-socket")
+                .help("Unix socket path for JSON-RPC (default: <out-dir>/.downloader.sock)")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("rpc_listen")
+                .long("rpc-listen")
+                .help("Also serve JSON-RPC on this loopback TCP address, e.g. 127.0.0.1:6801")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("aria2_listen")
+                .long("aria2-listen")
+                .help("Serve an aria2-compatible RPC (HTTP/WebSocket /jsonrpc) on this address, e.g. 127.0.0.1:6800")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("aria2_secret")
+                .long("aria2-secret")
+                .help("Secret expected as \"token:<secret>\" by the aria2-compatible RPC (required off loopback and for browser clients)")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("http_listen")
+                .long("http-listen")
+                .help("Serve the REST + Server-Sent Events API on this address, e.g. 127.0.0.1:6802")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("http_token")
+                .long("http-token")
+                .help("Bearer token required by the REST API")
+                .num_args(1),
+        );
+    let daemon = registry.augment_download_command(engine_args(daemon));
 
-    Command::new("downloader")
+    let cmd = Command::new("downloader")
         .about("Multi-fragment downloader (HTTP + GitHub resolver) - plugin based")
         .subcommand_required(true)
         .arg_required_else_help(true)
         .subcommand(download)
+        .subcommand(resume)
+        .subcommand(list)
+        .subcommand(status)
+        .subcommand(cancel)
+        .subcommand(verify)
+        .subcommand(history)
+        .subcommand(clean);
+    if cfg!(unix) {
+        cmd.subcommand(daemon)
+    } else {
+        cmd
+    }
+}
+
+fn out_dir(m: &ArgMatches) -> PathBuf {
+    m.get_one::<String>("out_dir").unwrap().into()
+}
+
+/// 按 engine_args 与插件参数创建 Engine；返回的配置里 headers/options 是每个链接的默认值
+async fn build_engine(registry: PluginRegistry, m: &ArgMatches) -> anyhow::Result<(Engine, DownloadCliConfig)> {
+    let out_dir = out_dir(m);
+    let concurrency: usize = m.get_one::<String>("concurrency").unwrap().parse()?;
+    let chunk_mb: u64 = m.get_one::<String>("chunk_mb").unwrap().parse()?;
+    let fragment_retries: u32 = m.get_one::<String>("fragment_retries").unwrap().parse()?;
+    let fragment_hash = m
+        .get_one::<String>("fragment_hash")
+        .map(|v| HashAlgo::parse(v).ok_or_else(|| anyhow::anyhow!("unsupported fragment hash algorithm: {}", v)))
+        .transpose()?;
+
+    tokio::fs::create_dir_all(&out_dir).await?;
+
+    let mut cfg = DownloadCliConfig {
+        headers: HashMap::new(),
+        options: HashMap::new(),
+        driver_ctx: DriverContext {
+            user_agent: "OrangeDownloader/0.1".to_string(),
+            timeout_secs: 60,
+            retries: 2,
+            retry_backoff_ms: 400,
+            item_speed_limit: parse_rate(m.get_one::<String>("max_item_speed").unwrap())?,
+            host_speed_limit: parse_rate(m.get_one::<String>("max_host_speed").unwrap())?,
+            max_conns_per_host: m.get_one::<String>("max_conns_per_host").unwrap().parse()?,
+            max_connections: m.get_one::<String>("max_connections").unwrap().parse()?,
+        },
+    };
+    registry.apply_download_matches(m, &mut cfg)?;
+
+    let engine = Engine::new(
+        registry,
+        out_dir,
+        concurrency,
+        chunk_mb * 1024 * 1024,
+        fragment_retries,
+        fragment_hash,
+        cfg.driver_ctx.clone(),
+    )
+    .await?;
+    engine.set_speed_limit(parse_rate(m.get_one::<String>("max_speed").unwrap())?);
+    engine.set_max_active_items(m.get_one::<String>("max_active_items").unwrap().parse()?);
+    Ok((engine, cfg))
 }
 
 #[tokio::main]
@@ -73,204 +375,493 @@ async fn main() -> anyhow::Result<()> {
         Some(("download", m)) => {
             let locale = Locale::from_str(m.get_one::<String>("locale").map(|s| s.as_str()).unwrap_or("en"));
             let msg = get_messages(locale);
+            let json_output = m.get_one::<String>("output").map(|s| s.as_str()) == Some("json");
 
-            let out_dir: PathBuf = m.get_one::<String>("out_dir").unwrap().into();
-            let concurrency: usize = m.get_one::<String>("concurrency").unwrap().parse()?;
-            let chunk_mb: u64 = m.get_one::<String>("chunk_mb").unwrap().parse()?;
+            let (engine, mut cfg) = build_engine(registry, m).await?;
 
-            tokio::fs::create_dir_all(&out_dir).await?;
-
-            let mut cfg = DownloadCliConfig {
-                headers: HashMap::new(),
-                options: HashMap::new(),
-                driver_ctx: DriverContext {
-                    user_agent: "OrangeDownloader/0.1".to_string(),
-                    timeout_secs: 60,
-                    retries: 2,
-                    retry_backoff_ms: 400,
-                },
-            };
-            registry.apply_download_matches(m, &mut cfg)?;
-
-            let engine = Engine::new(
-                registry,
-                out_dir.clone(),
-                concurrency,
-                chunk_mb * 1024 * 1024,
-                cfg.driver_ctx.clone(),
-            )
-            .await?;
+            if let Some(values) = m.get_many::<String>("checksum") {
+                let specs: Vec<String> = values.cloned().collect();
+                // 提前校验格式，engine 按 options["checksum"] 解析
+                core::checksum::parse_checksum_list(&specs.join(","))?;
+                cfg.options.insert("checksum".to_string(), specs.join(","));
+            }
 
-            let links: Vec<String> = m
+            let mut links: Vec<LinkInput> = m
                 .get_many::<String>("links")
-                .unwrap()
-                .map(|s| s.to_string())
-                .collect();
-
-            let inputs: Vec<LinkInput> = links
                 .into_iter()
+                .flatten()
                 .map(|raw| LinkInput {
-                    raw,
+                    raw: raw.to_string(),
                     headers: cfg.headers.clone(),
                     options: cfg.options.clone(),
                 })
                 .collect();
 
-            let job_id = engine.add_and_start(inputs).await?;
-            println!("{}: {}", msg.job_started, job_id);
-
-            let mut rx = engine.subscribe();
-            let ui_job_id = job_id;
-            let ui_task = tokio::spawn(async move {
-                let mp = MultiProgress::new();
-                let sty_pb = ProgressStyle::with_template("{spinner:.green} {prefix} {wide_msg}")
-                    .unwrap()
-                    .tick_chars("|/-\\ ");
-                let sty_bar = ProgressStyle::with_template(
-                    "{prefix} {bar:40.cyan/blue} {bytes}/{total_bytes} ({bytes_per_sec}, eta {eta}) {wide_msg}",
-                )
-                .unwrap();
-
-                #[derive(Clone)]
-                struct ItemView {
-                    display_name: String,
-                    target_path: String,
-                    uri: String,
-                    status: String,
-                    downloaded: u64,
-                    total: Option<u64>,
-                    errors: Vec<String>,
+            if let Some(path) = m.get_one::<String>("input_file") {
+                let text = if path == "-" {
+                    std::io::read_to_string(std::io::stdin())?
+                } else {
+                    std::fs::read_to_string(path).with_context(|| format!("read input file {}", path))?
+                };
+                // 清单里每个链接自己的 headers / options 覆盖命令行给出的默认值
+                for entry in core::inputfile::parse_input_file(&text).with_context(|| format!("input file {}", path))? {
+                    let mut headers = cfg.headers.clone();
+                    headers.extend(entry.headers);
+                    let mut options = cfg.options.clone();
+                    options.extend(entry.options);
+                    links.push(LinkInput { raw: entry.raw, headers, options });
                 }
+            }
 
-                let mut bars: HashMap<Uuid, ProgressBar> = HashMap::new();
-                let mut items: HashMap<Uuid, ItemView> = HashMap::new();
+            // 上次中断的任务不在这里自动恢复（可能已暂停，或正由同一 out_dir 上的 daemon 下载），用 resume 继续
+            if links.is_empty() {
+                anyhow::bail!("no links to download");
+            }
 
-                loop {
-                    let evt = match rx.recv().await {
-                        Ok(e) => e,
-                        Err(_) => break,
-                    };
+            let rx = engine.subscribe();
+            let job_id = engine.add_and_start(links).await?;
+            if json_output {
+                println!("{}", serde_json::json!({ "type": "job_started", "job_id": job_id }));
+            } else {
+                println!("{}: {}", msg.job_started, job_id);
+            }
 
-                    match evt {
-                        EngineEvent::JobStatusChanged { job_id, status } => {
-                            if job_id == ui_job_id {
-                                let _ = mp.println(format!("[{}] {} -> {:?}", msg.job_prefix, job_id, status));
-                                if matches!(status, core::model::JobStatus::Completed | core::model::JobStatus::Failed) {
-                                    let _ = mp.println("".to_string());
-                                    let _ = mp.println(format!("{}:", msg.summary_header));
-                                    let mut ids: Vec<_> = items.keys().cloned().collect();
-                                    ids.sort();
-                                    for id in ids {
-                                        if let Some(v) = items.get(&id) {
-                                            let total_s = v.total.map(fmt_bytes).unwrap_or_else(|| msg.total_unknown.to_string());
-                                            let _ = mp.println(format!(
-                                                "- item={} status={} {} / {} name={} path={} uri={}",
-                                                id,
-                                                v.status,
-                                                fmt_bytes(v.downloaded),
-                                                total_s,
-                                                v.display_name,
-                                                v.target_path,
-                                                v.uri,
-                                            ));
-                                            for e in &v.errors {
-                                                let _ = mp.println(format!("  {}: {}", msg.error_prefix, e));
-                                            }
-                                        }
-                                    }
-                                    break;
-                                }
-                            }
-                        }
-                        EngineEvent::ItemAdded { item_id, display_name, target_path, uri } => {
-                            let pb = mp.add(ProgressBar::new_spinner());
-                            pb.set_style(sty_pb.clone());
-                            pb.set_prefix(format!("[{display_name}]"));
-                            pb.enable_steady_tick(std::time::Duration::from_millis(120));
-                            pb.set_message(format!("{} -> {} ({})", msg.item_added, target_path.display(), uri));
-                            bars.insert(item_id, pb);
-                            items.insert(
-                                item_id,
-                                ItemView {
-                                    display_name,
-                                    target_path: target_path.display().to_string(),
-                                    uri,
-                                    status: msg.item_added.to_string(),
-                                    downloaded: 0,
-                                    total: None,
-                                    errors: vec![],
-                                },
-                            );
+            let exit_code = watch_jobs(&engine, rx, vec![job_id], msg, json_output).await?;
+            if exit_code != EXIT_OK {
+                std::process::exit(exit_code);
+            }
+        }
+        Some(("resume", m)) => {
+            let locale = Locale::from_str(m.get_one::<String>("locale").map(|s| s.as_str()).unwrap_or("en"));
+            let msg = get_messages(locale);
+            let json_output = m.get_one::<String>("output").map(|s| s.as_str()) == Some("json");
+
+            let (engine, cfg) = build_engine(registry, m).await?;
+            let store = manage::open_store(engine.out_dir()).await?;
+            let index = manage::job_item_index(&store).await?;
+            let rx = engine.subscribe();
+
+            // 要继续的任务，以及不属于任何任务、需按记录重新下载的 item
+            let mut jobs = vec![];
+            let mut restart = vec![];
+            let all = m.get_flag("all");
+            if all {
+                jobs = store
+                    .load_jobs()
+                    .await?
+                    .into_iter()
+                    .filter(|j| j.status != JobStatus::Completed)
+                    .collect();
+                for p in store.load_item_progress(None).await? {
+                    if !manage::is_finished(&p) && !index.contains_key(&(p.source_uri.clone(), p.target_path.clone())) {
+                        restart.push(manage::restart_input(&p, engine.out_dir(), &cfg.headers, &cfg.options)?);
+                    }
+                }
+            } else {
+                for id in m.get_many::<String>("ids").into_iter().flatten() {
+                    match manage::find_target(&store, id).await? {
+                        manage::Target::Job(job) => jobs.push(job),
+                        manage::Target::Item(p) => match index.get(&(p.source_uri.clone(), p.target_path.clone())) {
+                            Some(job) => jobs.push(job.clone()),
+                            None => restart.push(manage::restart_input(&p, engine.out_dir(), &cfg.headers, &cfg.options)?),
+                        },
+                    }
+                }
+            }
+
+            let mut job_ids = vec![];
+            for job in jobs {
+                if job_ids.contains(&job.job_id) {
+                    continue;
+                }
+                match engine.resume_stored_job(job.job_id).await {
+                    Ok(_) => {}
+                    // --all 时跳过别的进程（daemon）正在下载的任务
+                    Err(e) if all && e.is::<core::engine::JobOwnedElsewhere>() => {
+                        eprintln!("skipping: {:#}", e);
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                }
+                if json_output {
+                    println!("{}", serde_json::json!({ "type": "job_restored", "job_id": job.job_id }));
+                } else {
+                    println!("{}: {}", msg.job_restored, job.job_id);
+                }
+                job_ids.push(job.job_id);
+            }
+            if !restart.is_empty() {
+                let job_id = engine.add_and_start(restart).await?;
+                if json_output {
+                    println!("{}", serde_json::json!({ "type": "job_started", "job_id": job_id }));
+                } else {
+                    println!("{}: {}", msg.job_started, job_id);
+                }
+                job_ids.push(job_id);
+            }
+
+            if job_ids.is_empty() {
+                anyhow::bail!("nothing to resume");
+            }
+
+            let exit_code = watch_jobs(&engine, rx, job_ids, msg, json_output).await?;
+            if exit_code != EXIT_OK {
+                std::process::exit(exit_code);
+            }
+        }
+        Some(("list", m)) => {
+            let store = manage::open_store(&out_dir(m)).await?;
+            manage::list(&store).await?;
+        }
+        Some(("status", m)) => {
+            let store = manage::open_store(&out_dir(m)).await?;
+            manage::status(&store, m.get_one::<String>("id").unwrap()).await?;
+        }
+        Some(("cancel", m)) => {
+            let store = manage::open_store(&out_dir(m)).await?;
+            let ids: Vec<String> = m.get_many::<String>("ids").into_iter().flatten().cloned().collect();
+            manage::cancel(&store, &ids).await?;
+        }
+        Some(("verify-partial", m)) => {
+            let store = manage::open_store(&out_dir(m)).await?;
+            let ids: Vec<String> = m.get_many::<String>("ids").into_iter().flatten().cloned().collect();
+            manage::verify_partial(&store, &ids).await?;
+        }
+        Some(("history", m)) => {
+            let store = manage::open_store(&out_dir(m)).await?;
+            let filter = core::store::HistoryFilter {
+                since: m.get_one::<String>("since").map(|s| manage::parse_time(s, false)).transpose()?,
+                until: m.get_one::<String>("until").map(|s| manage::parse_time(s, true)).transpose()?,
+                host: m.get_one::<String>("host").cloned(),
+                status: m.get_one::<String>("status").cloned(),
+            };
+            let limit: i64 = m.get_one::<String>("limit").unwrap().parse()?;
+            manage::history(
+                &store,
+                &filter,
+                (limit > 0).then_some(limit),
+                m.get_one::<String>("by").map(|s| s.as_str()),
+                m.get_one::<String>("output").map(|s| s.as_str()) == Some("json"),
+            )
+            .await?;
+        }
+        Some(("clean", m)) => {
+            let out_dir = out_dir(m);
+            let store = manage::open_store(&out_dir).await?;
+            manage::clean(&store, &out_dir, m.get_flag("dry_run")).await?;
+        }
+        #[cfg(unix)]
+        Some(("daemon", m)) => {
+            let (engine, cfg) = build_engine(registry, m).await?;
+            let out_dir = out_dir(m);
+            let socket_path = m
+                .get_one::<String>("rpc_socket")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| out_dir.join(".downloader.sock"));
+            let tcp_listen = m
+                .get_one::<String>("rpc_listen")
+                .map(|s| s.parse().map_err(|e| anyhow::anyhow!("invalid --rpc-listen {}: {}", s, e)))
+                .transpose()?;
+
+            let aria2_listen = m
+                .get_one::<String>("aria2_listen")
+                .map(|s| s.parse().map_err(|e| anyhow::anyhow!("invalid --aria2-listen {}: {}", s, e)))
+                .transpose()?;
+
+            let http_listen = m
+                .get_one::<String>("http_listen")
+                .map(|s| s.parse().map_err(|e| anyhow::anyhow!("invalid --http-listen {}: {}", s, e)))
+                .transpose()?;
+
+            daemon::serve(
+                engine,
+                daemon::DaemonOptions {
+                    socket_path,
+                    tcp_listen,
+                    aria2_listen,
+                    aria2_secret: m.get_one::<String>("aria2_secret").cloned(),
+                    http_listen,
+                    http_token: m.get_one::<String>("http_token").cloned(),
+                    headers: cfg.headers,
+                    options: cfg.options,
+                },
+            )
+            .await?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// 显示任务进度（进度条或 JSON 事件）直到全部结束；Ctrl-C 取消这些任务。返回退出码
+async fn watch_jobs(
+    engine: &Engine,
+    mut rx: tokio::sync::broadcast::Receiver<EngineEvent>,
+    job_ids: Vec<Uuid>,
+    msg: &'static Messages,
+    json_output: bool,
+) -> anyhow::Result<i32> {
+    let mut ui_jobs: std::collections::HashSet<Uuid> = job_ids.iter().cloned().collect();
+    let ui_engine = engine.clone();
+    let ui_task = tokio::spawn(async move {
+        let mp = MultiProgress::new();
+        let sty_pb = ProgressStyle::with_template("{spinner:.green} {prefix} {wide_msg}")
+            .unwrap()
+            .tick_chars("|/-\\ ");
+        let sty_bar = ProgressStyle::with_template(
+            "{prefix} {bar:40.cyan/blue} {bytes}/{total_bytes} ({bytes_per_sec}, eta {eta}) {wide_msg}",
+        )
+        .unwrap();
+
+        #[derive(Clone)]
+        struct ItemView {
+            display_name: String,
+            target_path: String,
+            uri: String,
+            status: String,
+            downloaded: u64,
+            total: Option<u64>,
+            errors: Vec<String>,
+        }
+
+        let mut bars: HashMap<Uuid, ProgressBar> = HashMap::new();
+        let mut items: HashMap<Uuid, ItemView> = HashMap::new();
+        // item -> job，用于给 JSON 输出里只有 item_id 的事件补上 job_id
+        let mut item_jobs: HashMap<Uuid, Uuid> = HashMap::new();
+        // 不属于某个 item 的任务级错误（如解析失败）
+        let mut job_errors: HashMap<Uuid, Vec<String>> = HashMap::new();
+        // 落后后从 engine 补出来的任务状态事件
+        let mut pending: std::collections::VecDeque<EngineEvent> = std::collections::VecDeque::new();
+
+        loop {
+            let evt = match pending.pop_front() {
+                Some(e) => e,
+                None => match rx.recv().await {
+                    Ok(e) => e,
+                    // 进度事件按 chunk 发送，UI 落后时跳过旧事件即可；但被跳过的可能包括任务结束事件，
+                    // 所以重新向 engine 查询各任务的状态，已结束的补发一条 JobStatusChanged
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                        if json_output {
+                            println!("{}", serde_json::json!({ "type": "lagged", "skipped": n }));
                         }
-                        EngineEvent::ItemStatusChanged { item_id, status } => {
-                            if let Some(v) = items.get_mut(&item_id) {
-                                v.status = format!("{:?}", status);
-                            }
-                            if let Some(pb) = bars.get(&item_id) {
-                                pb.set_message(format!("status={:?}", status));
-                                if matches!(status, core::model::ItemStatus::Done) {
-                                    pb.finish_with_message(msg.status_done.to_string());
-                                }
-                                if matches!(status, core::model::ItemStatus::Failed) {
-                                    pb.finish_with_message(msg.status_failed.to_string());
+                        for job_id in &ui_jobs {
+                            if let Some(status) = ui_engine.job_status(*job_id).await {
+                                if matches!(status, core::model::JobStatus::Completed | core::model::JobStatus::Failed | core::model::JobStatus::Cancelled) {
+                                    pending.push_back(EngineEvent::JobStatusChanged { job_id: *job_id, status });
                                 }
                             }
                         }
-                        EngineEvent::Progress { item_id, downloaded, total, speed_bps, eta } => {
-                            if let Some(v) = items.get_mut(&item_id) {
-                                v.downloaded = downloaded;
-                                v.total = total;
-                            }
-                            let pb = match bars.get(&item_id) {
-                                Some(pb) => pb,
-                                None => continue,
-                            };
-
-                            if let Some(t) = total {
-                                if pb.length().unwrap_or(0) != t {
-                                    pb.set_style(sty_bar.clone());
-                                    pb.set_length(t);
-                                }
-                                pb.set_position(downloaded.min(t));
-                            } else {
-                                pb.set_style(sty_pb.clone());
-                            }
+                        continue;
+                    }
+                    Err(_) => break,
+                },
+            };
 
-                            let eta_s = eta
-                                .map(|d| format!("{:.0}s", d.as_secs_f64()))
-                                .unwrap_or_else(|| msg.eta_unknown.to_string());
-                            pb.set_message(format!("{} / {} | {} | eta {}", fmt_bytes(downloaded), total.map(fmt_bytes).unwrap_or_else(|| msg.total_unknown.to_string()), fmt_bytes(speed_bps), eta_s));
+            if json_output {
+                if let EngineEvent::ItemAdded { job_id, item_id, .. } = &evt {
+                    item_jobs.insert(*item_id, *job_id);
+                }
+                let mut v = serde_json::to_value(&evt).unwrap_or_default();
+                let job_id = v["item_id"].as_str().and_then(|s| s.parse().ok()).and_then(|id| item_jobs.get(&id));
+                if let (Some(job_id), Some(obj)) = (job_id.copied(), v.as_object_mut()) {
+                    // Error / Info 事件自带 `"job_id": null`，也要覆盖
+                    let slot = obj.entry("job_id").or_insert(serde_json::Value::Null);
+                    if slot.is_null() {
+                        *slot = serde_json::json!(job_id);
+                    }
+                }
+                println!("{}", v);
+            }
+
+            match evt {
+                EngineEvent::JobStatusChanged { job_id, status } => {
+                    if ui_jobs.contains(&job_id) {
+                        if !json_output {
+                            let _ = mp.println(format!("[{}] {} -> {:?}", msg.job_prefix, job_id, status));
                         }
-                        EngineEvent::FragmentDone { item_id, completed, total } => {
-                            if let Some(pb) = bars.get(&item_id) {
-                                pb.set_message(format!("{} {}/{}", msg.fragments_label, completed, total));
-                            }
+                        if matches!(status, core::model::JobStatus::Completed | core::model::JobStatus::Failed | core::model::JobStatus::Cancelled) {
+                            ui_jobs.remove(&job_id);
                         }
-                        EngineEvent::Error { scope, message } => {
-                            let _ = mp.println(format!("[{}] {}: {}", msg.error_prefix, scope, message));
-                            for (id, v) in items.iter_mut() {
-                                if scope.contains(&id.to_string()) {
-                                    v.errors.push(format!("{}: {}", scope, message));
+                        if ui_jobs.is_empty() && json_output {
+                            break;
+                        }
+                        if ui_jobs.is_empty() {
+                            let _ = mp.println("");
+                            let _ = mp.println(format!("{}:", msg.summary_header));
+                            let mut ids: Vec<_> = items.keys().cloned().collect();
+                            ids.sort();
+                            for id in ids {
+                                if let Some(v) = items.get(&id) {
+                                    let total_s = v.total.map(fmt_bytes).unwrap_or_else(|| msg.total_unknown.to_string());
+                                    let _ = mp.println(format!(
+                                        "- item={} status={} {} / {} name={} path={} uri={}",
+                                        id,
+                                        v.status,
+                                        fmt_bytes(v.downloaded),
+                                        total_s,
+                                        v.display_name,
+                                        v.target_path,
+                                        v.uri,
+                                    ));
+                                    for e in &v.errors {
+                                        let _ = mp.println(format!("  {}: {}", msg.error_prefix, e));
+                                    }
                                 }
                             }
+                            break;
                         }
-                        EngineEvent::Info { scope, message } => {
-                            let _ = mp.println(format!("[{}] {}: {}", msg.info_prefix, scope, message));
+                    }
+                }
+                EngineEvent::ItemAdded { item_id, display_name, target_path, uri, .. } => {
+                    if !json_output {
+                        let pb = mp.add(ProgressBar::new_spinner());
+                        pb.set_style(sty_pb.clone());
+                        pb.set_prefix(format!("[{display_name}]"));
+                        pb.enable_steady_tick(std::time::Duration::from_millis(120));
+                        pb.set_message(format!("{} -> {} ({})", msg.item_added, target_path.display(), uri));
+                        bars.insert(item_id, pb);
+                    }
+                    items.insert(
+                        item_id,
+                        ItemView {
+                            display_name,
+                            target_path: target_path.display().to_string(),
+                            uri,
+                            status: msg.item_added.to_string(),
+                            downloaded: 0,
+                            total: None,
+                            errors: vec![],
+                        },
+                    );
+                }
+                EngineEvent::ItemStatusChanged { item_id, status } => {
+                    if let Some(v) = items.get_mut(&item_id) {
+                        v.status = format!("{:?}", status);
+                    }
+                    if let Some(pb) = bars.get(&item_id) {
+                        pb.set_message(format!("status={:?}", status));
+                        if matches!(status, core::model::ItemStatus::Done) {
+                            pb.finish_with_message(msg.status_done.to_string());
                         }
+                        if matches!(status, core::model::ItemStatus::Failed) {
+                            pb.finish_with_message(msg.status_failed.to_string());
+                        }
+                    }
+                }
+                EngineEvent::Progress { item_id, downloaded, total, speed_bps, eta } => {
+                    if let Some(v) = items.get_mut(&item_id) {
+                        v.downloaded = downloaded;
+                        v.total = total;
+                    }
+                    let pb = match bars.get(&item_id) {
+                        Some(pb) => pb,
+                        None => continue,
+                    };
+
+                    if let Some(t) = total {
+                        if pb.length().unwrap_or(0) != t {
+                            pb.set_style(sty_bar.clone());
+                            pb.set_length(t);
+                        }
+                        pb.set_position(downloaded.min(t));
+                    } else {
+                        pb.set_style(sty_pb.clone());
+                    }
+
+                    let eta_s = eta
+                        .map(|d| format!("{:.0}s", d.as_secs_f64()))
+                        .unwrap_or_else(|| msg.eta_unknown.to_string());
+                    pb.set_message(format!("{} / {} | {} | eta {}", fmt_bytes(downloaded), total.map(fmt_bytes).unwrap_or_else(|| msg.total_unknown.to_string()), fmt_bytes(speed_bps), eta_s));
+                }
+                EngineEvent::FragmentDone { item_id, completed, total } => {
+                    if let Some(pb) = bars.get(&item_id) {
+                        pb.set_message(format!("{} {}/{}", msg.fragments_label, completed, total));
+                    }
+                }
+                EngineEvent::Error { job_id, item_id, scope, message, .. } => {
+                    if !json_output {
+                        let _ = mp.println(format!("[{}] {}: {}", msg.error_prefix, scope, message));
+                    }
+                    match (item_id.and_then(|id| items.get_mut(&id)), job_id) {
+                        (Some(v), _) => v.errors.push(format!("{}: {}", scope, message)),
+                        (None, Some(job_id)) => job_errors.entry(job_id).or_default().push(format!("{}: {}", scope, message)),
+                        (None, None) => {}
+                    }
+                }
+                EngineEvent::Info { scope, message, .. } => {
+                    if !json_output {
+                        let _ = mp.println(format!("[{}] {}: {}", msg.info_prefix, scope, message));
                     }
                 }
-            });
+            }
+        }
+        let item_errors: HashMap<Uuid, Vec<String>> = items.into_iter().map(|(id, v)| (id, v.errors)).collect();
+        (item_errors, job_errors)
+    });
 
-            engine.wait_job(job_id).await;
+    // Ctrl-C：取消任务，让在途分片在 store 中回退为 Missing，下次可续传
+    let ctrlc_engine = engine.clone();
+    let ctrlc_jobs = job_ids.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            for job_id in ctrlc_jobs {
+                let _ = ctrlc_engine.cancel_job(job_id).await;
+            }
+        }
+    });
 
-            let _ = ui_task.await;
+    for job_id in &job_ids {
+        engine.wait_job(*job_id).await;
+    }
 
+    let (item_errors, job_errors) = ui_task.await.unwrap_or_default();
+
+    let jobs: Vec<_> = engine.list_jobs().await?.into_iter().filter(|j| job_ids.contains(&j.job_id)).collect();
+    let exit_code = download_exit_code(&jobs);
+
+    if json_output {
+        // 状态与字节数以 store 为准：恢复的任务里已完成的 item 不会再发事件
+        let mut summary_items = vec![];
+        for job_id in &job_ids {
+            for item in engine.list_items(Some(*job_id)).await? {
+                let mut v = serde_json::to_value(&item)?;
+                v["errors"] = serde_json::json!(item_errors.get(&item.item_id).cloned().unwrap_or_default());
+                summary_items.push(v);
+            }
+        }
+        let summary_jobs: Vec<_> = jobs
+            .iter()
+            .map(|j| {
+                let mut v = serde_json::json!(j);
+                v["errors"] = serde_json::json!(job_errors.get(&j.job_id).cloned().unwrap_or_default());
+                v
+            })
+            .collect();
+        println!(
+            "{}",
+            serde_json::json!({ "type": "summary", "exit_code": exit_code, "jobs": summary_jobs, "items": summary_items })
+        );
+    } else {
+        for job_id in &job_ids {
             println!("{}: {}", msg.job_finished, job_id);
         }
-        _ => {}
     }
+    Ok(exit_code)
+}
 
-    Ok(())
+/// 取消优先，其次是解析失败，再次是 item 失败
+fn download_exit_code(jobs: &[core::model::JobSnapshot]) -> i32 {
+    use core::model::JobStatus;
+    if jobs.iter().any(|j| j.status == JobStatus::Cancelled) {
+        EXIT_CANCELLED
+    } else if jobs.iter().any(|j| j.resolve_failed) {
+        EXIT_RESOLVE_FAILED
+    } else if jobs.iter().any(|j| j.status == JobStatus::Failed) {
+        EXIT_ITEMS_FAILED
+    } else {
+        EXIT_OK
+    }
 }
 
 fn fmt_bytes(n: u64) -> String {
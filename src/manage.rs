@@ -0,0 +1,692 @@
+//! 管理子命令（list / status / cancel / verify-partial / clean / history，以及 resume 用到的查询）：
+//! 直接读写 out_dir 下的续传库，不需要 daemon。
+
+use crate::core::model::{JobId, JobStatus, LinkInput};
+use crate::core::store::{HistoryFilter, ItemProgress, JobRecord, SqliteStore, DB_FILE_NAME};
+use anyhow::Context;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// 打开已有的续传库；库不存在时报错而不是新建
+pub async fn open_store(out_dir: &Path) -> anyhow::Result<SqliteStore> {
+    let db_path = out_dir.join(DB_FILE_NAME);
+    if !db_path.exists() {
+        anyhow::bail!("no download database in {}", out_dir.display());
+    }
+    SqliteStore::open(&db_path).await
+}
+
+/// 命令行里的 id：任务 uuid（可只写前缀）或 items 表的行号
+pub enum Target {
+    Job(JobRecord),
+    Item(ItemProgress),
+}
+
+pub async fn find_target(store: &SqliteStore, id: &str) -> anyhow::Result<Target> {
+    if let Ok(n) = id.trim_start_matches('#').parse::<i64>() {
+        let item = store.load_item_progress(Some(n)).await?.into_iter().next();
+        return item.map(Target::Item).with_context(|| format!("no such item: {}", id));
+    }
+
+    let id = id.to_ascii_lowercase();
+    let mut jobs: Vec<JobRecord> = store
+        .load_jobs()
+        .await?
+        .into_iter()
+        .filter(|j| j.job_id.to_string().starts_with(&id))
+        .collect();
+    match jobs.len() {
+        0 => anyhow::bail!("no such job: {}", id),
+        1 => Ok(Target::Job(jobs.remove(0))),
+        n => anyhow::bail!("job id prefix {} is ambiguous ({} jobs)", id, n),
+    }
+}
+
+/// (source_uri, target_path) -> 所属任务；不属于任何任务的 items 行是旧版本留下的
+pub async fn job_item_index(store: &SqliteStore) -> anyhow::Result<HashMap<(String, PathBuf), JobRecord>> {
+    let mut out = HashMap::new();
+    for job in store.load_jobs().await? {
+        for r in store.load_job_items(job.job_id).await? {
+            if let Some(res0) = r.item.resources.first() {
+                out.insert((res0.uri.clone(), r.item.target_path.clone()), job.clone());
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// 按 items 表记录重新下载：同一个来源，写到原来的目标路径。
+/// 目标目录以相对 out_dir 的路径传给 engine（库就在 out_dir 里，items 表的目标路径本来就在它下面）
+pub fn restart_input(
+    p: &ItemProgress,
+    out_dir: &Path,
+    headers: &HashMap<String, String>,
+    options: &HashMap<String, String>,
+) -> anyhow::Result<LinkInput> {
+    let file_name = p
+        .target_path
+        .file_name()
+        .with_context(|| format!("item {} has no target file name", p.item_db_id))?;
+    let parent = std::path::absolute(p.target_path.parent().unwrap_or(Path::new(".")))?;
+    let base = std::path::absolute(out_dir)?;
+    let rel = parent.strip_prefix(&base).with_context(|| {
+        format!("item {} target {} is not inside {}", p.item_db_id, p.target_path.display(), out_dir.display())
+    })?;
+
+    let mut options = options.clone();
+    options.insert("out".to_string(), file_name.to_string_lossy().to_string());
+    if rel.as_os_str().is_empty() {
+        options.remove("dir");
+    } else {
+        options.insert("dir".to_string(), rel.to_string_lossy().to_string());
+    }
+    Ok(LinkInput { raw: p.source_uri.clone(), headers: headers.clone(), options })
+}
+
+/// 分片全部完成且已合并（.partial 不在了）
+pub fn is_finished(p: &ItemProgress) -> bool {
+    p.is_complete() && !p.partial_path.exists()
+}
+
+fn item_state(p: &ItemProgress) -> &'static str {
+    if p.is_complete() {
+        if p.partial_path.exists() {
+            "assembling"
+        } else {
+            "done"
+        }
+    } else if p.fragments_downloading > 0 {
+        "downloading"
+    } else if p.fragments_done > 0 || p.downloaded_bytes > 0 {
+        "partial"
+    } else {
+        "pending"
+    }
+}
+
+fn progress(p: &ItemProgress) -> String {
+    let done = p.downloaded_bytes.max(0) as u64;
+    match p.total_size {
+        Some(t) if t > 0 => format!(
+            "{} / {} ({:.0}%)",
+            crate::fmt_bytes(done),
+            crate::fmt_bytes(t as u64),
+            done as f64 * 100.0 / t as f64
+        ),
+        _ => crate::fmt_bytes(done),
+    }
+}
+
+pub async fn list(store: &SqliteStore) -> anyhow::Result<()> {
+    let jobs = store.load_jobs().await?;
+    if !jobs.is_empty() {
+        println!("Jobs:");
+        for job in &jobs {
+            let items = store.load_job_items(job.job_id).await?;
+            let done = items.iter().filter(|r| r.status == crate::core::model::ItemStatus::Done).count();
+            let first = job.inputs.first().map(|i| i.raw.as_str()).unwrap_or("");
+            let more = if job.inputs.len() > 1 { format!(" (+{} more)", job.inputs.len() - 1) } else { String::new() };
+            println!(
+                "  {}  {:<9}  {}/{} items  {}  {}{}",
+                job.job_id,
+                format!("{:?}", job.status),
+                done,
+                items.len(),
+                fmt_time(job.created_at),
+                first,
+                more
+            );
+        }
+    }
+
+    let items = store.load_item_progress(None).await?;
+    if !items.is_empty() {
+        println!("Items:");
+        for p in &items {
+            println!(
+                "  #{:<4} {:<11} {:<28} {:>4}/{:<4} frags  {}",
+                p.item_db_id,
+                item_state(p),
+                progress(p),
+                p.fragments_done,
+                p.fragments,
+                p.target_path.display()
+            );
+        }
+    }
+    if jobs.is_empty() && items.is_empty() {
+        println!("No downloads recorded.");
+    }
+    Ok(())
+}
+
+pub async fn status(store: &SqliteStore, id: &str) -> anyhow::Result<()> {
+    match find_target(store, id).await? {
+        Target::Job(job) => {
+            println!("job:      {}", job.job_id);
+            println!("status:   {:?}", job.status);
+            println!("created:  {}", fmt_time(job.created_at));
+            if job.resolve_failed {
+                println!("resolve:  some links could not be resolved");
+            }
+            for i in &job.inputs {
+                println!("link:     {}", i.raw);
+            }
+            for r in store.load_job_items(job.job_id).await? {
+                let uri = r.item.resources.first().map(|res| res.uri.as_str()).unwrap_or("");
+                let rec = store.find_item(uri, &r.item.target_path).await?;
+                let p = match &rec {
+                    Some(rec) => store.load_item_progress(Some(rec.item_db_id)).await?.into_iter().next(),
+                    None => None,
+                };
+                println!(
+                    "item:     {:<11} {} {} ({})",
+                    format!("{:?}", r.status),
+                    p.as_ref().map(|p| format!("#{}", p.item_db_id)).unwrap_or_else(|| "-".to_string()),
+                    r.item.target_path.display(),
+                    p.as_ref().map(progress).unwrap_or_else(|| "not started".to_string()),
+                );
+            }
+        }
+        Target::Item(p) => {
+            println!("item:       #{}", p.item_db_id);
+            println!("state:      {}", item_state(&p));
+            println!("source:     {}", p.source_uri);
+            println!("target:     {}", p.target_path.display());
+            let partial = match std::fs::metadata(&p.partial_path) {
+                Ok(m) => format!("{} bytes", m.len()),
+                Err(_) => "absent".to_string(),
+            };
+            println!("partial:    {} ({})", p.partial_path.display(), partial);
+            println!("progress:   {}", progress(&p));
+            println!(
+                "fragments:  {} total, {} done, {} downloading, {} bad, {} retries",
+                p.fragments, p.fragments_done, p.fragments_downloading, p.fragments_bad, p.retries
+            );
+            println!("ranges:     {}", if p.supports_ranges { "supported" } else { "not supported" });
+            if let Some(e) = &p.etag {
+                println!("etag:       {}", e);
+            }
+            if let Some(l) = &p.last_modified {
+                println!("modified:   {}", l);
+            }
+            println!("updated:    {}", fmt_time(p.updated_at));
+        }
+    }
+    Ok(())
+}
+
+/// 只改库里的状态：之后的 download / resume 不会再自动恢复这些任务。
+/// 不影响其他进程（如 daemon）中正在运行的任务。
+pub async fn cancel(store: &SqliteStore, ids: &[String]) -> anyhow::Result<()> {
+    for id in ids {
+        let Target::Job(job) = find_target(store, id).await? else {
+            anyhow::bail!("{} is an item; cancel takes job ids", id);
+        };
+        match job.status {
+            JobStatus::Pending | JobStatus::Running | JobStatus::Paused => {
+                store.set_job_status(job.job_id, JobStatus::Cancelled).await?;
+                println!("cancelled {}", job.job_id);
+            }
+            s => println!("{} already finished ({:?})", job.job_id, s),
+        }
+    }
+    Ok(())
+}
+
+/// 删除已完成的任务与 items 行，以及没有任何记录引用的 .partial 文件
+pub async fn clean(store: &SqliteStore, out_dir: &Path, dry_run: bool) -> anyhow::Result<()> {
+    let verb = if dry_run { "would remove" } else { "removed" };
+
+    let jobs = store.load_jobs().await?;
+    for job in jobs.iter().filter(|j| j.status == JobStatus::Completed) {
+        if !dry_run {
+            store.delete_job(job.job_id).await?;
+        }
+        println!("{} job {}", verb, job.job_id);
+    }
+
+    let mut referenced: Vec<PathBuf> = vec![];
+    for p in store.load_item_progress(None).await? {
+        if is_finished(&p) {
+            if !dry_run {
+                store.delete_item(p.item_db_id).await?;
+            }
+            println!("{} item #{} {}", verb, p.item_db_id, p.target_path.display());
+        } else {
+            referenced.push(p.partial_path);
+        }
+    }
+    // 还没开始下载的 item 没有 items 行，但可能已由外部工具写了 .partial
+    let unfinished: HashSet<JobId> = jobs
+        .iter()
+        .filter(|j| !matches!(j.status, JobStatus::Completed))
+        .map(|j| j.job_id)
+        .collect();
+    for job_id in unfinished {
+        for r in store.load_job_items(job_id).await? {
+            referenced.push(r.item.target_path.with_extension("partial"));
+        }
+    }
+
+    // 库里记录的路径可能是相对下载时工作目录的：能规范化的按完整路径比较，其余只按文件名比较
+    let mut canonical: HashSet<PathBuf> = HashSet::new();
+    let mut names: HashSet<std::ffi::OsString> = HashSet::new();
+    for p in referenced {
+        match std::fs::canonicalize(&p) {
+            Ok(c) => {
+                canonical.insert(c);
+            }
+            Err(_) => {
+                if let Some(n) = p.file_name() {
+                    names.insert(n.to_os_string());
+                }
+            }
+        }
+    }
+
+    let mut dirs = vec![out_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = std::fs::read_dir(&dir).with_context(|| format!("read_dir {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            if path.extension().map(|e| e != "partial").unwrap_or(true) {
+                continue;
+            }
+            let known = std::fs::canonicalize(&path).map(|c| canonical.contains(&c)).unwrap_or(true)
+                || path.file_name().map(|n| names.contains(n)).unwrap_or(true);
+            if known {
+                continue;
+            }
+            if !dry_run {
+                std::fs::remove_file(&path).with_context(|| format!("remove {}", path.display()))?;
+            }
+            println!("{} orphaned {}", verb, path.display());
+        }
+    }
+    Ok(())
+}
+
+/// 复查 .partial：不带 id 时检查所有留有 .partial 的 item；任务 id 代表其全部 item
+pub async fn verify_partial(store: &SqliteStore, ids: &[String]) -> anyhow::Result<()> {
+    let mut items: Vec<ItemProgress> = vec![];
+    if ids.is_empty() {
+        items = store
+            .load_item_progress(None)
+            .await?
+            .into_iter()
+            .filter(|p| p.partial_path.exists())
+            .collect();
+    }
+    for id in ids {
+        match find_target(store, id).await? {
+            Target::Item(p) => items.push(p),
+            Target::Job(job) => {
+                for r in store.load_job_items(job.job_id).await? {
+                    let uri = r.item.resources.first().map(|res| res.uri.as_str()).unwrap_or("");
+                    if let Some(rec) = store.find_item(uri, &r.item.target_path).await? {
+                        items.extend(store.load_item_progress(Some(rec.item_db_id)).await?);
+                    }
+                }
+            }
+        }
+    }
+
+    // piece 列表只保存在任务的 item 记录里
+    let mut pieces = HashMap::new();
+    for job in store.load_jobs().await? {
+        for r in store.load_job_items(job.job_id).await? {
+            if let (Some(res0), Some(p)) = (r.item.resources.first(), r.item.piece_hashes) {
+                pieces.insert((res0.uri.clone(), r.item.target_path.clone()), p);
+            }
+        }
+    }
+
+    for p in items {
+        if is_finished(&p) {
+            println!("#{} {}: already finished", p.item_db_id, p.target_path.display());
+            continue;
+        }
+        let report = crate::core::recovery::verify_partial(
+            store,
+            p.item_db_id,
+            &p.partial_path,
+            pieces.get(&(p.source_uri.clone(), p.target_path.clone())),
+            p.total_size.map(|t| t as u64),
+        )
+        .await?;
+        println!(
+            "#{} {}: {} verified, {} bad, {} missing from .partial, {} without hash",
+            p.item_db_id,
+            p.partial_path.display(),
+            report.checked,
+            report.bad,
+            report.missing,
+            report.unverified
+        );
+    }
+    Ok(())
+}
+
+/// 下载历史：逐条列出（最近的在前），或按天 / 主机汇总（by = "day" / "host"）
+pub async fn history(
+    store: &SqliteStore,
+    filter: &HistoryFilter,
+    limit: Option<i64>,
+    by: Option<&str>,
+    json_output: bool,
+) -> anyhow::Result<()> {
+    for line in history_lines(store, filter, limit, by, json_output).await? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// history 要输出的各行（JSON 模式下每行一个对象）
+async fn history_lines(
+    store: &SqliteStore,
+    filter: &HistoryFilter,
+    limit: Option<i64>,
+    by: Option<&str>,
+    json_output: bool,
+) -> anyhow::Result<Vec<String>> {
+    let mut out = vec![];
+    if let Some(by) = by {
+        let totals = store.history_totals(filter, by == "host").await?;
+        if json_output {
+            for t in &totals {
+                out.push(serde_json::to_string(t)?);
+            }
+            return Ok(out);
+        }
+        out.push(format!("{:<24} {:>6} {:>6} {:>12}", by, "runs", "failed", "bytes"));
+        for t in &totals {
+            out.push(format!("{:<24} {:>6} {:>6} {:>12}", t.key, t.runs, t.failed, crate::fmt_bytes(t.bytes.max(0) as u64)));
+        }
+        return Ok(out);
+    }
+
+    let records = store.load_history(filter, limit).await?;
+    if json_output {
+        for r in &records {
+            out.push(serde_json::to_string(r)?);
+        }
+        return Ok(out);
+    }
+    for r in &records {
+        out.push(format!(
+            "{}  {:<9} {:>10}  {:>10}/s (peak {}/s)  {:<16} {:<12} {}",
+            fmt_time(r.finished_at),
+            r.status,
+            crate::fmt_bytes(r.bytes.max(0) as u64),
+            crate::fmt_bytes(r.avg_speed.max(0) as u64),
+            crate::fmt_bytes(r.peak_speed.max(0) as u64),
+            r.host.as_deref().unwrap_or("-"),
+            r.driver.as_deref().unwrap_or("-"),
+            r.target_path.display()
+        ));
+        if let Some(e) = &r.error {
+            out.push(format!("    error: {}", e));
+        }
+    }
+    let bytes: i64 = records.iter().map(|r| r.bytes).sum();
+    let failed = records.iter().filter(|r| r.status == "failed").count();
+    out.push(format!("{} runs, {} failed, {} transferred", records.len(), failed, crate::fmt_bytes(bytes.max(0) as u64)));
+    Ok(out)
+}
+
+/// `--since` / `--until` 的取值：`7d`、`12h`（相对现在），`YYYY-MM-DD` 或 `YYYY-MM-DD HH:MM[:SS]`（UTC）。
+/// end_of_day 时只写日期表示当天结束（用于 --until）
+pub fn parse_time(s: &str, end_of_day: bool) -> anyhow::Result<i64> {
+    let s = s.trim();
+    let now = SqliteStore::now_epoch();
+    if let Some(n) = s.strip_suffix('d').and_then(|n| n.parse::<i64>().ok()) {
+        return Ok(now - n * 86_400);
+    }
+    if let Some(n) = s.strip_suffix('h').and_then(|n| n.parse::<i64>().ok()) {
+        return Ok(now - n * 3600);
+    }
+
+    let bad = || anyhow::anyhow!("invalid time {:?}: expected 7d, 12h, YYYY-MM-DD or YYYY-MM-DD HH:MM[:SS]", s);
+    let (date, time) = match s.split_once([' ', 'T']) {
+        Some((d, t)) => (d, Some(t)),
+        None => (s, None),
+    };
+    let ymd: Vec<i64> = date.split('-').map(|p| p.parse().map_err(|_| bad())).collect::<anyhow::Result<_>>()?;
+    let [y, m, d] = ymd[..] else { return Err(bad()) };
+    if !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+        return Err(bad());
+    }
+    let day_start = days_from_civil(y, m, d) * 86_400;
+
+    let Some(time) = time else {
+        return Ok(if end_of_day { day_start + 86_400 } else { day_start });
+    };
+    let hms: Vec<i64> = time.split(':').map(|p| p.parse().map_err(|_| bad())).collect::<anyhow::Result<_>>()?;
+    let (h, mi, sec) = match hms[..] {
+        [h, mi] => (h, mi, 0),
+        [h, mi, sec] => (h, mi, sec),
+        _ => return Err(bad()),
+    };
+    if !(0..24).contains(&h) || !(0..60).contains(&mi) || !(0..60).contains(&sec) {
+        return Err(bad());
+    }
+    Ok(day_start + h * 3600 + mi * 60 + sec)
+}
+
+/// 公历日期 -> 1970-01-01 起的天数（Howard Hinnant 的 days_from_civil）
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if m > 2 { m - 3 } else { m + 9 };
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// unix 秒 -> `YYYY-MM-DD HH:MM:SS UTC`
+pub fn fmt_time(epoch: i64) -> String {
+    let days = epoch.div_euclid(86_400);
+    let secs = epoch.rem_euclid(86_400);
+    // 由天数推公历日期（Howard Hinnant 的 civil_from_days）
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", y, m, d, secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::model::FragmentState;
+    use crate::core::testutil::{test_dir, test_item};
+
+    #[test]
+    fn parses_dates_and_times() {
+        assert_eq!(parse_time("1970-01-01", false).unwrap(), 0);
+        assert_eq!(parse_time("1970-01-01", true).unwrap(), 86_400);
+        assert_eq!(parse_time("2000-02-29", false).unwrap(), 951_782_400);
+        assert_eq!(parse_time("2000-02-29", true).unwrap(), 951_868_800);
+        assert_eq!(parse_time(" 2024-02-29 12:34:56 ", false).unwrap(), 1_709_210_096);
+        // 带时间时 end_of_day 不起作用
+        assert_eq!(parse_time("2024-02-29T12:34", true).unwrap(), 1_709_210_040);
+        assert_eq!(parse_time("1969-12-31 23:59:59", false).unwrap(), -1);
+    }
+
+    #[test]
+    fn parses_relative_times() {
+        let now = SqliteStore::now_epoch();
+        let d = parse_time("7d", false).unwrap();
+        assert!((now - 7 * 86_400..=now - 7 * 86_400 + 2).contains(&d), "{}", d);
+        let h = parse_time("12h", true).unwrap();
+        assert!((now - 12 * 3600..=now - 12 * 3600 + 2).contains(&h), "{}", h);
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        let bad = [
+            "", "yesterday", "7x", "2024-01", "2024-1-1-1", "2024-13-01", "2024-00-10", "2024-01-32",
+            "2024-01-01 12", "2024-01-01 24:00", "2024-01-01 12:60", "2024-01-01 12:00:00:00", "2024-01-01 aa:00",
+        ];
+        for s in bad {
+            let e = parse_time(s, false).unwrap_err();
+            assert!(e.to_string().contains("invalid time"), "{:?}: {}", s, e);
+        }
+    }
+
+    #[test]
+    fn civil_day_arithmetic() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        // 2000 是闰年，1900 / 2100 不是
+        assert_eq!(days_from_civil(2000, 3, 1) - days_from_civil(2000, 2, 28), 2);
+        assert_eq!(days_from_civil(2100, 3, 1) - days_from_civil(2100, 2, 28), 1);
+        assert_eq!(days_from_civil(1900, 3, 1) - days_from_civil(1900, 2, 28), 1);
+        assert_eq!(days_from_civil(2024, 1, 1) - days_from_civil(2023, 1, 1), 365);
+        assert_eq!(days_from_civil(2025, 1, 1) - days_from_civil(2024, 1, 1), 366);
+    }
+
+    #[test]
+    fn formats_times() {
+        assert_eq!(fmt_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(fmt_time(-1), "1969-12-31 23:59:59 UTC");
+        assert_eq!(fmt_time(951_782_400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(fmt_time(4_107_542_400), "2100-03-01 00:00:00 UTC");
+        // 与 parse_time 互逆
+        for t in [1_709_210_096, 951_868_799, 86_399] {
+            let s = fmt_time(t);
+            assert_eq!(parse_time(s.trim_end_matches(" UTC"), false).unwrap(), t, "{}", s);
+        }
+    }
+
+    async fn item_progress(store: &SqliteStore, target: &Path) -> ItemProgress {
+        let rec = store
+            .upsert_item("https://example.com/a.iso", target, &target.with_extension("partial"), 4, Some(8), true)
+            .await
+            .unwrap();
+        store.load_item_progress(Some(rec.item_db_id)).await.unwrap().remove(0)
+    }
+
+    #[tokio::test]
+    async fn restart_input_resolves_against_out_dir() {
+        let tmp = test_dir();
+        let out = tmp.path().join("dl");
+        let store = SqliteStore::open(&out.join(DB_FILE_NAME)).await.unwrap();
+        let headers = HashMap::from([("Referer".to_string(), "r".to_string())]);
+        let options = HashMap::from([("dir".to_string(), "cli".to_string()), ("ftp_user".to_string(), "u".to_string())]);
+
+        let p = item_progress(&store, &out.join("sub/a.iso")).await;
+        let input = restart_input(&p, &out, &headers, &options).unwrap();
+        assert_eq!(input.raw, "https://example.com/a.iso");
+        assert_eq!(input.headers, headers);
+        assert_eq!(input.options["out"], "a.iso");
+        assert_eq!(input.options["dir"], "sub");
+        assert_eq!(input.options["ftp_user"], "u");
+
+        // 直接在 out_dir 下：不传 dir（也不沿用命令行的 dir）
+        let p = item_progress(&store, &out.join("./b.iso")).await;
+        let input = restart_input(&p, &out, &headers, &options).unwrap();
+        assert_eq!(input.options["out"], "b.iso");
+        assert!(!input.options.contains_key("dir"));
+
+        let p = item_progress(&store, &tmp.path().join("elsewhere/c.iso")).await;
+        assert!(restart_input(&p, &out, &headers, &options).is_err());
+    }
+
+    #[tokio::test]
+    async fn clean_removes_finished_records_and_orphaned_partials() {
+        let tmp = test_dir();
+        let out = tmp.path().to_path_buf();
+        let store = SqliteStore::open(&out.join(DB_FILE_NAME)).await.unwrap();
+
+        // 已完成的 item：分片全部 Done，.partial 已合并掉
+        let done = store
+            .upsert_item("https://example.com/done.iso", &out.join("done.iso"), &out.join("done.partial"), 4, Some(4), true)
+            .await
+            .unwrap();
+        store.ensure_fragments_for_ranges(done.item_db_id, &[(0, 4)]).await.unwrap();
+        let frag = store.load_fragments(done.item_db_id).await.unwrap().remove(0);
+        store.mark_fragment_done_and_add_bytes(frag.frag_db_id, done.item_db_id, 4, None).await.unwrap();
+        assert_eq!(store.load_fragments(done.item_db_id).await.unwrap()[0].state, FragmentState::Done);
+        // 下载到一半的 item
+        let partial = store
+            .upsert_item("https://example.com/half.iso", &out.join("half.iso"), &out.join("half.partial"), 4, Some(8), true)
+            .await
+            .unwrap();
+        std::fs::write(out.join("half.partial"), b"1234").unwrap();
+        // 未完成任务里还没有 items 行的 item（外部工具可能已经写了 .partial）
+        let queued = test_item(&out, "queued.bin");
+        let job_id = queued.job_id;
+        store.insert_job(job_id, &[], "test").await.unwrap();
+        store.save_job_items(job_id, &[queued], false).await.unwrap();
+        std::fs::write(out.join("queued.partial"), b"").unwrap();
+        // 已完成的任务
+        let finished_job = uuid::Uuid::new_v4();
+        store.insert_job(finished_job, &[], "test").await.unwrap();
+        store.set_job_status(finished_job, JobStatus::Completed).await.unwrap();
+        // 没有任何记录引用的 .partial，以及其他文件
+        std::fs::create_dir(out.join("sub")).unwrap();
+        std::fs::write(out.join("orphan.partial"), b"x").unwrap();
+        std::fs::write(out.join("sub/old.partial"), b"x").unwrap();
+        std::fs::write(out.join("keep.bin"), b"x").unwrap();
+
+        clean(&store, &out, true).await.unwrap();
+        assert!(out.join("orphan.partial").exists() && out.join("sub/old.partial").exists());
+        assert_eq!(store.load_item_progress(None).await.unwrap().len(), 2);
+        assert_eq!(store.load_jobs().await.unwrap().len(), 2);
+
+        clean(&store, &out, false).await.unwrap();
+        assert!(!out.join("orphan.partial").exists());
+        assert!(!out.join("sub/old.partial").exists());
+        assert!(out.join("half.partial").exists());
+        assert!(out.join("queued.partial").exists());
+        assert!(out.join("keep.bin").exists());
+        let items: Vec<i64> = store.load_item_progress(None).await.unwrap().iter().map(|p| p.item_db_id).collect();
+        assert_eq!(items, [partial.item_db_id]);
+        let jobs: Vec<JobId> = store.load_jobs().await.unwrap().iter().map(|j| j.job_id).collect();
+        assert_eq!(jobs, [job_id]);
+    }
+
+    #[tokio::test]
+    async fn history_lists_and_summarizes() {
+        use crate::core::testutil::test_history;
+        let tmp = test_dir();
+        let store = SqliteStore::open(&tmp.path().join(DB_FILE_NAME)).await.unwrap();
+        let filter = HistoryFilter::default();
+        assert_eq!(history_lines(&store, &filter, None, None, false).await.unwrap(), ["0 runs, 0 failed, 0B transferred"]);
+
+        store.insert_history(&test_history(Some("a.example"), "done", 2048, 951_782_400)).await.unwrap();
+        store.insert_history(&test_history(Some("b.example"), "failed", 10, 951_782_500)).await.unwrap();
+
+        let lines = history_lines(&store, &filter, None, None, false).await.unwrap();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("2000-02-29 00:01:40 UTC  failed"), "{}", lines[0]);
+        assert!(lines[0].contains("b.example"), "{}", lines[0]);
+        assert_eq!(lines[1], "    error: connection reset");
+        assert!(lines[2].contains("2.00KiB") && lines[2].contains("a.example"), "{}", lines[2]);
+        assert_eq!(lines[3], "2 runs, 1 failed, 2.01KiB transferred");
+
+        let lines = history_lines(&store, &filter, Some(1), None, true).await.unwrap();
+        assert_eq!(lines.len(), 1);
+        let v: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!((v["status"].as_str(), v["bytes"].as_i64()), (Some("failed"), Some(10)));
+
+        let lines = history_lines(&store, &filter, None, Some("host"), false).await.unwrap();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("host ") && lines[1].starts_with("a.example "), "{:?}", lines);
+        let lines = history_lines(&store, &filter, None, Some("day"), true).await.unwrap();
+        let v: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(v, serde_json::json!({ "key": "2000-02-29", "runs": 2, "failed": 1, "bytes": 2058 }));
+    }
+}
@@ -1,6 +1,5 @@
 use crate::core::model::ResourceDescriptor;
-use crate::plugins::registry::DriverContext;
-use anyhow::Context;
+use crate::plugins::registry::{DriverContext, ExternalToolFailed};
 use std::path::Path;
 use tokio::process::Command;
 
@@ -52,10 +51,16 @@ impl AdbDriver {
         cmd.arg(&device_path);
         cmd.arg(&tmp_path);
 
-        let out = cmd.output().await.context("spawn adb pull")?;
+        // engine 暂停/取消时会丢弃该 future，确保子进程随之结束
+        cmd.kill_on_drop(true);
+
+        let out = cmd.output().await.map_err(|e| ExternalToolFailed {
+            tool: "adb pull".to_string(),
+            message: format!("cannot spawn: {}", e),
+        })?;
         if !out.status.success() {
             let stderr = String::from_utf8_lossy(&out.stderr).to_string();
-            anyhow::bail!("adb pull failed: {}", stderr.trim());
+            return Err(ExternalToolFailed { tool: "adb pull".to_string(), message: stderr.trim().to_string() }.into());
         }
 
         if tokio::fs::metadata(target_path).await.is_ok() {
@@ -65,6 +65,8 @@ impl LinkResolver for AdbResolver {
                 suggested_path,
                 total_size: None,
                 resources: vec![res],
+                checksums: vec![],
+                piece_hashes: None,
             }],
             warnings: vec![
                 "ADB pull uses local adb binary; ensure a device is connected and authorized.".into(),
@@ -69,6 +69,8 @@ impl LinkResolver for BtResolver {
                 suggested_path: ctx.out_dir.join(&dn),
                 total_size: None,
                 resources: vec![res],
+                checksums: vec![],
+                piece_hashes: None,
             }],
             warnings: vec![],
         })
@@ -1,5 +1,5 @@
 use crate::core::model::ResourceDescriptor;
-use crate::plugins::registry::DriverContext;
+use crate::plugins::registry::{DriverContext, ExternalToolFailed};
 use anyhow::Context;
 use std::path::Path;
 use tokio::process::Command;
@@ -52,10 +52,16 @@ impl Ed2kDriver {
             proc.arg(replace(&a));
         }
 
-        let out = proc.output().await.context("spawn ed2k command")?;
+        // engine 暂停/取消时会丢弃该 future，确保子进程随之结束
+        proc.kill_on_drop(true);
+
+        let out = proc.output().await.map_err(|e| ExternalToolFailed {
+            tool: "ed2k command".to_string(),
+            message: format!("cannot spawn: {}", e),
+        })?;
         if !out.status.success() {
             let stderr = String::from_utf8_lossy(&out.stderr).to_string();
-            anyhow::bail!("ed2k command failed: {}", stderr.trim());
+            return Err(ExternalToolFailed { tool: "ed2k command".to_string(), message: stderr.trim().to_string() }.into());
         }
 
         Ok(())
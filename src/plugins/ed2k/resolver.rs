@@ -1,6 +1,6 @@
 use async_trait::async_trait;
 use crate::plugins::registry::{DownloadItemDraft, LinkResolver, ResolveContext, ResolveResult};
-use crate::core::model::{Capabilities, LinkInput, ResourceDescriptor, ResourceType};
+use crate::core::model::{Capabilities, Checksum, HashAlgo, LinkInput, ResourceDescriptor, ResourceType};
 use sanitize_filename::sanitize;
 use std::collections::HashMap;
 
@@ -46,6 +46,9 @@ impl LinkResolver for Ed2kResolver {
             sanitize(&name)
         };
 
+        // 链接自带的 eD2k 哈希用于下载后校验外部客户端的产物
+        let checksums: Vec<Checksum> = Checksum::new(HashAlgo::Ed2k, &hash).ok().into_iter().collect();
+
         let mut meta = HashMap::new();
         meta.insert("name".into(), name);
         meta.insert("size".into(), size.to_string());
@@ -65,6 +68,8 @@ impl LinkResolver for Ed2kResolver {
                 suggested_path: ctx.out_dir.join(&dn),
                 total_size: Some(size),
                 resources: vec![res],
+                checksums,
+                piece_hashes: None,
             }],
             warnings: vec!["ED2K requires an external client command (see --ed2k-cmd).".into()],
         })
@@ -1,15 +1,19 @@
 use async_trait::async_trait;
 use bytes::Bytes;
-use tokio::io::AsyncReadExt;
+use futures::StreamExt;
+use tokio::io::{AsyncReadExt, BufReader};
 
 use crate::core::model::{ResourceDescriptor, ResourceType};
-use crate::plugins::registry::{DriverContext, TransferDriver};
+use crate::plugins::registry::{ByteStream, DriverContext, HostBusy, ProbeInfo, TransferDriver};
 use anyhow::Context;
-use async_ftp::FtpStream;
+use async_ftp::{DataStream, FtpError, FtpStream};
 use url::Url;
 use std::time::Duration;
 use tokio::time::sleep;
 
+/// Size of each chunk read from the FTP data connection.
+const READ_CHUNK: usize = 64 * 1024;
+
 pub struct FtpDriver;
 
 impl FtpDriver {
@@ -56,12 +60,61 @@ impl FtpDriver {
         )
         .await
         .context("ftp connect timeout")?
-        .context("ftp connect")?;
+        .map_err(|e| Self::busy_or(e, "ftp connect"))?;
 
-        ftp.login(user, pass).await.context("ftp login")?;
+        ftp.login(user, pass).await.map_err(|e| Self::busy_or(e, "ftp login"))?;
         Ok(ftp)
     }
 
+    /// 421（连接过多 / 服务暂不可用）交给 engine 按主机退避，其余错误照常带上下文返回
+    fn busy_or(e: FtpError, what: &'static str) -> anyhow::Error {
+        match &e {
+            FtpError::InvalidResponse(desc) if desc.contains("got response: 421") => {
+                HostBusy { retry_after: None }.into()
+            }
+            _ => anyhow::Error::new(e).context(what),
+        }
+    }
+
+    /// Turn an open RETR data connection into a chunk stream.
+    /// `limit` caps the number of bytes read (for REST ranges); the control
+    /// connection is closed once the limit is reached or the server hits EOF.
+    /// A read that makes no progress for `idle` fails the stream instead of
+    /// hanging on a stalled data connection.
+    fn retr_stream(ftp: FtpStream, reader: BufReader<DataStream>, limit: Option<u64>, idle: Duration) -> ByteStream {
+        futures::stream::try_unfold((Some(ftp), reader, limit), move |(mut ftp, mut reader, remaining)| async move {
+            let want = match remaining {
+                Some(r) => r.min(READ_CHUNK as u64) as usize,
+                None => READ_CHUNK,
+            };
+
+            let mut buf = vec![0u8; want];
+            let n = if want == 0 {
+                0
+            } else {
+                tokio::time::timeout(idle, reader.read(&mut buf))
+                    .await
+                    .context("ftp read timeout")?
+                    .context("ftp read")?
+            };
+
+            if n == 0 {
+                if let Some(r) = remaining.filter(|r| *r > 0) {
+                    anyhow::bail!("ftp data connection closed early ({} bytes missing)", r);
+                }
+                drop(reader);
+                if let Some(mut f) = ftp.take() {
+                    let _ = f.quit().await;
+                }
+                return Ok(None);
+            }
+
+            buf.truncate(n);
+            Ok(Some((Bytes::from(buf), (ftp, reader, remaining.map(|r| r - n as u64)))))
+        })
+        .boxed()
+    }
+
     async fn sleep_backoff(ctx: &DriverContext, attempt: u32) {
         let base = ctx.retry_backoff_ms.max(1);
         // Cap the exponent at 16 to avoid overflow; this gives a maximum multiplier of 65536.
@@ -80,43 +133,59 @@ impl TransferDriver for FtpDriver {
         matches!(res.rtype, ResourceType::Ftp)
     }
 
-    /// Probe the FTP server: use the SIZE command to determine file size.
-    /// Returns (Some(size), true) if the server supports SIZE and therefore
-    /// REST-based range downloads; (None, false) otherwise.
-    async fn probe(&self, res: &ResourceDescriptor, ctx: &DriverContext) -> anyhow::Result<(Option<u64>, bool)> {
+    /// Connection limits are counted per control-connection endpoint, which may
+    /// use a port from `res.meta` rather than the URL.
+    fn host_key(&self, res: &ResourceDescriptor) -> Option<String> {
+        let (host, port, ..) = Self::parse_conn(res).ok()?;
+        Some(format!("{}:{}", host.to_ascii_lowercase(), port))
+    }
+
+    /// Probe the FTP server: use the SIZE command to determine file size and
+    /// MDTM for the modification time (used to validate resumes).
+    /// Ranges are reported as supported when the server answers SIZE, since
+    /// REST-based range downloads need a known length.
+    async fn probe(&self, res: &ResourceDescriptor, ctx: &DriverContext) -> anyhow::Result<ProbeInfo> {
         let (host, port, user, pass, path) = match Self::parse_conn(res) {
             Ok(v) => v,
-            Err(_) => return Ok((None, false)),
+            Err(_) => return Ok(ProbeInfo::default()),
         };
         if path.is_empty() {
-            return Ok((None, false));
+            return Ok(ProbeInfo::default());
         }
 
-        let result: anyhow::Result<(Option<u64>, bool)> = async {
+        let result: anyhow::Result<ProbeInfo> = async {
             let mut ftp = Self::connect(&host, port, &user, &pass, ctx).await?;
             let file_size = ftp.size(&path).await.ok().flatten().map(|bytes| bytes as u64);
-            let supports_ranges = file_size.is_some();
+            let modified = ftp.mdtm(&path).await.ok().flatten().map(|t| t.to_string());
             let _ = ftp.quit().await;
-            Ok((file_size, supports_ranges))
+            Ok(ProbeInfo {
+                total_size: file_size,
+                supports_ranges: file_size.is_some(),
+                etag: None,
+                last_modified: modified,
+            })
         }.await;
 
-        Ok(result.unwrap_or((None, false)))
+        match result {
+            Err(e) if e.is::<HostBusy>() => Err(e),
+            r => Ok(r.unwrap_or_default()),
+        }
     }
 
-    /// Download a byte range using the FTP REST+RETR commands.
+    /// Open a byte range using the FTP REST+RETR commands.
     async fn download_range(
         &self,
         res: &ResourceDescriptor,
         ctx: &DriverContext,
         start: u64,
         end_inclusive: u64,
-    ) -> anyhow::Result<Bytes> {
+    ) -> anyhow::Result<ByteStream> {
         let (host, port, user, pass, path) = Self::parse_conn(res)?;
         if path.is_empty() {
             anyhow::bail!("ftp url missing path: {}", res.uri);
         }
 
-        let len = (end_inclusive - start + 1) as usize;
+        let len = end_inclusive - start + 1;
         let mut last_err: Option<anyhow::Error> = None;
 
         for attempt in 0..=ctx.retries {
@@ -124,19 +193,16 @@ impl TransferDriver for FtpDriver {
                 Self::sleep_backoff(ctx, attempt - 1).await;
             }
 
-            let result: anyhow::Result<Bytes> = async {
+            let result: anyhow::Result<ByteStream> = async {
                 let mut ftp = Self::connect(&host, port, &user, &pass, ctx).await?;
                 ftp.restart_from(start).await.context("ftp REST")?;
-                let mut reader = ftp.get(&path).await.context("ftp RETR")?;
-                let mut buf = vec![0u8; len];
-                reader.read_exact(&mut buf).await.context("ftp read range")?;
-                drop(reader);
-                let _ = ftp.quit().await;
-                Ok(Bytes::from(buf))
+                let reader = ftp.get(&path).await.context("ftp RETR")?;
+                Ok(Self::retr_stream(ftp, reader, Some(len), Duration::from_secs(ctx.timeout_secs.max(1))))
             }.await;
 
             match result {
-                Ok(bytes) => return Ok(bytes),
+                Ok(stream) => return Ok(stream),
+                Err(e) if e.is::<HostBusy>() => return Err(e),
                 Err(e) => { last_err = Some(e); }
             }
         }
@@ -144,8 +210,8 @@ impl TransferDriver for FtpDriver {
         Err(last_err.unwrap_or_else(|| anyhow::anyhow!("ftp range download failed after retries")))
     }
 
-    /// Download the entire file using RETR.
-    async fn download_all(&self, res: &ResourceDescriptor, ctx: &DriverContext) -> anyhow::Result<Bytes> {
+    /// Open the entire file using RETR.
+    async fn download_all(&self, res: &ResourceDescriptor, ctx: &DriverContext) -> anyhow::Result<ByteStream> {
         let (host, port, user, pass, path) = Self::parse_conn(res)?;
         if path.is_empty() {
             anyhow::bail!("ftp url missing path: {}", res.uri);
@@ -158,15 +224,15 @@ impl TransferDriver for FtpDriver {
                 Self::sleep_backoff(ctx, attempt - 1).await;
             }
 
-            let result: anyhow::Result<Bytes> = async {
+            let result: anyhow::Result<ByteStream> = async {
                 let mut ftp = Self::connect(&host, port, &user, &pass, ctx).await?;
-                let cursor = ftp.simple_retr(&path).await.context("ftp RETR")?;
-                let _ = ftp.quit().await;
-                Ok(Bytes::from(cursor.into_inner()))
+                let reader = ftp.get(&path).await.context("ftp RETR")?;
+                Ok(Self::retr_stream(ftp, reader, None, Duration::from_secs(ctx.timeout_secs.max(1))))
             }.await;
 
             match result {
-                Ok(bytes) => return Ok(bytes),
+                Ok(stream) => return Ok(stream),
+                Err(e) if e.is::<HostBusy>() => return Err(e),
                 Err(e) => { last_err = Some(e); }
             }
         }
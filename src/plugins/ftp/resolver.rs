@@ -82,6 +82,8 @@ impl LinkResolver for FtpResolver {
                 suggested_path,
                 total_size: None,
                 resources: vec![res],
+                checksums: vec![],
+                piece_hashes: None,
             }],
             warnings: vec![],
         })
@@ -1,13 +1,19 @@
 use async_trait::async_trait;
 use crate::plugins::registry::{DownloadItemDraft, LinkResolver, ResolveContext, ResolveResult};
-use crate::core::model::{Capabilities, LinkInput, ResourceDescriptor, ResourceType};
+use crate::core::model::{Capabilities, Checksum, HashAlgo, LinkInput, ResourceDescriptor, ResourceType};
+use reqwest::header::{ACCEPT, AUTHORIZATION, USER_AGENT};
 use sanitize_filename::sanitize;
+use std::time::Duration;
 use url::Url;
 
-pub struct GitHubResolver;
+pub struct GitHubResolver {
+    client: reqwest::Client,
+}
 
 impl GitHubResolver {
-    pub fn new() -> Self { Self }
+    pub fn new() -> Self {
+        Self { client: reqwest::Client::new() }
+    }
 
     fn is_github_host(host: &str) -> bool {
         host.eq_ignore_ascii_case("github.com")
@@ -43,6 +49,47 @@ impl GitHubResolver {
         zip.set_path(&format!("{}/{}/archive/refs/heads/main.zip", owner, repo));
         Some(zip)
     }
+
+    fn release_asset(u: &Url) -> Option<(String, String, String, String)> {
+        // https://github.com/owner/repo/releases/download/<tag>/<asset>
+        if u.host_str()? != "github.com" { return None; }
+        let seg: Vec<_> = u.path_segments()?.collect();
+        if seg.len() != 6 || seg[2] != "releases" || seg[3] != "download" { return None; }
+        Some((seg[0].to_string(), seg[1].to_string(), seg[4].to_string(), seg[5].to_string()))
+    }
+
+    /// 通过 GitHub API 查询 release 资源的 digest（形如 "sha256:<hex>"，旧 release 可能没有）
+    async fn release_digest(
+        &self,
+        input: &LinkInput,
+        ctx: &ResolveContext,
+        (owner, repo, tag, asset): &(String, String, String, String),
+    ) -> anyhow::Result<Option<Checksum>> {
+        let api = format!("https://api.github.com/repos/{}/{}/releases/tags/{}", owner, repo, tag);
+        let mut req = self.client
+            .get(&api)
+            .header(USER_AGENT, &ctx.user_agent)
+            .header(ACCEPT, "application/vnd.github+json")
+            .timeout(Duration::from_secs(15));
+        // 私有仓库需要 --header 'Authorization: Bearer ...'
+        if let Some((_, v)) = input.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case("authorization")) {
+            req = req.header(AUTHORIZATION, v);
+        }
+
+        let body: serde_json::Value = req.send().await?.error_for_status()?.json().await?;
+        let digest = body["assets"]
+            .as_array()
+            .and_then(|assets| assets.iter().find(|a| a["name"].as_str() == Some(asset.as_str())))
+            .and_then(|a| a["digest"].as_str());
+
+        match digest.and_then(|d| d.split_once(':')) {
+            Some((algo, hex)) => {
+                let algo = HashAlgo::parse(algo).ok_or_else(|| anyhow::anyhow!("unknown digest algorithm: {}", algo))?;
+                Ok(Some(Checksum::new(algo, hex)?))
+            }
+            None => Ok(None),
+        }
+    }
 }
 
 #[async_trait]
@@ -87,6 +134,16 @@ impl LinkResolver for GitHubResolver {
 
         let suggested_path = ctx.out_dir.join(filename);
 
+        let mut checksums = vec![];
+        let mut warnings = vec![];
+        if let Some(asset) = Self::release_asset(&final_url) {
+            match self.release_digest(input, ctx, &asset).await {
+                Ok(Some(c)) => checksums.push(c),
+                Ok(None) => {}
+                Err(e) => warnings.push(format!("could not fetch release digest for {}: {:#}", asset.3, e)),
+            }
+        }
+
         let res = ResourceDescriptor {
             rtype: ResourceType::GitHubResolvedHttp,
             uri: final_url.to_string(),
@@ -101,8 +158,10 @@ impl LinkResolver for GitHubResolver {
                 suggested_path,
                 total_size: None,
                 resources: vec![res],
+                checksums,
+                piece_hashes: None,
             }],
-            warnings: vec![],
+            warnings,
         })
     }
 }
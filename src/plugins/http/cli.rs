@@ -35,7 +35,7 @@ impl CliPlugin for HttpCliPlugin {
             Arg::new("http_timeout_secs")
                 .long("timeout-secs")
                 .help_heading("HTTP")
-                .help("HTTP timeout in seconds")
+                .help("HTTP connect and read-idle timeout in seconds")
                 .default_value("60")
                 .num_args(1),
         )
@@ -55,6 +55,13 @@ impl CliPlugin for HttpCliPlugin {
                 .default_value("400")
                 .num_args(1),
         )
+        .arg(
+            Arg::new("http_checksum_sidecar")
+                .long("checksum-sidecar")
+                .help_heading("HTTP")
+                .help("Look for <url>.sha256/.sha512/.sha1/.md5 next to each file and verify against it")
+                .action(ArgAction::SetTrue),
+        )
     }
 
     fn apply_download_matches(&self, matches: &ArgMatches, cfg: &mut DownloadCliConfig) -> anyhow::Result<()> {
@@ -71,6 +78,10 @@ impl CliPlugin for HttpCliPlugin {
             cfg.driver_ctx.retry_backoff_ms = s.parse()?;
         }
 
+        if matches.get_flag("http_checksum_sidecar") {
+            cfg.options.insert("checksum_sidecar".to_string(), "1".to_string());
+        }
+
         if let Some(values) = matches.get_many::<String>("http_header") {
             for h in values {
                 let (k, v) = h
@@ -1,37 +1,60 @@
 use async_trait::async_trait;
-use bytes::Bytes;
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, RANGE, USER_AGENT};
+use futures::StreamExt;
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED,
+    RANGE, RETRY_AFTER, USER_AGENT,
+};
 use reqwest::StatusCode;
 use std::time::Duration;
 use tokio::time::sleep;
 
 use crate::core::model::{ResourceDescriptor, ResourceType};
-use crate::plugins::registry::{DriverContext, TransferDriver};
+use crate::plugins::registry::{ByteStream, DriverContext, HostBusy, ProbeInfo, TransferDriver};
 
 #[derive(thiserror::Error, Debug)]
 pub enum HttpDriverError {
     #[error("range not supported by server (verified)")]
     RangeNotSupported,
 
-    /// 服务器忽略 Range，直接返回 200 + 全量 body
+    /// 服务器忽略 Range，直接返回 200 + 全量 body（body 已丢弃，不在内存中缓存）
     #[error("server ignored range and returned full content")]
-    RangeIgnoredFull(Bytes),
+    RangeIgnoredFull,
+
+    /// 带 If-Range 请求却拿到 200 且验证器不同：远端文件已变化
+    #[error("remote entity changed (If-Range validator mismatch)")]
+    ValidatorChanged,
 
     #[error("http status error: {0}")]
     Status(StatusCode),
 }
 
 pub struct HttpDriver {
-    client: reqwest::Client,
+    /// 按 timeout_secs 构建的 client（连接池随 client 复用，超时变了才重建）
+    client: std::sync::Mutex<Option<(u64, reqwest::Client)>>,
 }
 
 impl HttpDriver {
     pub fn new() -> Self {
+        Self { client: std::sync::Mutex::new(None) }
+    }
+
+    /// 不设整体请求超时（它也覆盖读 body，大文件流式下载会被中途掐断）：
+    /// 只限制建立连接的时间，以及 body 两次读到数据之间的空闲时间
+    fn client(&self, ctx: &DriverContext) -> anyhow::Result<reqwest::Client> {
+        let mut cached = self.client.lock().unwrap();
+        if let Some((secs, client)) = cached.as_ref() {
+            if *secs == ctx.timeout_secs {
+                return Ok(client.clone());
+            }
+        }
+        let timeout = Duration::from_secs(ctx.timeout_secs.max(1));
         let client = reqwest::Client::builder()
             .redirect(reqwest::redirect::Policy::limited(10))
-            .build()
-            .expect("reqwest client");
-        Self { client }
+            .connect_timeout(timeout)
+            .read_timeout(timeout)
+            .build()?;
+        *cached = Some((ctx.timeout_secs, client.clone()));
+        Ok(client)
     }
 
     fn build_headers(res: &ResourceDescriptor, ctx: &DriverContext) -> anyhow::Result<HeaderMap> {
@@ -59,6 +82,56 @@ impl HttpDriver {
         sleep(Duration::from_millis(ms)).await;
     }
 
+    fn body_stream(resp: reqwest::Response) -> ByteStream {
+        resp.bytes_stream()
+            .map(|r| r.map_err(anyhow::Error::from))
+            .boxed()
+    }
+
+    fn header_string(resp: &reqwest::Response, name: HeaderName) -> Option<String> {
+        resp.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string())
+    }
+
+    /// If-Range 只能用强 ETag；弱 ETag 时退而使用 Last-Modified
+    fn if_range_validator(res: &ResourceDescriptor) -> Option<String> {
+        res.meta
+            .get("etag")
+            .filter(|e| !e.starts_with("W/"))
+            .or_else(|| res.meta.get("last_modified"))
+            .cloned()
+    }
+
+    /// 200 响应是否仍是同一个实体（无法判断时视为相同，即服务器只是忽略了 Range）
+    fn same_entity(resp: &reqwest::Response, res: &ResourceDescriptor) -> bool {
+        if let (Some(ours), Some(theirs)) = (res.meta.get("etag"), Self::header_string(resp, ETAG)) {
+            return *ours == theirs;
+        }
+        if let (Some(ours), Some(theirs)) = (res.meta.get("last_modified"), Self::header_string(resp, LAST_MODIFIED)) {
+            return *ours == theirs;
+        }
+        true
+    }
+
+    /// 429/503 交给 engine 按主机退避（不在驱动里原地重试，以免继续加重服务器负担）
+    fn host_busy(resp: &reqwest::Response) -> Option<HostBusy> {
+        if !matches!(resp.status(), StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE) {
+            return None;
+        }
+        // Retry-After: 秒数或 HTTP-date
+        let retry_after = Self::header_string(resp, RETRY_AFTER).and_then(|v| {
+            let v = v.trim();
+            v.parse::<u64>().ok().map(Duration::from_secs).or_else(|| {
+                httpdate::parse_http_date(v)
+                    .ok()
+                    .map(|t| t.duration_since(std::time::SystemTime::now()).unwrap_or_default())
+            })
+        });
+        Some(HostBusy { retry_after })
+    }
+
     fn accept_ranges_hint(resp: &reqwest::Response) -> bool {
         resp.headers()
             .get(ACCEPT_RANGES)
@@ -81,15 +154,18 @@ impl TransferDriver for HttpDriver {
     }
 
     /// ✅ 真 Range 探测：HEAD + GET bytes=0-0 => 必须 206 + Content-Range
-    async fn probe(&self, res: &ResourceDescriptor, ctx: &DriverContext) -> anyhow::Result<(Option<u64>, bool)> {
+    async fn probe(&self, res: &ResourceDescriptor, ctx: &DriverContext) -> anyhow::Result<ProbeInfo> {
         let headers = Self::build_headers(res, ctx)?;
+        let client = self.client(ctx)?;
 
-        let head = self.client
+        let head = client
             .head(&res.uri)
             .headers(headers.clone())
-            .timeout(Duration::from_secs(ctx.timeout_secs))
             .send()
             .await?;
+        if let Some(busy) = Self::host_busy(&head) {
+            return Err(busy.into());
+        }
 
         let total = head.headers()
             .get(CONTENT_LENGTH)
@@ -98,18 +174,27 @@ impl TransferDriver for HttpDriver {
 
         let _hint = Self::accept_ranges_hint(&head);
 
-        let test = self.client
+        let test = client
             .get(&res.uri)
             .headers(headers)
-            .timeout(Duration::from_secs(ctx.timeout_secs))
             .header(RANGE, "bytes=0-0")
             .send()
             .await?;
+        if let Some(busy) = Self::host_busy(&test) {
+            return Err(busy.into());
+        }
 
         let supports_ranges = test.status() == StatusCode::PARTIAL_CONTENT
             && test.headers().get(CONTENT_RANGE).is_some();
 
-        Ok((total, supports_ranges))
+        // 部分服务器 HEAD 不带验证器，用测试 GET 的响应补齐
+        Ok(ProbeInfo {
+            total_size: total,
+            supports_ranges,
+            etag: Self::header_string(&head, ETAG).or_else(|| Self::header_string(&test, ETAG)),
+            last_modified: Self::header_string(&head, LAST_MODIFIED)
+                .or_else(|| Self::header_string(&test, LAST_MODIFIED)),
+        })
     }
 
     async fn download_range(
@@ -118,20 +203,26 @@ impl TransferDriver for HttpDriver {
         ctx: &DriverContext,
         start: u64,
         end_inclusive: u64,
-    ) -> anyhow::Result<Bytes> {
-        let headers = Self::build_headers(res, ctx)?;
+    ) -> anyhow::Result<ByteStream> {
+        let mut headers = Self::build_headers(res, ctx)?;
+        let client = self.client(ctx)?;
         let range_value = format!("bytes={}-{}", start, end_inclusive);
 
+        // If-Range：远端变化时服务器返回 200 全量而不是 206，避免拼接新旧数据
+        let if_range = Self::if_range_validator(res);
+        if let Some(v) = &if_range {
+            headers.insert(IF_RANGE, HeaderValue::from_str(v)?);
+        }
+
         let mut last_err: Option<anyhow::Error> = None;
         for attempt in 0..=ctx.retries {
             if attempt > 0 {
                 Self::sleep_backoff(ctx, attempt - 1).await;
             }
 
-            let resp = match self.client
+            let resp = match client
                 .get(&res.uri)
                 .headers(headers.clone())
-                .timeout(Duration::from_secs(ctx.timeout_secs))
                 .header(RANGE, range_value.clone())
                 .send()
                 .await
@@ -143,13 +234,19 @@ impl TransferDriver for HttpDriver {
                 }
             };
 
+            if let Some(busy) = Self::host_busy(&resp) {
+                return Err(busy.into());
+            }
+
             match resp.status() {
-                StatusCode::PARTIAL_CONTENT => return Ok(resp.bytes().await?),
+                StatusCode::PARTIAL_CONTENT => return Ok(Self::body_stream(resp)),
 
-                // ✅ 关键：Range 被忽略 => 200 + 全量
+                // ✅ 关键：Range 被忽略 => 200 + 全量（不读取 body，交给 engine 决定如何回退）
                 StatusCode::OK => {
-                    let full = resp.bytes().await?;
-                    return Err(HttpDriverError::RangeIgnoredFull(full).into());
+                    if if_range.is_some() && !Self::same_entity(&resp, res) {
+                        return Err(HttpDriverError::ValidatorChanged.into());
+                    }
+                    return Err(HttpDriverError::RangeIgnoredFull.into());
                 }
 
                 StatusCode::RANGE_NOT_SATISFIABLE => return Err(HttpDriverError::RangeNotSupported.into()),
@@ -171,8 +268,9 @@ impl TransferDriver for HttpDriver {
         Err(last_err.unwrap_or_else(|| HttpDriverError::Status(StatusCode::REQUEST_TIMEOUT).into()))
     }
 
-    async fn download_all(&self, res: &ResourceDescriptor, ctx: &DriverContext) -> anyhow::Result<Bytes> {
+    async fn download_all(&self, res: &ResourceDescriptor, ctx: &DriverContext) -> anyhow::Result<ByteStream> {
         let headers = Self::build_headers(res, ctx)?;
+        let client = self.client(ctx)?;
 
         let mut last_err: Option<anyhow::Error> = None;
         for attempt in 0..=ctx.retries {
@@ -180,10 +278,9 @@ impl TransferDriver for HttpDriver {
                 Self::sleep_backoff(ctx, attempt - 1).await;
             }
 
-            let resp = match self.client
+            let resp = match client
                 .get(&res.uri)
                 .headers(headers.clone())
-                .timeout(Duration::from_secs(ctx.timeout_secs))
                 .send()
                 .await
             {
@@ -195,7 +292,10 @@ impl TransferDriver for HttpDriver {
             };
 
             if resp.status().is_success() {
-                return Ok(resp.bytes().await?);
+                return Ok(Self::body_stream(resp));
+            }
+            if let Some(busy) = Self::host_busy(&resp) {
+                return Err(busy.into());
             }
 
             if Self::should_retry_status(resp.status()) {
@@ -1,13 +1,55 @@
 use async_trait::async_trait;
 use crate::plugins::registry::{DownloadItemDraft, LinkResolver, ResolveContext, ResolveResult};
-use crate::core::model::{Capabilities, LinkInput, ResourceDescriptor, ResourceType};
+use crate::core::checksum::parse_sidecar;
+use crate::core::model::{Capabilities, Checksum, HashAlgo, LinkInput, ResourceDescriptor, ResourceType};
+use anyhow::Context;
+use reqwest::header::USER_AGENT;
 use sanitize_filename::sanitize;
+use std::time::Duration;
 use url::Url;
 
-pub struct HttpResolver;
+/// 按顺序尝试的旁路校验文件后缀
+const SIDECAR_EXTS: &[(&str, HashAlgo)] = &[
+    ("sha256", HashAlgo::Sha256),
+    ("sha512", HashAlgo::Sha512),
+    ("sha1", HashAlgo::Sha1),
+    ("md5", HashAlgo::Md5),
+];
+
+pub struct HttpResolver {
+    client: reqwest::Client,
+}
 
 impl HttpResolver {
-    pub fn new() -> Self { Self }
+    pub fn new() -> Self {
+        Self { client: reqwest::Client::new() }
+    }
+
+    /// 查找 `<url>.sha256` 等旁路文件（--checksum-sidecar 开启时）；都不存在时返回 None
+    async fn fetch_sidecar(&self, input: &LinkInput, ctx: &ResolveContext, file_name: &str) -> Option<Checksum> {
+        for (ext, algo) in SIDECAR_EXTS {
+            let mut req = self.client
+                .get(format!("{}.{}", input.raw, ext))
+                .header(USER_AGENT, &ctx.user_agent)
+                .timeout(Duration::from_secs(15));
+            for (k, v) in &input.headers {
+                req = req.header(k.as_str(), v.as_str());
+            }
+
+            let resp = match req.send().await {
+                Ok(r) if r.status().is_success() => r,
+                _ => continue,
+            };
+            let text = match resp.text().await {
+                Ok(t) => t,
+                Err(_) => continue,
+            };
+            if let Some(c) = parse_sidecar(&text, file_name).and_then(|hex| Checksum::new(*algo, &hex).ok()) {
+                return Some(c);
+            }
+        }
+        None
+    }
 }
 
 #[async_trait]
@@ -32,6 +74,16 @@ impl LinkResolver for HttpResolver {
 
         let suggested_path = ctx.out_dir.join(filename);
 
+        let mut checksums = vec![];
+        if input.options.get("checksum_sidecar").map(|v| v == "1").unwrap_or(false) {
+            let name = suggested_path
+                .file_name()
+                .with_context(|| format!("no file name in {}", suggested_path.display()))?
+                .to_string_lossy()
+                .to_string();
+            checksums.extend(self.fetch_sidecar(input, ctx, &name).await);
+        }
+
         // 先不做 HEAD 探测（由 driver 在 engine 内做更合适），这里给个基础资源
         let res = ResourceDescriptor {
             rtype: ResourceType::Http,
@@ -47,6 +99,8 @@ impl LinkResolver for HttpResolver {
                 suggested_path,
                 total_size: None,
                 resources: vec![res],
+                checksums,
+                piece_hashes: None,
             }],
             warnings: vec![],
         })
@@ -0,0 +1,33 @@
+use crate::plugins::registry::{CliPlugin, DownloadCliConfig};
+use clap::{Arg, ArgMatches, Command};
+
+pub struct MetalinkCliPlugin;
+
+impl MetalinkCliPlugin {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl CliPlugin for MetalinkCliPlugin {
+    fn name(&self) -> &'static str {
+        "metalink"
+    }
+
+    fn augment_download_command(&self, cmd: Command) -> Command {
+        cmd.arg(
+            Arg::new("metalink_location")
+                .long("metalink-location")
+                .help_heading("Metalink")
+                .help("Prefer mirrors in these locations (comma-separated ISO 3166 codes, e.g. 'de,fr')")
+                .num_args(1),
+        )
+    }
+
+    fn apply_download_matches(&self, matches: &ArgMatches, cfg: &mut DownloadCliConfig) -> anyhow::Result<()> {
+        if let Some(v) = matches.get_one::<String>("metalink_location") {
+            cfg.options.insert("metalink_location".to_string(), v.clone());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,2 @@
+pub mod resolver;
+pub mod cli;
\ No newline at end of file
@@ -0,0 +1,426 @@
+use async_trait::async_trait;
+use crate::plugins::registry::{DownloadItemDraft, LinkResolver, ResolveContext, ResolveResult};
+use crate::core::model::{Capabilities, Checksum, HashAlgo, LinkInput, PieceHashes, ResourceDescriptor, ResourceType};
+use anyhow::Context;
+use reqwest::header::USER_AGENT;
+use sanitize_filename::sanitize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use url::Url;
+
+const METALINK_EXTS: &[&str] = &[".meta4", ".metalink"];
+
+/// RFC 5854：priority 取值 1..=999999，越小越优先；缺省视为最低
+const LOWEST_PRIORITY: u32 = 999_999;
+
+pub struct MetalinkResolver {
+    client: reqwest::Client,
+}
+
+/// `<file>` 元素解析结果
+#[derive(Debug, Default)]
+struct MetalinkFile {
+    name: String,
+    size: Option<u64>,
+    /// (type, hex)，type 为 Metalink 的名字（sha-256 等）
+    hashes: Vec<(String, String)>,
+    /// (type, length, hashes)
+    pieces: Vec<(String, u64, Vec<String>)>,
+    urls: Vec<MetalinkUrl>,
+}
+
+#[derive(Debug)]
+struct MetalinkUrl {
+    uri: String,
+    location: Option<String>,
+    priority: u32,
+}
+
+impl MetalinkResolver {
+    pub fn new() -> Self {
+        Self { client: reqwest::Client::new() }
+    }
+
+    fn has_metalink_ext(path: &str) -> bool {
+        let lower = path.to_ascii_lowercase();
+        METALINK_EXTS.iter().any(|ext| lower.ends_with(ext))
+    }
+
+    async fn load(&self, input: &LinkInput, ctx: &ResolveContext) -> anyhow::Result<String> {
+        if let Ok(u) = Url::parse(&input.raw) {
+            match u.scheme() {
+                "http" | "https" => {
+                    let mut req = self.client
+                        .get(u)
+                        .header(USER_AGENT, &ctx.user_agent)
+                        .timeout(Duration::from_secs(30));
+                    for (k, v) in &input.headers {
+                        req = req.header(k.as_str(), v.as_str());
+                    }
+                    return Ok(req.send().await?.error_for_status()?.text().await?);
+                }
+                "file" => {
+                    let path = u.to_file_path().map_err(|_| anyhow::anyhow!("invalid file url: {}", input.raw))?;
+                    return tokio::fs::read_to_string(&path).await.with_context(|| format!("read {:?}", path));
+                }
+                _ => {}
+            }
+        }
+        tokio::fs::read_to_string(&input.raw).await.with_context(|| format!("read {}", input.raw))
+    }
+
+    /// 同时支持 Metalink 4（RFC 5854，元素直接挂在 `<file>` 下）
+    /// 和 Metalink 3（`<verification>` / `<resources>` 包一层）
+    fn parse(xml: &str) -> anyhow::Result<Vec<MetalinkFile>> {
+        let doc = roxmltree::Document::parse(xml).context("parse metalink xml")?;
+        let root = doc.root_element();
+        if root.tag_name().name() != "metalink" {
+            anyhow::bail!("not a metalink document (root element <{}>)", root.tag_name().name());
+        }
+
+        let text = |n: roxmltree::Node| n.text().unwrap_or_default().trim().to_string();
+        let mut files = vec![];
+
+        for file in root.descendants().filter(|n| n.has_tag_name("file")) {
+            let mut f = MetalinkFile {
+                name: file.attribute("name").context("<file> without name attribute")?.to_string(),
+                ..Default::default()
+            };
+
+            for n in file.descendants().filter(|n| n.is_element()) {
+                let parent = n.parent_element().map(|p| p.tag_name().name()).unwrap_or_default();
+                match n.tag_name().name() {
+                    "size" if parent == "file" => f.size = text(n).parse().ok(),
+                    "hash" if parent != "pieces" => {
+                        if let Some(t) = n.attribute("type") {
+                            f.hashes.push((t.to_string(), text(n)));
+                        }
+                    }
+                    "pieces" => {
+                        let (Some(t), Some(len)) = (n.attribute("type"), n.attribute("length").and_then(|v| v.parse().ok()))
+                        else {
+                            continue;
+                        };
+                        let hashes = n.children().filter(|c| c.has_tag_name("hash")).map(text).collect();
+                        f.pieces.push((t.to_string(), len, hashes));
+                    }
+                    "url" => {
+                        // v3 的 type="bittorrent" 指向 .torrent 文件，不是文件本身
+                        if n.attribute("type") == Some("bittorrent") {
+                            continue;
+                        }
+                        let priority = match (n.attribute("priority"), n.attribute("preference")) {
+                            (Some(p), _) => p.parse().unwrap_or(LOWEST_PRIORITY),
+                            // v3 preference 0..=100，越大越优先
+                            (None, Some(p)) => 101 - p.parse::<u32>().unwrap_or(0).min(100),
+                            (None, None) => LOWEST_PRIORITY,
+                        };
+                        f.urls.push(MetalinkUrl {
+                            uri: text(n),
+                            location: n.attribute("location").map(|s| s.to_ascii_lowercase()),
+                            priority,
+                        });
+                    }
+                    _ => {}
+                }
+            }
+            files.push(f);
+        }
+
+        if files.is_empty() {
+            anyhow::bail!("metalink contains no <file> entries");
+        }
+        Ok(files)
+    }
+
+    /// `<file name>` 可以带相对目录；逐段清理，拒绝绝对路径和 `..`
+    fn target_path(out_dir: &Path, name: &str) -> Option<PathBuf> {
+        if name.starts_with(['/', '\\']) || Path::new(name).is_absolute() {
+            return None;
+        }
+        let mut path = out_dir.to_path_buf();
+        let mut pushed = false;
+        for seg in name.split(['/', '\\']).filter(|s| !s.is_empty() && *s != ".") {
+            if seg == ".." {
+                return None;
+            }
+            path.push(sanitize(seg));
+            pushed = true;
+        }
+        pushed.then_some(path)
+    }
+
+    fn resource(u: &MetalinkUrl, input: &LinkInput) -> Option<ResourceDescriptor> {
+        let url = Url::parse(&u.uri).ok()?;
+        let mut meta = HashMap::new();
+        meta.insert("priority".to_string(), u.priority.to_string());
+        if let Some(loc) = &u.location {
+            meta.insert("location".to_string(), loc.clone());
+        }
+
+        let (rtype, headers, caps) = match url.scheme() {
+            "http" | "https" => (
+                ResourceType::Http,
+                input.headers.clone(),
+                Capabilities { supports_ranges: true, max_parallel: 8 },
+            ),
+            "ftp" => {
+                // 与 ftp resolver 一致：CLI 选项优先，其余由 driver 从 URL 取
+                for key in ["ftp_user", "ftp_pass", "ftp_port"] {
+                    if let Some(v) = input.options.get(key) {
+                        meta.insert(key.to_string(), v.clone());
+                    }
+                }
+                (ResourceType::Ftp, Default::default(), Capabilities { supports_ranges: true, max_parallel: 1 })
+            }
+            _ => return None,
+        };
+
+        Some(ResourceDescriptor { rtype, uri: u.uri.clone(), headers, meta, caps })
+    }
+
+    fn draft(f: MetalinkFile, input: &LinkInput, ctx: &ResolveContext, warnings: &mut Vec<String>) -> Option<DownloadItemDraft> {
+        let Some(suggested_path) = Self::target_path(&ctx.out_dir, &f.name) else {
+            warnings.push(format!("skipping metalink file with unsafe name: {}", f.name));
+            return None;
+        };
+
+        let mut urls = f.urls;
+        urls.sort_by_key(|u| u.priority);
+
+        // --metalink-location 指定的地区优先（同优先级内的稳定排序）
+        if let Some(pref) = input.options.get("metalink_location") {
+            let pref: Vec<String> = pref.split(',').map(|s| s.trim().to_ascii_lowercase()).collect();
+            urls.sort_by_key(|u| !u.location.as_ref().is_some_and(|l| pref.contains(l)));
+        }
+
+        let mut resources = vec![];
+        for u in &urls {
+            match Self::resource(u, input) {
+                Some(r) => resources.push(r),
+                None => warnings.push(format!("{}: unsupported url {}", f.name, u.uri)),
+            }
+        }
+        if resources.is_empty() {
+            warnings.push(format!("skipping metalink file without usable urls: {}", f.name));
+            return None;
+        }
+
+        let mut checksums: Vec<Checksum> = vec![];
+        for (t, hex) in &f.hashes {
+            match HashAlgo::parse(t).map(|a| Checksum::new(a, hex)) {
+                Some(Ok(c)) => checksums.push(c),
+                Some(Err(e)) => warnings.push(format!("{}: {:#}", f.name, e)),
+                None => {}
+            }
+        }
+
+        // 取第一组能识别且数量与文件大小相符的 piece 列表
+        let piece_hashes = f.pieces.into_iter().find_map(|(t, len, hashes)| {
+            let algo = HashAlgo::parse(&t)?;
+            let expected = f.size.map(|s| s.div_ceil(len.max(1)) as usize);
+            if len == 0 || expected.is_some_and(|n| n != hashes.len()) {
+                warnings.push(format!("{}: ignoring {} pieces with inconsistent count", f.name, t));
+                return None;
+            }
+            let hashes = hashes
+                .iter()
+                .map(|h| Checksum::new(algo, h).map(|c| c.hex))
+                .collect::<anyhow::Result<Vec<_>>>()
+                .ok()?;
+            Some(PieceHashes { algo, piece_len: len, hashes })
+        });
+
+        Some(DownloadItemDraft {
+            display_name: f.name,
+            suggested_path,
+            total_size: f.size,
+            resources,
+            checksums,
+            piece_hashes,
+        })
+    }
+}
+
+#[async_trait]
+impl LinkResolver for MetalinkResolver {
+    fn name(&self) -> &'static str { "metalink-resolver" }
+
+    fn can_handle(&self, input: &LinkInput) -> u8 {
+        let path = match Url::parse(&input.raw) {
+            Ok(u) if matches!(u.scheme(), "http" | "https" | "file") => u.path().to_string(),
+            Ok(_) => return 0,
+            // 本地文件路径
+            Err(_) => input.raw.clone(),
+        };
+        if Self::has_metalink_ext(&path) {
+            return 95; // 比 GitHub/HTTP 更优先
+        }
+        0
+    }
+
+    async fn resolve(&self, input: &LinkInput, ctx: &ResolveContext) -> anyhow::Result<ResolveResult> {
+        let xml = self.load(input, ctx).await?;
+        let files = Self::parse(&xml)?;
+
+        let mut warnings = vec![];
+        let drafts: Vec<DownloadItemDraft> = files
+            .into_iter()
+            .filter_map(|f| Self::draft(f, input, ctx, &mut warnings))
+            .collect();
+        if drafts.is_empty() {
+            anyhow::bail!("metalink {} has no downloadable files", input.raw);
+        }
+
+        Ok(ResolveResult { drafts, warnings })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1_A: &str = "da39a3ee5e6b4b0d3255bfef95601890afd80709";
+    const SHA1_B: &str = "a9993e364706816aba3e25717850c26c9cd0d89d";
+
+    const V4: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metalink xmlns="urn:ietf:params:xml:ns:metalink">
+  <file name="dir/a.iso">
+    <size>10</size>
+    <hash type="sha-1">da39a3ee5e6b4b0d3255bfef95601890afd80709</hash>
+    <pieces type="sha-1" length="4">
+      <hash>da39a3ee5e6b4b0d3255bfef95601890afd80709</hash>
+      <hash>a9993e364706816aba3e25717850c26c9cd0d89d</hash>
+      <hash>da39a3ee5e6b4b0d3255bfef95601890afd80709</hash>
+    </pieces>
+    <url location="us" priority="2">https://us.example.com/a.iso</url>
+    <url location="de" priority="1">https://de.example.com/a.iso</url>
+    <url>ftp://ftp.example.com/a.iso</url>
+  </file>
+</metalink>"#;
+
+    const V3: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metalink version="3.0" xmlns="http://www.metalinker.org/">
+  <files>
+    <file name="b.bin">
+      <size>8</size>
+      <verification>
+        <hash type="sha1">a9993e364706816aba3e25717850c26c9cd0d89d</hash>
+        <pieces type="sha1" length="4">
+          <hash piece="0">da39a3ee5e6b4b0d3255bfef95601890afd80709</hash>
+          <hash piece="1">a9993e364706816aba3e25717850c26c9cd0d89d</hash>
+        </pieces>
+      </verification>
+      <resources>
+        <url type="bittorrent" preference="100">https://example.com/b.torrent</url>
+        <url type="http" location="jp" preference="10">https://jp.example.com/b.bin</url>
+        <url type="http" location="fr" preference="90">https://fr.example.com/b.bin</url>
+      </resources>
+    </file>
+  </files>
+</metalink>"#;
+
+    fn ctx() -> ResolveContext {
+        ResolveContext { out_dir: PathBuf::from("/downloads"), user_agent: "test".to_string() }
+    }
+
+    fn input(options: &[(&str, &str)]) -> LinkInput {
+        LinkInput {
+            raw: "list.meta4".to_string(),
+            headers: HashMap::new(),
+            options: options.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        }
+    }
+
+    fn uris(d: &DownloadItemDraft) -> Vec<&str> {
+        d.resources.iter().map(|r| r.uri.as_str()).collect()
+    }
+
+    #[test]
+    fn parses_metalink_v4() {
+        let files = MetalinkResolver::parse(V4).unwrap();
+        assert_eq!(files.len(), 1);
+        let f = &files[0];
+        assert_eq!((f.name.as_str(), f.size), ("dir/a.iso", Some(10)));
+        assert_eq!(f.hashes, vec![("sha-1".to_string(), SHA1_A.to_string())]);
+        assert_eq!(f.pieces.len(), 1);
+        assert_eq!((f.pieces[0].1, f.pieces[0].2.len()), (4, 3));
+
+        let mut warnings = vec![];
+        let d = MetalinkResolver::draft(MetalinkResolver::parse(V4).unwrap().remove(0), &input(&[]), &ctx(), &mut warnings).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(d.suggested_path, PathBuf::from("/downloads/dir/a.iso"));
+        assert_eq!(d.total_size, Some(10));
+        // priority 越小越优先，缺省的排最后
+        assert_eq!(uris(&d), ["https://de.example.com/a.iso", "https://us.example.com/a.iso", "ftp://ftp.example.com/a.iso"]);
+        assert_eq!(d.resources[2].rtype, ResourceType::Ftp);
+        assert_eq!(d.checksums[0].algo, HashAlgo::Sha1);
+        let pieces = d.piece_hashes.unwrap();
+        assert_eq!((pieces.algo, pieces.piece_len), (HashAlgo::Sha1, 4));
+        assert_eq!(pieces.hashes, [SHA1_A, SHA1_B, SHA1_A]);
+    }
+
+    #[test]
+    fn parses_metalink_v3_preference_and_skips_torrents() {
+        let files = MetalinkResolver::parse(V3).unwrap();
+        let f = &files[0];
+        assert_eq!(f.hashes, vec![("sha1".to_string(), SHA1_B.to_string())]);
+        // type="bittorrent" 的 url 不是文件本身
+        assert_eq!(f.urls.len(), 2);
+        // preference 越大越优先，换算成 priority 后越小
+        assert_eq!(f.urls.iter().map(|u| u.priority).collect::<Vec<_>>(), [91, 11]);
+
+        let mut warnings = vec![];
+        let d = MetalinkResolver::draft(MetalinkResolver::parse(V3).unwrap().remove(0), &input(&[]), &ctx(), &mut warnings).unwrap();
+        assert_eq!(uris(&d), ["https://fr.example.com/b.bin", "https://jp.example.com/b.bin"]);
+        assert_eq!(d.resources[0].meta["location"], "fr");
+        assert_eq!(d.piece_hashes.unwrap().hashes, [SHA1_A, SHA1_B]);
+    }
+
+    #[test]
+    fn preferred_locations_come_first() {
+        let mut warnings = vec![];
+        let opts = [("metalink_location", "US, jp")];
+        let d = MetalinkResolver::draft(MetalinkResolver::parse(V4).unwrap().remove(0), &input(&opts), &ctx(), &mut warnings).unwrap();
+        // 指定地区内部及其余 url 仍按 priority 排序
+        assert_eq!(uris(&d), ["https://us.example.com/a.iso", "https://de.example.com/a.iso", "ftp://ftp.example.com/a.iso"]);
+
+        let d = MetalinkResolver::draft(MetalinkResolver::parse(V3).unwrap().remove(0), &input(&opts), &ctx(), &mut warnings).unwrap();
+        assert_eq!(uris(&d), ["https://jp.example.com/b.bin", "https://fr.example.com/b.bin"]);
+    }
+
+    #[test]
+    fn piece_count_must_match_size() {
+        // 10 字节、每块 4 字节应有 3 个 piece
+        let xml = V4.replace("<size>10</size>", "<size>20</size>");
+        let mut warnings = vec![];
+        let d = MetalinkResolver::draft(MetalinkResolver::parse(&xml).unwrap().remove(0), &input(&[]), &ctx(), &mut warnings).unwrap();
+        assert!(d.piece_hashes.is_none());
+        assert!(warnings.iter().any(|w| w.contains("inconsistent count")), "{:?}", warnings);
+    }
+
+    #[test]
+    fn rejects_non_metalink_documents() {
+        assert!(MetalinkResolver::parse("<feed/>").is_err());
+        assert!(MetalinkResolver::parse("<metalink/>").is_err());
+        assert!(MetalinkResolver::parse("<metalink><file><size>1</size></file></metalink>").is_err());
+    }
+
+    #[test]
+    fn target_path_stays_inside_out_dir() {
+        let out = Path::new("/downloads");
+        assert_eq!(MetalinkResolver::target_path(out, "a.iso"), Some(out.join("a.iso")));
+        assert_eq!(MetalinkResolver::target_path(out, "./sub\\b.iso"), Some(out.join("sub").join("b.iso")));
+        assert_eq!(MetalinkResolver::target_path(out, "../a.iso"), None);
+        assert_eq!(MetalinkResolver::target_path(out, "sub/../../a.iso"), None);
+        assert_eq!(MetalinkResolver::target_path(out, "/etc/passwd"), None);
+        assert_eq!(MetalinkResolver::target_path(out, "\\server\\share"), None);
+        assert_eq!(MetalinkResolver::target_path(out, "./"), None);
+
+        let xml = V4.replace("dir/a.iso", "../a.iso");
+        let mut warnings = vec![];
+        assert!(MetalinkResolver::draft(MetalinkResolver::parse(&xml).unwrap().remove(0), &input(&[]), &ctx(), &mut warnings).is_none());
+        assert!(warnings[0].contains("unsafe name"), "{:?}", warnings);
+    }
+}
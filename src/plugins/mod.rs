@@ -5,4 +5,5 @@ pub mod bt;
 pub mod ed2k;
 pub mod ftp;
 pub mod sftp;
-pub mod adb;
\ No newline at end of file
+pub mod adb;
+pub mod metalink;
\ No newline at end of file
@@ -1,9 +1,11 @@
 use async_trait::async_trait;
-use crate::core::model::{LinkInput, ResourceDescriptor, ResourceType};
+use crate::core::model::{Checksum, LinkInput, PieceHashes, ResourceDescriptor, ResourceType};
 use clap::{ArgMatches, Command};
+use futures::stream::BoxStream;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct ResolveContext {
@@ -23,6 +25,9 @@ pub struct DownloadItemDraft {
     pub suggested_path: PathBuf,
     pub total_size: Option<u64>,
     pub resources: Vec<ResourceDescriptor>,
+    /// resolver 已知的期望摘要（可为空）
+    pub checksums: Vec<Checksum>,
+    pub piece_hashes: Option<PieceHashes>,
 }
 
 #[async_trait]
@@ -51,31 +56,74 @@ pub struct DriverContext {
     pub timeout_secs: u64,
     pub retries: u32,
     pub retry_backoff_ms: u64,
+    /// 每个 item / 每个主机的默认限速（bytes/s，0 = 不限）；运行中可通过 Engine 调整
+    pub item_speed_limit: u64,
+    pub host_speed_limit: u64,
+    /// 每个主机（host:port）的最大连接数，跨 item / job 共享（0 = 不限）
+    pub max_conns_per_host: usize,
+    /// 所有 item / job 合计的最大连接数（0 = 不限）
+    pub max_connections: usize,
 }
 
+/// 远端主机要求稍后再试（HTTP 429/503、FTP 421）；engine 据此对整个主机退避，而不是当作分片失败
+#[derive(thiserror::Error, Debug)]
+#[error("host busy (retry after {retry_after:?})")]
+pub struct HostBusy {
+    pub retry_after: Option<Duration>,
+}
+
+/// 外部命令（adb、scp、ed2k 命令）无法启动或以非零状态退出
+#[derive(thiserror::Error, Debug)]
+#[error("{tool} failed: {message}")]
+pub struct ExternalToolFailed {
+    pub tool: String,
+    pub message: String,
+}
+
+/// 资源探测结果
+#[derive(Debug, Clone, Default)]
+pub struct ProbeInfo {
+    pub total_size: Option<u64>,
+    pub supports_ranges: bool,
+    /// 续传校验用的验证器：HTTP 的 ETag / Last-Modified，FTP 的 MDTM
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// 驱动返回的分块数据流：engine 边收边写入 Assembler，不在内存中缓存整个分片
+pub type ByteStream = BoxStream<'static, anyhow::Result<bytes::Bytes>>;
+
 #[async_trait]
 pub trait TransferDriver: Send + Sync {
     fn name(&self) -> &'static str;
     fn supports(&self, res: &ResourceDescriptor) -> bool;
 
+    /// 连接数限制按这个 key 计数；默认取 URL 的 host:port
+    fn host_key(&self, res: &ResourceDescriptor) -> Option<String> {
+        let u = url::Url::parse(&res.uri).ok()?;
+        Some(format!("{}:{}", u.host_str()?.to_ascii_lowercase(), u.port_or_known_default().unwrap_or(0)))
+    }
+
     /// 可选：做 connection pool/认证等初始化；骨架里不强制用
     async fn prepare(&self, _res: &ResourceDescriptor, _ctx: &DriverContext) -> anyhow::Result<()> {
         Ok(())
     }
 
+    /// 打开 [start, end_inclusive] 区间的数据流；重试只覆盖建立连接阶段，流中途出错由 engine 处理
     async fn download_range(
         &self,
         res: &ResourceDescriptor,
         ctx: &DriverContext,
         start: u64,
         end_inclusive: u64,
-    ) -> anyhow::Result<bytes::Bytes>;
+    ) -> anyhow::Result<ByteStream>;
 
-    async fn download_all(&self, res: &ResourceDescriptor, ctx: &DriverContext) -> anyhow::Result<bytes::Bytes>;
+    /// 打开整个资源的数据流（不支持 Range 时使用）
+    async fn download_all(&self, res: &ResourceDescriptor, ctx: &DriverContext) -> anyhow::Result<ByteStream>;
 
-    /// 可选：探测资源（大小、Range 支持）。默认表示“未知/不支持”。
-    async fn probe(&self, _res: &ResourceDescriptor, _ctx: &DriverContext) -> anyhow::Result<(Option<u64>, bool)> {
-        Ok((None, false))
+    /// 可选：探测资源（大小、Range 支持、验证器）。默认表示“未知/不支持”。
+    async fn probe(&self, _res: &ResourceDescriptor, _ctx: &DriverContext) -> anyhow::Result<ProbeInfo> {
+        Ok(ProbeInfo::default())
     }
 
 }
@@ -97,6 +145,7 @@ impl PluginRegistry {
         reg.resolvers.push(Box::new(crate::plugins::ftp::resolver::FtpResolver::new()));
         reg.resolvers.push(Box::new(crate::plugins::sftp::resolver::SftpResolver::new()));
         reg.resolvers.push(Box::new(crate::plugins::adb::resolver::AdbResolver::new()));
+        reg.resolvers.push(Box::new(crate::plugins::metalink::resolver::MetalinkResolver::new()));
 
         reg.drivers.push(Arc::new(crate::plugins::http::driver::HttpDriver::new()));
         reg.drivers.push(Arc::new(crate::plugins::ftp::driver::FtpDriver::new()));
@@ -106,9 +155,16 @@ impl PluginRegistry {
         reg.cli_plugins.push(Box::new(crate::plugins::ftp::cli::FtpCliPlugin::new()));
         reg.cli_plugins.push(Box::new(crate::plugins::sftp::cli::SftpCliPlugin::new()));
         reg.cli_plugins.push(Box::new(crate::plugins::adb::cli::AdbCliPlugin::new()));
+        reg.cli_plugins.push(Box::new(crate::plugins::metalink::cli::MetalinkCliPlugin::new()));
         reg
     }
 
+    /// 只含给定解析器和驱动的注册表（测试用）
+    #[cfg(test)]
+    pub fn for_tests(resolvers: Vec<Box<dyn LinkResolver>>, drivers: Vec<Arc<dyn TransferDriver>>) -> Self {
+        Self { resolvers, drivers, cli_plugins: vec![] }
+    }
+
     pub fn augment_download_command(&self, cmd: Command) -> Command {
         self.cli_plugins
             .iter()
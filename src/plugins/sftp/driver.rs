@@ -1,5 +1,5 @@
 use crate::core::model::ResourceDescriptor;
-use crate::plugins::registry::DriverContext;
+use crate::plugins::registry::{DriverContext, ExternalToolFailed};
 use anyhow::Context;
 use std::path::Path;
 use tokio::process::Command;
@@ -67,10 +67,16 @@ impl SftpDriver {
         cmd.arg(remote);
         cmd.arg(&tmp_path);
 
-        let out = cmd.output().await.context("spawn scp")?;
+        // engine 暂停/取消时会丢弃该 future，确保子进程随之结束
+        cmd.kill_on_drop(true);
+
+        let out = cmd.output().await.map_err(|e| ExternalToolFailed {
+            tool: "scp".to_string(),
+            message: format!("cannot spawn: {}", e),
+        })?;
         if !out.status.success() {
             let stderr = String::from_utf8_lossy(&out.stderr).to_string();
-            anyhow::bail!("scp failed: {}", stderr.trim());
+            return Err(ExternalToolFailed { tool: "scp".to_string(), message: stderr.trim().to_string() }.into());
         }
 
         if tokio::fs::metadata(target_path).await.is_ok() {
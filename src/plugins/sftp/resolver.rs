@@ -57,6 +57,8 @@ impl LinkResolver for SftpResolver {
                 suggested_path,
                 total_size: None,
                 resources: vec![res],
+                checksums: vec![],
+                piece_hashes: None,
             }],
             warnings: vec![
                 "SFTP downloads use system scp. Configure key-based auth; password prompts are not supported.".into(),